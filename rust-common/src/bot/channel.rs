@@ -9,6 +9,7 @@ use teloxide::stop::{mk_stop_token, StopFlag, StopToken};
 use teloxide::update_listeners::{AsUpdateStream, UpdateListener};
 
 use crate::bot::utils::extract_span_from_delivery;
+use crate::health::checks::AmqpHealthCheck;
 use crate::settings::Settings;
 
 pub struct MqUpdateListener {
@@ -147,6 +148,11 @@ impl MqUpdateListener {
         })
     }
 
+    /// Health check watching the channel and consumer of this listener
+    pub fn health_check(&self) -> AmqpHealthCheck {
+        AmqpHealthCheck::new(self.channel.clone(), self.consumer.clone())
+    }
+
     pub async fn stop(&mut self) -> Result<(), lapin::Error> {
         self.channel
             .basic_cancel(&self.consumer_tag, BasicCancelOptions::default())
@@ -0,0 +1,421 @@
+use std::backtrace::BacktraceStatus;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use opentelemetry::trace::TraceContextExt;
+use teloxide::error_handlers::{ErrorHandler, LoggingErrorHandler};
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+use teloxide::utils::html;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::bot::instrument::HandlerTrace;
+use crate::duration::parse_go_duration;
+use crate::settings::{ErrorReport, Settings};
+
+static DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(10 * 60);
+static DEFAULT_RATE_LIMIT: usize = 10;
+static RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+static BACKTRACE_LINES: usize = 12;
+static MAX_ERROR_LENGTH: usize = 1000;
+static MAX_MESSAGE_LENGTH: usize = 4000;
+
+/// Fingerprint of an error message, numbers are masked so ids in the message do not matter
+fn fingerprint(handler: &str, message: &str) -> u64 {
+    let mut normalized = String::with_capacity(message.len());
+    let mut in_number = false;
+    for c in message.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                normalized.push('#');
+            }
+            in_number = true;
+        } else {
+            normalized.push(c);
+            in_number = false;
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    handler.hash(&mut hasher);
+    normalized.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Default)]
+struct ReportState {
+    /// fingerprint -> (last reported at, suppressed since)
+    seen: HashMap<u64, (Instant, usize)>,
+    sent: VecDeque<Instant>,
+    dropped: usize,
+}
+
+/// Decision whether an error should be reported
+#[derive(Debug, PartialEq, Eq)]
+enum Decision {
+    /// Report it, with the count of similar errors suppressed, of repeats of earlier errors
+    /// suppressed until their fingerprint expired, and of errors dropped since
+    Report {
+        suppressed: usize,
+        expired: usize,
+        dropped: usize,
+    },
+    Skip,
+}
+
+impl ReportState {
+    fn decide(
+        &mut self,
+        fingerprint: u64,
+        now: Instant,
+        dedup_window: Duration,
+        rate_limit: usize,
+    ) -> Decision {
+        if let Some((reported_at, suppressed)) = self.seen.get_mut(&fingerprint) {
+            if now.duration_since(*reported_at) < dedup_window {
+                *suppressed += 1;
+                return Decision::Skip;
+            }
+        }
+
+        while let Some(sent_at) = self.sent.front() {
+            if now.duration_since(*sent_at) < RATE_LIMIT_WINDOW {
+                break;
+            }
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= rate_limit {
+            self.dropped += 1;
+            return Decision::Skip;
+        }
+
+        let suppressed = self
+            .seen
+            .remove(&fingerprint)
+            .map(|(_, suppressed)| suppressed)
+            .unwrap_or_default();
+        // repeats of errors not seen again since are reported along, rather than lost
+        let mut expired = 0;
+        self.seen.retain(|_, (reported_at, suppressed)| {
+            let kept = now.duration_since(*reported_at) < dedup_window;
+            if !kept {
+                expired += *suppressed;
+            }
+            kept
+        });
+        self.seen.insert(fingerprint, (now, 0));
+        self.sent.push_back(now);
+
+        Decision::Report {
+            suppressed,
+            expired,
+            dropped: std::mem::take(&mut self.dropped),
+        }
+    }
+}
+
+///
+/// Error handler of the dispatcher, which logs errors and reports them to an admin chat
+///
+/// Errors are deduplicated by fingerprint within the dedup window, and no more than the
+/// rate limit of reports are sent per minute.
+///
+pub struct ReportingErrorHandler {
+    bot: Bot,
+    service_name: String,
+    chat_id: ChatId,
+    dedup_window: Duration,
+    rate_limit: usize,
+    state: Mutex<ReportState>,
+}
+
+impl ReportingErrorHandler {
+    pub fn new(bot: Bot, service_name: &str, settings: &ErrorReport) -> Arc<Self> {
+        Arc::new(Self {
+            bot,
+            service_name: service_name.to_string(),
+            chat_id: ChatId(settings.chat_id),
+            dedup_window: settings
+                .dedup_window
+                .as_ref()
+                .and_then(|window| parse_go_duration(window).ok())
+                .unwrap_or(DEFAULT_DEDUP_WINDOW),
+            rate_limit: settings.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT),
+            state: Mutex::new(ReportState::default()),
+        })
+    }
+
+    fn report_message(
+        &self,
+        error: &anyhow::Error,
+        trace: Option<&HandlerTrace>,
+        suppressed: usize,
+        expired: usize,
+        dropped: usize,
+    ) -> String {
+        let mut lines = vec![format!(
+            "<b>{}</b>: {}",
+            html::escape(&self.service_name),
+            html::escape(&match trace {
+                Some(trace) => format!("handler {} failed", trace.handler),
+                None => "error".to_string(),
+            })
+        )];
+
+        let root_cause = error.root_cause().to_string();
+        lines.push(format!(
+            "Error: {}",
+            html::escape(
+                &root_cause
+                    .chars()
+                    .take(MAX_ERROR_LENGTH)
+                    .collect::<String>()
+            )
+        ));
+        if let Some(trace) = trace {
+            lines.push(format!(
+                "Trace ID: {}",
+                html::code_inline(&trace.span_context.trace_id().to_string())
+            ));
+        }
+        if suppressed > 0 {
+            lines.push(format!("Similar errors suppressed: {}", suppressed));
+        }
+        if expired > 0 {
+            lines.push(format!("Repeats of earlier errors suppressed: {}", expired));
+        }
+        if dropped > 0 {
+            lines.push(format!("Reports dropped by rate limit: {}", dropped));
+        }
+
+        let mut message = lines.join("\n");
+
+        let backtrace = error.backtrace();
+        if backtrace.status() == BacktraceStatus::Captured {
+            let backtrace = html::code_block(
+                &backtrace
+                    .to_string()
+                    .lines()
+                    .take(BACKTRACE_LINES)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+
+            // drop the backtrace rather than cutting html tags in half
+            if message.len() + backtrace.len() < MAX_MESSAGE_LENGTH {
+                message = format!("{}\n\n{}", message, backtrace);
+            }
+        }
+
+        message
+    }
+}
+
+impl ErrorHandler<anyhow::Error> for ReportingErrorHandler {
+    fn handle_error(self: Arc<Self>, error: anyhow::Error) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let trace = error.downcast_ref::<HandlerTrace>().cloned();
+
+            log::error!("Error from the dispatcher: {:?}", error);
+            let span = tracing::error_span!("bot_error_handler");
+            match &trace {
+                // recorded to the span of the handler already
+                Some(trace) => span.set_parent(
+                    opentelemetry::Context::new()
+                        .with_remote_span_context(trace.span_context.clone()),
+                ),
+                None => tracing::error!(parent: &span, error = ?error, "Error from the dispatcher"),
+            }
+
+            let handler = trace
+                .as_ref()
+                .map(|trace| trace.handler)
+                .unwrap_or_default();
+            let decision = self.state.lock().unwrap().decide(
+                fingerprint(handler, &error.root_cause().to_string()),
+                Instant::now(),
+                self.dedup_window,
+                self.rate_limit,
+            );
+
+            if let Decision::Report {
+                suppressed,
+                expired,
+                dropped,
+            } = decision
+            {
+                let message =
+                    self.report_message(&error, trace.as_ref(), suppressed, expired, dropped);
+                if let Err(err) = self
+                    .bot
+                    .send_message(self.chat_id, message)
+                    .parse_mode(ParseMode::Html)
+                    .disable_web_page_preview(true)
+                    .await
+                {
+                    tracing::error!(parent: &span, error = %err, "Failed to report error");
+                    log::error!("Failed to report error to chat {}: {}", self.chat_id, err);
+                }
+            }
+        })
+    }
+}
+
+///
+/// Create the error handler of the dispatcher
+///
+/// # Arguments
+///
+/// * `bot`: bot used to send the reports
+/// * `service_name`: service name shown in the reports
+/// * `settings`: application settings, reports are only sent if `telegram_bot.error_report` is set
+///
+/// returns: `Arc<dyn ErrorHandler>` the error handler
+///
+pub fn new_error_handler(
+    bot: Bot,
+    service_name: &str,
+    settings: &Settings,
+) -> Arc<dyn ErrorHandler<anyhow::Error> + Send + Sync> {
+    match settings
+        .telegram_bot
+        .as_ref()
+        .and_then(|telegram_bot| telegram_bot.error_report.as_ref())
+    {
+        Some(error_report) => ReportingErrorHandler::new(bot, service_name, error_report),
+        None => LoggingErrorHandler::with_custom_text("An error from the handler"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint("handler", "Message 123 not found in chat -100456"),
+            fingerprint("handler", "Message 7 not found in chat -100789")
+        );
+        assert_ne!(
+            fingerprint("handler", "Message 123 not found"),
+            fingerprint("other_handler", "Message 123 not found")
+        );
+        assert_ne!(
+            fingerprint("handler", "Message not found"),
+            fingerprint("handler", "Bot not found")
+        );
+    }
+
+    #[test]
+    fn test_report_state_dedup() {
+        let mut state = ReportState::default();
+        let window = Duration::from_secs(60);
+        let now = Instant::now();
+
+        assert_eq!(
+            state.decide(1, now, window, 10),
+            Decision::Report {
+                suppressed: 0,
+                expired: 0,
+                dropped: 0
+            }
+        );
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(1), window, 10),
+            Decision::Skip
+        );
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(2), window, 10),
+            Decision::Skip
+        );
+        assert_eq!(
+            state.decide(2, now + Duration::from_secs(3), window, 10),
+            Decision::Report {
+                suppressed: 0,
+                expired: 0,
+                dropped: 0
+            }
+        );
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(59), window, 10),
+            Decision::Skip
+        );
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(61), window, 10),
+            Decision::Report {
+                suppressed: 3,
+                expired: 0,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_report_state_expired() {
+        let mut state = ReportState::default();
+        let window = Duration::from_secs(60);
+        let now = Instant::now();
+
+        assert!(matches!(
+            state.decide(1, now, window, 10),
+            Decision::Report { .. }
+        ));
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(1), window, 10),
+            Decision::Skip
+        );
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(2), window, 10),
+            Decision::Skip
+        );
+
+        // error 1 is not seen again, its repeats go along with the next report
+        assert_eq!(
+            state.decide(2, now + Duration::from_secs(61), window, 10),
+            Decision::Report {
+                suppressed: 0,
+                expired: 2,
+                dropped: 0
+            }
+        );
+        assert_eq!(
+            state.decide(1, now + Duration::from_secs(62), window, 10),
+            Decision::Report {
+                suppressed: 0,
+                expired: 0,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_report_state_rate_limit() {
+        let mut state = ReportState::default();
+        let window = Duration::from_secs(600);
+        let now = Instant::now();
+
+        for fingerprint in 0..3 {
+            assert!(matches!(
+                state.decide(fingerprint, now, window, 3),
+                Decision::Report { .. }
+            ));
+        }
+        assert_eq!(state.decide(3, now, window, 3), Decision::Skip);
+        assert_eq!(state.decide(4, now, window, 3), Decision::Skip);
+        assert_eq!(
+            state.decide(5, now + RATE_LIMIT_WINDOW, window, 3),
+            Decision::Report {
+                suppressed: 0,
+                expired: 0,
+                dropped: 2
+            }
+        );
+    }
+}
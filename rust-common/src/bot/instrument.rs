@@ -0,0 +1,184 @@
+use std::fmt::{Display, Formatter};
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Instant;
+
+use opentelemetry::metrics::Histogram;
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry::{global, KeyValue};
+use teloxide::dispatching::dialogue::GetChatId;
+use teloxide::dispatching::DpHandlerDescription;
+use teloxide::dptree::di::{DependencyMap, DependencySupplier};
+use teloxide::dptree::{self, Cont, Handler, HandlerDescription};
+use teloxide::types::{Update, UpdateKind};
+use tracing::field::Empty;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+static METER_NAME: &str = "pegasus/rust-common/bot";
+
+fn handler_duration() -> Histogram<f64> {
+    global::meter(METER_NAME)
+        .f64_histogram("bot.handler.duration")
+        .with_description("Duration of bot update handlers")
+        .with_unit(opentelemetry::metrics::Unit::new("s"))
+        .init()
+}
+
+/// Trace of the handler an error was returned from, attached to the error by [`instrument`]
+#[derive(Clone, Debug)]
+pub struct HandlerTrace {
+    pub handler: &'static str,
+    pub span_context: SpanContext,
+}
+
+impl Display for HandlerTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Handler {} failed, trace id: {}",
+            self.handler,
+            self.span_context.trace_id()
+        )
+    }
+}
+
+/// Errors able to carry the [`HandlerTrace`] they were returned from
+pub trait TraceableError: Display + Sized {
+    fn with_trace(self, trace: HandlerTrace) -> Self;
+}
+
+impl TraceableError for anyhow::Error {
+    fn with_trace(self, trace: HandlerTrace) -> Self {
+        self.context(trace)
+    }
+}
+
+/// Command of the update, the first word of a message starting with `/` without the bot username
+fn update_command(update: &Update) -> Option<String> {
+    match &update.kind {
+        UpdateKind::Message(message) => message
+            .text()
+            .filter(|text| text.starts_with('/'))
+            .and_then(|text| text.split_whitespace().next())
+            .map(|command| command.split('@').next().unwrap_or(command).to_string()),
+        _ => None,
+    }
+}
+
+///
+/// Instrument the rest of the handler chain, usually put right before `endpoint`
+///
+/// The span is parented on `update.cx`, so handlers can pick it up with
+/// `tracing::Span::current()`. Errors returned by the endpoint are recorded to the span
+/// and get a [`HandlerTrace`] attached, the duration is recorded to the
+/// `bot.handler.duration` histogram.
+///
+/// # Arguments
+///
+/// * `name`: span name, usually the endpoint function name
+///
+/// returns: `Handler` a middleware to chain with
+///
+/// # Examples
+///
+/// ```ignore
+/// dptree::case![BotCommand::Ping(target)]
+///     .chain(instrument("ping_handler"))
+///     .endpoint(ping_handler)
+/// ```
+pub fn instrument<T, E>(
+    name: &'static str,
+) -> Handler<'static, DependencyMap, Result<T, E>, DpHandlerDescription>
+where
+    T: Send + Sync + 'static,
+    E: TraceableError + Send + Sync + 'static,
+{
+    let duration = handler_duration();
+
+    dptree::from_fn_with_description(
+        DpHandlerDescription::entry(),
+        move |deps: DependencyMap, cont: Cont<'static, DependencyMap, Result<T, E>>| {
+            let duration = duration.clone();
+
+            async move {
+                let update: Arc<Update> = deps.get();
+                let user_id = update.user().map(|user| user.id.0);
+                let chat_id = update.chat_id().map(|chat_id| chat_id.0);
+
+                let span = tracing::info_span!(
+                    "bot_handler",
+                    otel.name = name,
+                    otel.status_code = Empty,
+                    update_id = update.id,
+                    chat_id = chat_id,
+                    user_id = user_id,
+                    command = update_command(&update),
+                );
+                span.set_parent(update.cx.clone().unwrap_or_default());
+
+                let start = Instant::now();
+                let result = cont(deps).instrument(span.clone()).await;
+
+                let (status, result) = match result {
+                    ControlFlow::Break(Err(err)) => {
+                        span.record("otel.status_code", "ERROR");
+                        tracing::error!(parent: &span, error = %err, "Handler {} failed", name);
+
+                        let trace = HandlerTrace {
+                            handler: name,
+                            span_context: span.context().span().span_context().clone(),
+                        };
+                        ("error", ControlFlow::Break(Err(err.with_trace(trace))))
+                    }
+                    ControlFlow::Break(Ok(output)) => ("ok", ControlFlow::Break(Ok(output))),
+                    ControlFlow::Continue(deps) => return ControlFlow::Continue(deps),
+                };
+
+                duration.record(
+                    start.elapsed().as_secs_f64(),
+                    &[
+                        KeyValue::new("handler", name),
+                        KeyValue::new("status", status),
+                    ],
+                );
+
+                result
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn message_update(text: &str) -> Update {
+        let update = serde_json::json!({
+            "update_id": 1,
+            "message": {
+                "message_id": 1,
+                "date": 0,
+                "chat": {"id": 1, "type": "private", "first_name": "Alice"},
+                "from": {"id": 1, "is_bot": false, "first_name": "Alice"},
+                "text": text,
+            },
+        });
+
+        serde_json::from_str(&update.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_update_command() {
+        assert_eq!(
+            update_command(&message_update("/ping example.com")),
+            Some("/ping".to_string())
+        );
+        assert_eq!(
+            update_command(&message_update("/qrcode@pegasus_bot hello")),
+            Some("/qrcode".to_string())
+        );
+        assert_eq!(update_command(&message_update("hello")), None);
+    }
+}
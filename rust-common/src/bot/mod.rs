@@ -3,6 +3,8 @@ use teloxide::Bot;
 use crate::settings::TelegramBot;
 
 pub mod channel;
+pub mod error;
+pub mod instrument;
 pub mod state;
 mod utils;
 
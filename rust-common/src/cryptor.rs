@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use subtle::ConstantTimeEq;
+
+use crate::settings::Encryption;
+
+/// Prefix of values encrypted by [`Cryptor`]
+static PREFIX: &str = "enc:v2:";
+static KEY_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptorError {
+    #[error("Invalid encryption key {0}: {1}")]
+    InvalidKey(String, String),
+    #[error("Primary encryption key {0} is not configured")]
+    MissingPrimaryKey(String),
+    #[error("Unknown encryption key {0}")]
+    UnknownKey(String),
+    #[error("Malformed encrypted value")]
+    Malformed,
+    #[error("Failed to encrypt value")]
+    Encrypt,
+    #[error("Failed to decrypt value, the key or the value is wrong")]
+    Decrypt,
+}
+
+///
+/// AES-256-GCM envelope encryption of values stored in the database
+///
+/// Every value is encrypted with its own random data key, which is encrypted with the primary
+/// key encryption key. Rotating keys only needs the data keys to be wrapped again, and values
+/// name the key they were wrapped with, so old keys keep decrypting until they are removed.
+///
+/// Encrypted values look like `enc:v2:<key id>:<wrapped data key>:<ciphertext>`. The ciphertext
+/// is bound to a context naming where the value is stored, such as its column, so a value copied
+/// into another column does not decrypt. Values without the prefix were stored before encryption
+/// was enabled and are returned as is.
+///
+#[derive(Clone)]
+pub struct Cryptor {
+    primary_key_id: Option<String>,
+    keys: HashMap<String, LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl Debug for Cryptor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cryptor")
+            .field("primary_key_id", &self.primary_key_id)
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn new_key(key: &[u8]) -> Option<LessSafeKey> {
+    UnboundKey::new(&AES_256_GCM, key)
+        .ok()
+        .map(LessSafeKey::new)
+}
+
+impl Cryptor {
+    ///
+    /// Create the cryptor from the encryption settings
+    ///
+    /// # Arguments
+    ///
+    /// * `settings`: encryption settings, `None` stores values in plaintext
+    ///
+    /// returns: `Result<Cryptor, CryptorError>` error if a key is not 32 bytes of base64,
+    /// or the primary key is missing
+    ///
+    pub fn new(settings: Option<&Encryption>) -> Result<Self, CryptorError> {
+        let settings = match settings {
+            Some(settings) => settings,
+            None => {
+                log::warn!("Encryption is not configured, secrets are stored in plaintext");
+                return Ok(Self {
+                    primary_key_id: None,
+                    keys: HashMap::new(),
+                    rng: SystemRandom::new(),
+                });
+            }
+        };
+
+        let mut keys = HashMap::new();
+        for key in &settings.keys {
+            if key.id.is_empty() || key.id.contains(':') {
+                return Err(CryptorError::InvalidKey(
+                    key.id.clone(),
+                    "id must be non-empty without ':'".to_string(),
+                ));
+            }
+
+            let bytes = STANDARD
+                .decode(&key.key)
+                .map_err(|err| CryptorError::InvalidKey(key.id.clone(), err.to_string()))?;
+            if bytes.len() != KEY_LEN {
+                return Err(CryptorError::InvalidKey(
+                    key.id.clone(),
+                    format!("expected {} bytes, got {}", KEY_LEN, bytes.len()),
+                ));
+            }
+
+            keys.insert(
+                key.id.clone(),
+                new_key(&bytes).ok_or_else(|| {
+                    CryptorError::InvalidKey(key.id.clone(), "rejected".to_string())
+                })?,
+            );
+        }
+
+        if !keys.contains_key(&settings.primary_key_id) {
+            return Err(CryptorError::MissingPrimaryKey(
+                settings.primary_key_id.clone(),
+            ));
+        }
+
+        Ok(Self {
+            primary_key_id: Some(settings.primary_key_id.clone()),
+            keys,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    fn seal(
+        &self,
+        key: &LessSafeKey,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<String, CryptorError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| CryptorError::Encrypt)?;
+
+        let mut in_out = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut in_out,
+        )
+        .map_err(|_| CryptorError::Encrypt)?;
+
+        Ok(URL_SAFE_NO_PAD.encode([nonce.as_slice(), &in_out].concat()))
+    }
+
+    fn open(&self, key: &LessSafeKey, aad: &[u8], sealed: &str) -> Result<Vec<u8>, CryptorError> {
+        let sealed = URL_SAFE_NO_PAD
+            .decode(sealed)
+            .map_err(|_| CryptorError::Malformed)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(CryptorError::Malformed);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| CryptorError::Malformed)?;
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = key
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| CryptorError::Decrypt)?;
+
+        Ok(plaintext.to_vec())
+    }
+
+    ///
+    /// Encrypt the value with a new data key wrapped by the primary key
+    ///
+    /// # Arguments
+    ///
+    /// * `context`: where the value is stored, such as `table.column`, decrypting needs the same
+    /// * `plaintext`: value to encrypt
+    ///
+    /// returns: `Result<String, CryptorError>` encrypted value, the plaintext if encryption is off
+    ///
+    pub fn encrypt(&self, context: &str, plaintext: &str) -> Result<String, CryptorError> {
+        let key_id = match &self.primary_key_id {
+            Some(key_id) => key_id,
+            None => return Ok(plaintext.to_string()),
+        };
+        let key = &self.keys[key_id];
+
+        let mut data_key = [0u8; KEY_LEN];
+        self.rng
+            .fill(&mut data_key)
+            .map_err(|_| CryptorError::Encrypt)?;
+
+        let wrapped_key = self.seal(key, key_id.as_bytes(), &data_key)?;
+        let ciphertext = self.seal(
+            &new_key(&data_key).ok_or(CryptorError::Encrypt)?,
+            context.as_bytes(),
+            plaintext.as_bytes(),
+        )?;
+
+        Ok(format!(
+            "{}{}:{}:{}",
+            PREFIX, key_id, wrapped_key, ciphertext
+        ))
+    }
+
+    ///
+    /// Decrypt the value with the key it names, plaintext values are returned as is
+    ///
+    /// # Arguments
+    ///
+    /// * `context`: where the value is stored, as it was encrypted with
+    /// * `value`: stored value
+    ///
+    /// returns: `Result<String, CryptorError>` error if the value was encrypted for another context
+    ///
+    pub fn decrypt(&self, context: &str, value: &str) -> Result<String, CryptorError> {
+        let value = match value.strip_prefix(PREFIX) {
+            Some(value) => value,
+            None => return Ok(value.to_string()),
+        };
+
+        let mut parts = value.splitn(3, ':');
+        let (key_id, wrapped_key, ciphertext) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(key_id), Some(wrapped_key), Some(ciphertext)) => {
+                (key_id, wrapped_key, ciphertext)
+            }
+            _ => return Err(CryptorError::Malformed),
+        };
+
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| CryptorError::UnknownKey(key_id.to_string()))?;
+        let data_key = self.open(key, key_id.as_bytes(), wrapped_key)?;
+        let plaintext = self.open(
+            &new_key(&data_key).ok_or(CryptorError::Malformed)?,
+            context.as_bytes(),
+            ciphertext,
+        )?;
+
+        String::from_utf8(plaintext).map_err(|_| CryptorError::Malformed)
+    }
+
+    /// Whether the value has to be encrypted again to be under the primary key
+    pub fn needs_rotation(&self, value: &str) -> bool {
+        match &self.primary_key_id {
+            Some(key_id) => !value.starts_with(&format!("{}{}:", PREFIX, key_id)),
+            None => false,
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of the value, to look up secrets without storing or querying them
+pub fn secret_hash(value: &str) -> String {
+    digest(&SHA256, value.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Compare secrets in time independent of where they differ, so they can not be guessed byte by byte
+pub fn secret_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::EncryptionKey;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn encryption(primary_key_id: &str, ids: &[&str]) -> Encryption {
+        Encryption {
+            primary_key_id: primary_key_id.to_string(),
+            keys: ids
+                .iter()
+                .map(|id| EncryptionKey {
+                    id: id.to_string(),
+                    // the same id always gets the same key
+                    key: STANDARD.encode(digest(&SHA256, id.as_bytes())),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let cryptor = Cryptor::new(Some(&encryption("k1", &["k1"]))).unwrap();
+
+        let encrypted = cryptor.encrypt("bots.token", "123456:bot-token").unwrap();
+        assert!(encrypted.starts_with("enc:v2:k1:"));
+        assert!(!encrypted.contains("bot-token"));
+        assert_ne!(
+            encrypted,
+            cryptor.encrypt("bots.token", "123456:bot-token").unwrap()
+        );
+        assert_eq!(
+            cryptor.decrypt("bots.token", &encrypted).unwrap(),
+            "123456:bot-token"
+        );
+        // copied into another column
+        assert!(matches!(
+            cryptor.decrypt("bots.secret", &encrypted),
+            Err(CryptorError::Decrypt)
+        ));
+
+        // values stored before encryption was enabled
+        assert_eq!(cryptor.decrypt("bots.token", "plain").unwrap(), "plain");
+        assert!(cryptor.needs_rotation("plain"));
+        assert!(!cryptor.needs_rotation(&encrypted));
+
+        let mut tampered = encrypted.clone();
+        tampered.pop();
+        tampered.push(if encrypted.ends_with('A') { 'B' } else { 'A' });
+        assert!(cryptor.decrypt("bots.token", &tampered).is_err());
+        assert!(cryptor.decrypt("bots.token", "enc:v2:k1:broken").is_err());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = Cryptor::new(Some(&encryption("k1", &["k1"]))).unwrap();
+        let encrypted = old.encrypt("bots.secret", "secret").unwrap();
+
+        let rotated = Cryptor::new(Some(&encryption("k2", &["k1", "k2"]))).unwrap();
+        assert_eq!(
+            rotated.decrypt("bots.secret", &encrypted).unwrap(),
+            "secret"
+        );
+        assert!(rotated.needs_rotation(&encrypted));
+
+        let reencrypted = rotated.encrypt("bots.secret", "secret").unwrap();
+        assert!(reencrypted.starts_with("enc:v2:k2:"));
+
+        let retired = Cryptor::new(Some(&encryption("k2", &["k2"]))).unwrap();
+        assert!(matches!(
+            retired.decrypt("bots.secret", &encrypted),
+            Err(CryptorError::UnknownKey(_))
+        ));
+        assert_eq!(
+            retired.decrypt("bots.secret", &reencrypted).unwrap(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        assert!(Cryptor::new(Some(&encryption("k2", &["k1"]))).is_err());
+        assert!(Cryptor::new(Some(&encryption("k:1", &["k:1"]))).is_err());
+
+        let mut settings = encryption("k1", &["k1"]);
+        settings.keys[0].key = STANDARD.encode([0u8; 16]);
+        assert!(Cryptor::new(Some(&settings)).is_err());
+
+        let disabled = Cryptor::new(None).unwrap();
+        assert_eq!(disabled.encrypt("bots.secret", "secret").unwrap(), "secret");
+        assert!(!disabled.needs_rotation("secret"));
+    }
+
+    #[test]
+    fn test_secret_hash() {
+        assert_eq!(
+            secret_hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_secret_eq() {
+        assert!(secret_eq("secret", "secret"));
+        assert!(!secret_eq("secret", "secreT"));
+        assert!(!secret_eq("secret", "secret2"));
+        assert!(!secret_eq("", "secret"));
+        assert!(secret_eq("", ""));
+    }
+}
@@ -1,2 +1,5 @@
+pub mod pm_forwarding_block;
 pub mod pm_forwarding_bot;
 pub mod pm_forwarding_message;
+pub mod pm_forwarding_reply;
+pub mod pm_forwarding_topic;
@@ -0,0 +1,68 @@
+use sea_orm::entity::prelude::*;
+
+/// Sender whose messages a bot no longer forwards, banned or muted from the target chat
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "pm_forwarding_blocks")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    #[sea_orm(created_at, default_expr = "Expr::current_timestamp()")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[sea_orm(updated_at, default_expr = "Expr::current_timestamp()")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+
+    pub bot_id: i64,
+    /// the private chat of the sender
+    pub telegram_chat_id: i64,
+    /// end of a mute, `None` for a ban
+    pub blocked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::pm_forwarding_bot::Entity",
+        from = "Column::BotId"
+        to = "super::pm_forwarding_bot::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Bot,
+}
+
+impl Related<super::pm_forwarding_bot::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Bot.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    /// Whether the block is in force at the given time, mutes expire
+    pub fn is_active(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        self.blocked_until.is_none_or(|until| until > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_is_active() {
+        let now = chrono::Utc::now();
+        let block = |blocked_until| Model {
+            id: 1,
+            created_at: now,
+            updated_at: now,
+            bot_id: 1,
+            telegram_chat_id: 42,
+            blocked_until,
+        };
+
+        assert!(block(None).is_active(now));
+        assert!(block(Some(now + chrono::Duration::hours(1))).is_active(now));
+        assert!(!block(Some(now)).is_active(now));
+    }
+}
@@ -1,6 +1,8 @@
+use std::fmt::{Debug, Formatter};
+
 use sea_orm::entity::prelude::*;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "pm_forwarding_bots")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -10,18 +12,101 @@ pub struct Model {
     #[sea_orm(updated_at, default_expr = "Expr::current_timestamp()")]
     pub updated_at: chrono::DateTime<chrono::Utc>,
 
+    /// encrypted, see [`crate::cryptor::Cryptor`]
     #[sea_orm(column_name = "bot_token", unique)]
     pub bot_token: String,
+    /// encrypted, see [`crate::cryptor::Cryptor`]
     pub bot_webhook_secret: String,
+    /// sha256 of the bot token, to look bots up by token
+    #[sea_orm(unique)]
+    pub bot_token_hash: Option<String>,
     pub target_chat_id: i64,
-    #[sea_orm(unsigned)]
     pub telegram_user_refer: i64,
+    /// days to keep message mappings, `None` uses the configured default and `0` keeps forever
+    pub message_retention_days: Option<i32>,
+    /// whether each sender gets a forum topic in the target chat, which must be a forum supergroup
+    pub topics_enabled: bool,
+    /// sent to blocked senders instead of forwarding their messages, `None` drops them silently
+    pub block_notice: Option<String>,
+    /// messages a sender may send per minute, `0` turns rate limiting off
+    pub rate_limit: i32,
+    /// sent to senders on `/start`
+    pub welcome_text: Option<String>,
+    /// sent for each forwarded message, `None` sends the default text and an empty one nothing
+    pub ack_text: Option<String>,
+    /// sent for each forwarded message instead of the acknowledgement while set
+    pub away_text: Option<String>,
+    /// sent when a message can not be forwarded, `None` sends the default text and an empty one
+    /// nothing, the error itself is only logged
+    pub error_text: Option<String>,
+    /// challenge first-time senders solve before their messages are forwarded, `math` or
+    /// `emoji`, `None` forwards every sender
+    pub captcha: Option<String>,
+    /// seconds to solve a captcha, also how long a sender out of retries waits for a new one
+    pub captcha_timeout: i32,
+    /// new challenges a sender gets after wrong answers to a captcha
+    pub captcha_retries: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::pm_forwarding_message::Entity")]
     Messages,
+    #[sea_orm(has_many = "super::pm_forwarding_reply::Entity")]
+    Replies,
+    #[sea_orm(has_many = "super::pm_forwarding_topic::Entity")]
+    Topics,
+    #[sea_orm(has_many = "super::pm_forwarding_block::Entity")]
+    Blocks,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
+
+/// Secrets are redacted, models end up in logs and traces
+impl Debug for Model {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Model")
+            .field("id", &self.id)
+            .field("created_at", &self.created_at)
+            .field("updated_at", &self.updated_at)
+            .field("bot_token", &"<redacted>")
+            .field("bot_webhook_secret", &"<redacted>")
+            .field("bot_token_hash", &self.bot_token_hash)
+            .field("target_chat_id", &self.target_chat_id)
+            .field("telegram_user_refer", &self.telegram_user_refer)
+            .field("message_retention_days", &self.message_retention_days)
+            .field("topics_enabled", &self.topics_enabled)
+            .field("block_notice", &self.block_notice)
+            .field("rate_limit", &self.rate_limit)
+            .field("welcome_text", &self.welcome_text)
+            .field("ack_text", &self.ack_text)
+            .field("away_text", &self.away_text)
+            .field("error_text", &self.error_text)
+            .field("captcha", &self.captcha)
+            .field("captcha_timeout", &self.captcha_timeout)
+            .field("captcha_retries", &self.captcha_retries)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::repositories::memory::bot_model;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let model = Model {
+            bot_token: "123456:bot-token".to_string(),
+            bot_webhook_secret: "webhook-secret".to_string(),
+            ..bot_model(1)
+        };
+
+        let debug = format!("{:?}", model);
+        assert!(!debug.contains("bot-token"));
+        assert!(!debug.contains("webhook-secret"));
+        assert!(debug.contains("target_chat_id: -100"));
+    }
+}
@@ -11,6 +11,8 @@ pub struct Model {
     pub updated_at: chrono::DateTime<chrono::Utc>,
 
     pub bot_id: i64,
+    /// chat the message was forwarded to, message ids are per chat
+    pub target_chat_id: i64,
 
     pub telegram_chat_id: i64,
     pub telegram_message_id: i32,
@@ -22,7 +24,8 @@ pub enum Relation {
     #[sea_orm(
         belongs_to = "super::pm_forwarding_bot::Entity",
         from = "Column::BotId"
-        to = "super::pm_forwarding_bot::Column::Id"
+        to = "super::pm_forwarding_bot::Column::Id",
+        on_delete = "Cascade"
     )]
     Bot,
 }
@@ -0,0 +1,40 @@
+use sea_orm::entity::prelude::*;
+
+/// Forum topic of a sender in the target supergroup of a bot in topics mode
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "pm_forwarding_topics")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    #[sea_orm(created_at, default_expr = "Expr::current_timestamp()")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[sea_orm(updated_at, default_expr = "Expr::current_timestamp()")]
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+
+    pub bot_id: i64,
+
+    /// the topic in the target chat
+    pub target_chat_id: i64,
+    pub message_thread_id: i32,
+    /// the private chat of the sender
+    pub telegram_chat_id: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::pm_forwarding_bot::Entity",
+        from = "Column::BotId"
+        to = "super::pm_forwarding_bot::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Bot,
+}
+
+impl Related<super::pm_forwarding_bot::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Bot.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+use sea_orm::{
+    ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbBackend, DbErr, Statement,
+    TransactionTrait,
+};
+use sea_orm_migration::MigratorTrait;
+
+/// Key of the postgres advisory lock, "pegasus" in ascii
+static ADVISORY_LOCK_KEY: i64 = 0x0070_6567_6173_7573;
+/// Name of the mysql named lock
+static NAMED_LOCK: &str = "pegasus_migration";
+/// Seconds to wait for the mysql named lock
+static NAMED_LOCK_TIMEOUT: i64 = 300;
+
+/// Migrations applied to the database that the binary does not know about
+fn unknown_migrations(applied: &[String], known: &[String]) -> Vec<String> {
+    let known: HashSet<&String> = known.iter().collect();
+    applied
+        .iter()
+        .filter(|version| !known.contains(version))
+        .cloned()
+        .collect()
+}
+
+async fn lock(txn: &DatabaseTransaction) -> Result<(), DbErr> {
+    match txn.get_database_backend() {
+        DbBackend::Postgres => {
+            // released on commit or rollback
+            txn.execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT pg_advisory_xact_lock($1)",
+                [ADVISORY_LOCK_KEY.into()],
+            ))
+            .await?;
+        }
+        DbBackend::MySql => {
+            let acquired = txn
+                .query_one(Statement::from_sql_and_values(
+                    DbBackend::MySql,
+                    "SELECT GET_LOCK(?, ?)",
+                    [NAMED_LOCK.into(), NAMED_LOCK_TIMEOUT.into()],
+                ))
+                .await?
+                .and_then(|row| row.try_get_by_index::<Option<i64>>(0).ok().flatten());
+
+            if acquired != Some(1) {
+                return Err(DbErr::Custom(format!(
+                    "Failed to acquire the migration lock in {} seconds",
+                    NAMED_LOCK_TIMEOUT
+                )));
+            }
+        }
+        // sqlite serializes writers by itself
+        DbBackend::Sqlite => {}
+    }
+
+    Ok(())
+}
+
+async fn unlock(txn: &DatabaseTransaction) -> Result<(), DbErr> {
+    if txn.get_database_backend() == DbBackend::MySql {
+        txn.execute(Statement::from_sql_and_values(
+            DbBackend::MySql,
+            "SELECT RELEASE_LOCK(?)",
+            [NAMED_LOCK.into()],
+        ))
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn check_and_migrate<M: MigratorTrait>(txn: &DatabaseTransaction) -> Result<(), DbErr> {
+    M::install(txn).await?;
+
+    let applied = M::get_migration_models(txn)
+        .await?
+        .into_iter()
+        .map(|model| model.version)
+        .collect::<Vec<_>>();
+    let known = M::migrations()
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect::<Vec<_>>();
+
+    let unknown = unknown_migrations(&applied, &known);
+    if !unknown.is_empty() {
+        return Err(DbErr::Custom(format!(
+            "Database schema is ahead of this binary, unknown migrations: {}",
+            unknown.join(", ")
+        )));
+    }
+
+    let pending = known.len() - applied.len();
+    if pending > 0 {
+        log::info!("Applying {} pending migrations", pending);
+    }
+
+    M::up(txn, None).await
+}
+
+///
+/// Apply the pending migrations while holding a database lock
+///
+/// Concurrent replicas wait for the lock, then find nothing left to apply.
+///
+/// # Arguments
+///
+/// * `db`: database connection
+///
+/// returns: `Result<(), DbErr>` error if the lock could not be taken, a migration failed,
+/// or the database has migrations the binary does not know about
+///
+pub async fn migrate<M: MigratorTrait>(db: &DatabaseConnection) -> Result<(), DbErr> {
+    // a transaction pins a single connection, which session level locks need
+    let txn = db.begin().await?;
+    lock(&txn).await?;
+
+    let result = check_and_migrate::<M>(&txn).await;
+    unlock(&txn).await?;
+
+    match result {
+        Ok(_) => txn.commit().await,
+        Err(err) => {
+            txn.rollback().await?;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_unknown_migrations() {
+        let known = vec![
+            "m20240407_142137_create_pm_forwarding_tables".to_string(),
+            "m20240501_000000_add_index".to_string(),
+        ];
+
+        assert!(unknown_migrations(&[], &known).is_empty());
+        assert!(unknown_migrations(&known[..1], &known).is_empty());
+        assert_eq!(
+            unknown_migrations(
+                &[
+                    known[0].clone(),
+                    "m20250101_000000_from_the_future".to_string()
+                ],
+                &known
+            ),
+            vec!["m20250101_000000_from_the_future".to_string()]
+        );
+    }
+}
@@ -1,12 +1,49 @@
 use sea_orm::{Database, DatabaseConnection};
+use sea_orm_migration::MigratorTrait;
 
 use crate::settings;
 
 pub mod entities;
+pub mod migrate;
+pub mod repositories;
 pub mod utils;
 
-pub async fn init_conn(
+/// Cargo feature that compiles in the driver of the database type
+fn driver_feature(database_type: &settings::DatabaseType) -> (&'static str, bool) {
+    match database_type {
+        settings::DatabaseType::Postgres => ("postgres", cfg!(feature = "postgres")),
+        settings::DatabaseType::Mysql | settings::DatabaseType::MariaDB => {
+            ("mysql", cfg!(feature = "mysql"))
+        }
+        settings::DatabaseType::Sqlite => ("sqlite", cfg!(feature = "sqlite")),
+    }
+}
+
+///
+/// Connect to the database, applying pending migrations of `M` if `database.auto_migrate` is set
+///
+/// # Arguments
+///
+/// * `database`: database settings
+///
+/// returns: `Result<DatabaseConnection, DbErr>` the connection pool
+///
+pub async fn init_conn<M: MigratorTrait>(
     database: &settings::Database,
 ) -> Result<DatabaseConnection, sea_orm::DbErr> {
-    Database::connect(utils::database_url(database)).await
+    let (feature, enabled) = driver_feature(&database.database_type);
+    if !enabled {
+        return Err(sea_orm::DbErr::Custom(format!(
+            "Database type {:?} is not supported by this build, enable the `{}` feature",
+            database.database_type, feature
+        )));
+    }
+
+    let db = Database::connect(utils::connect_options(database)?).await?;
+
+    if database.auto_migrate.unwrap_or(false) {
+        migrate::migrate::<M>(&db).await?;
+    }
+
+    Ok(db)
 }
@@ -0,0 +1,842 @@
+//! In-memory repositories, for tests of code built on the repository traits
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::DbErr;
+
+use crate::cryptor::secret_hash;
+use crate::database::entities::{
+    pm_forwarding_block, pm_forwarding_bot, pm_forwarding_message, pm_forwarding_reply,
+    pm_forwarding_topic,
+};
+use crate::database::repositories::pm_forwarding_block::{
+    NewPmForwardingBlock, PmForwardingBlockRepository,
+};
+use crate::database::repositories::pm_forwarding_bot::{
+    NewPmForwardingBot, PmForwardingBotRepository, UpdatePmForwardingBot,
+};
+use crate::database::repositories::pm_forwarding_message::{
+    NewPmForwardingMessage, PmForwardingMessageRepository,
+};
+use crate::database::repositories::pm_forwarding_reply::{
+    NewPmForwardingReply, PmForwardingReplyRepository,
+};
+use crate::database::repositories::pm_forwarding_topic::{
+    NewPmForwardingTopic, PmForwardingTopicRepository,
+};
+
+/// Bot with the column defaults of the migrations, change fields with struct update syntax
+pub fn bot_model(id: i64) -> pm_forwarding_bot::Model {
+    let now = Utc::now();
+    pm_forwarding_bot::Model {
+        id,
+        created_at: now,
+        updated_at: now,
+        bot_token: format!("{}:token", id),
+        bot_webhook_secret: "secret".to_string(),
+        bot_token_hash: None,
+        target_chat_id: -100,
+        telegram_user_refer: 1,
+        message_retention_days: None,
+        topics_enabled: false,
+        block_notice: None,
+        rate_limit: 20,
+        welcome_text: None,
+        ack_text: None,
+        away_text: None,
+        error_text: None,
+        captcha: None,
+        captcha_timeout: 300,
+        captcha_retries: 1,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryPmForwardingBotRepository {
+    rows: Mutex<Vec<pm_forwarding_bot::Model>>,
+}
+
+impl InMemoryPmForwardingBotRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a row as is, to set up fields `create` does not take
+    pub fn insert(&self, model: pm_forwarding_bot::Model) {
+        self.rows.lock().unwrap().push(model);
+    }
+
+    pub fn rows(&self) -> Vec<pm_forwarding_bot::Model> {
+        self.rows.lock().unwrap().clone()
+    }
+}
+
+impl PmForwardingBotRepository for InMemoryPmForwardingBotRepository {
+    fn create(
+        &self,
+        bot: NewPmForwardingBot,
+    ) -> BoxFuture<'_, Result<pm_forwarding_bot::Model, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.iter().any(|row| row.bot_token == bot.bot_token) {
+            return futures::future::err(DbErr::Custom(
+                "duplicate key value violates unique constraint \"bot_token\"".to_string(),
+            ))
+            .boxed();
+        }
+
+        let id = rows.iter().map(|row| row.id).max().unwrap_or_default() + 1;
+        let model = pm_forwarding_bot::Model {
+            bot_token_hash: Some(secret_hash(&bot.bot_token)),
+            bot_token: bot.bot_token,
+            bot_webhook_secret: bot.bot_webhook_secret,
+            target_chat_id: bot.target_chat_id,
+            telegram_user_refer: bot.telegram_user_refer,
+            ..bot_model(id)
+        };
+        rows.push(model.clone());
+
+        futures::future::ok(model).boxed()
+    }
+
+    fn find_by_id(
+        &self,
+        id: i64,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>> {
+        let row = self.rows().into_iter().find(|row| row.id == id);
+        futures::future::ok(row).boxed()
+    }
+
+    fn find_by_token(
+        &self,
+        bot_token: String,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>> {
+        let row = self
+            .rows()
+            .into_iter()
+            .find(|row| row.bot_token == bot_token);
+        futures::future::ok(row).boxed()
+    }
+
+    fn find_by_token_hash(
+        &self,
+        bot_token_hash: String,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>> {
+        let row = self
+            .rows()
+            .into_iter()
+            .find(|row| row.bot_token_hash.as_ref() == Some(&bot_token_hash));
+        futures::future::ok(row).boxed()
+    }
+
+    fn list_by_user(
+        &self,
+        telegram_user_refer: i64,
+    ) -> BoxFuture<'_, Result<Vec<pm_forwarding_bot::Model>, DbErr>> {
+        let rows = self
+            .rows()
+            .into_iter()
+            .filter(|row| row.telegram_user_refer == telegram_user_refer)
+            .collect();
+        futures::future::ok(rows).boxed()
+    }
+
+    fn list_all(&self) -> BoxFuture<'_, Result<Vec<pm_forwarding_bot::Model>, DbErr>> {
+        futures::future::ok(self.rows()).boxed()
+    }
+
+    fn update(
+        &self,
+        id: i64,
+        changes: UpdatePmForwardingBot,
+    ) -> BoxFuture<'_, Result<pm_forwarding_bot::Model, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        if let Some(bot_token) = &changes.bot_token {
+            if rows
+                .iter()
+                .any(|row| row.id != id && &row.bot_token == bot_token)
+            {
+                return futures::future::err(DbErr::Custom(
+                    "duplicate key value violates unique constraint \"bot_token\"".to_string(),
+                ))
+                .boxed();
+            }
+        }
+
+        let row = match rows.iter_mut().find(|row| row.id == id) {
+            Some(row) => row,
+            None => return futures::future::err(DbErr::RecordNotUpdated).boxed(),
+        };
+
+        row.updated_at = Utc::now();
+        if let Some(bot_token) = changes.bot_token {
+            row.bot_token_hash = Some(secret_hash(&bot_token));
+            row.bot_token = bot_token;
+        }
+        if let Some(bot_webhook_secret) = changes.bot_webhook_secret {
+            row.bot_webhook_secret = bot_webhook_secret;
+        }
+        if let Some(target_chat_id) = changes.target_chat_id {
+            row.target_chat_id = target_chat_id;
+        }
+        if let Some(topics_enabled) = changes.topics_enabled {
+            row.topics_enabled = topics_enabled;
+        }
+        if let Some(block_notice) = changes.block_notice {
+            row.block_notice = block_notice;
+        }
+        if let Some(rate_limit) = changes.rate_limit {
+            row.rate_limit = rate_limit;
+        }
+        if let Some(welcome_text) = changes.welcome_text {
+            row.welcome_text = welcome_text;
+        }
+        if let Some(ack_text) = changes.ack_text {
+            row.ack_text = ack_text;
+        }
+        if let Some(away_text) = changes.away_text {
+            row.away_text = away_text;
+        }
+        if let Some(error_text) = changes.error_text {
+            row.error_text = error_text;
+        }
+        if let Some(captcha) = changes.captcha {
+            row.captcha = captcha;
+        }
+        if let Some(captcha_timeout) = changes.captcha_timeout {
+            row.captcha_timeout = captcha_timeout;
+        }
+        if let Some(captcha_retries) = changes.captcha_retries {
+            row.captcha_retries = captcha_retries;
+        }
+        if let Some(message_retention_days) = changes.message_retention_days {
+            row.message_retention_days = message_retention_days;
+        }
+
+        futures::future::ok(row.clone()).boxed()
+    }
+
+    /// Mappings and blocks are kept in their own repositories, only the bot is deleted
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        let len = rows.len();
+        rows.retain(|row| row.id != id);
+
+        futures::future::ok(rows.len() < len).boxed()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryPmForwardingMessageRepository {
+    rows: Mutex<Vec<pm_forwarding_message::Model>>,
+}
+
+impl InMemoryPmForwardingMessageRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a row as is, to set up fields `create` does not take
+    pub fn insert(&self, model: pm_forwarding_message::Model) {
+        self.rows.lock().unwrap().push(model);
+    }
+
+    pub fn rows(&self) -> Vec<pm_forwarding_message::Model> {
+        self.rows.lock().unwrap().clone()
+    }
+}
+
+impl PmForwardingMessageRepository for InMemoryPmForwardingMessageRepository {
+    fn create(
+        &self,
+        message: NewPmForwardingMessage,
+    ) -> BoxFuture<'_, Result<pm_forwarding_message::Model, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.iter().any(|row| {
+            row.bot_id == message.bot_id
+                && row.target_chat_id == message.target_chat_id
+                && row.forward_telegram_message_id == message.forward_telegram_message_id
+        }) {
+            return futures::future::err(DbErr::Custom(
+                "duplicate key value violates unique constraint \"bot_id, target_chat_id, forward_telegram_message_id\""
+                    .to_string(),
+            ))
+            .boxed();
+        }
+
+        let now = Utc::now();
+        let model = pm_forwarding_message::Model {
+            id: rows.iter().map(|row| row.id).max().unwrap_or_default() + 1,
+            created_at: now,
+            updated_at: now,
+            bot_id: message.bot_id,
+            target_chat_id: message.target_chat_id,
+            telegram_chat_id: message.telegram_chat_id,
+            telegram_message_id: message.telegram_message_id,
+            forward_telegram_message_id: message.forward_telegram_message_id,
+        };
+        rows.push(model.clone());
+
+        futures::future::ok(model).boxed()
+    }
+
+    fn find_by_forward_message_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        forward_telegram_message_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_message::Model>, DbErr>> {
+        let row = self.rows().into_iter().find(|row| {
+            row.bot_id == bot_id
+                && row.target_chat_id == target_chat_id
+                && row.forward_telegram_message_id == forward_telegram_message_id
+        });
+        futures::future::ok(row).boxed()
+    }
+
+    fn delete_created_before(
+        &self,
+        bot_id: i64,
+        before: DateTime<Utc>,
+        limit: u64,
+    ) -> BoxFuture<'_, Result<u64, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+
+        let mut deleted = 0;
+        rows.retain(|row| {
+            let expired = row.bot_id == bot_id && row.created_at < before && deleted < limit;
+            if expired {
+                deleted += 1;
+            }
+            !expired
+        });
+
+        futures::future::ok(deleted).boxed()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryPmForwardingReplyRepository {
+    rows: Mutex<Vec<pm_forwarding_reply::Model>>,
+}
+
+impl InMemoryPmForwardingReplyRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(&self) -> Vec<pm_forwarding_reply::Model> {
+        self.rows.lock().unwrap().clone()
+    }
+}
+
+impl PmForwardingReplyRepository for InMemoryPmForwardingReplyRepository {
+    fn create(
+        &self,
+        reply: NewPmForwardingReply,
+    ) -> BoxFuture<'_, Result<pm_forwarding_reply::Model, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.iter().any(|row| {
+            row.bot_id == reply.bot_id
+                && row.target_chat_id == reply.target_chat_id
+                && row.target_message_id == reply.target_message_id
+        }) {
+            return futures::future::err(DbErr::Custom(
+                "duplicate key value violates unique constraint \"bot_id, target_chat_id, target_message_id\""
+                    .to_string(),
+            ))
+            .boxed();
+        }
+
+        let now = Utc::now();
+        let model = pm_forwarding_reply::Model {
+            id: rows.iter().map(|row| row.id).max().unwrap_or_default() + 1,
+            created_at: now,
+            updated_at: now,
+            bot_id: reply.bot_id,
+            target_chat_id: reply.target_chat_id,
+            target_message_id: reply.target_message_id,
+            telegram_chat_id: reply.telegram_chat_id,
+            telegram_message_id: reply.telegram_message_id,
+        };
+        rows.push(model.clone());
+
+        futures::future::ok(model).boxed()
+    }
+
+    fn find_by_target_message_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        target_message_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_reply::Model>, DbErr>> {
+        let row = self.rows().into_iter().find(|row| {
+            row.bot_id == bot_id
+                && row.target_chat_id == target_chat_id
+                && row.target_message_id == target_message_id
+        });
+        futures::future::ok(row).boxed()
+    }
+
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        let len = rows.len();
+        rows.retain(|row| row.id != id);
+
+        futures::future::ok(rows.len() < len).boxed()
+    }
+
+    fn delete_created_before(
+        &self,
+        bot_id: i64,
+        before: DateTime<Utc>,
+        limit: u64,
+    ) -> BoxFuture<'_, Result<u64, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+
+        let mut deleted = 0;
+        rows.retain(|row| {
+            let expired = row.bot_id == bot_id && row.created_at < before && deleted < limit;
+            if expired {
+                deleted += 1;
+            }
+            !expired
+        });
+
+        futures::future::ok(deleted).boxed()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryPmForwardingTopicRepository {
+    rows: Mutex<Vec<pm_forwarding_topic::Model>>,
+}
+
+impl InMemoryPmForwardingTopicRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(&self) -> Vec<pm_forwarding_topic::Model> {
+        self.rows.lock().unwrap().clone()
+    }
+}
+
+impl PmForwardingTopicRepository for InMemoryPmForwardingTopicRepository {
+    fn create(
+        &self,
+        topic: NewPmForwardingTopic,
+    ) -> BoxFuture<'_, Result<pm_forwarding_topic::Model, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.iter().any(|row| {
+            row.bot_id == topic.bot_id
+                && row.target_chat_id == topic.target_chat_id
+                && (row.telegram_chat_id == topic.telegram_chat_id
+                    || row.message_thread_id == topic.message_thread_id)
+        }) {
+            return futures::future::err(DbErr::Custom(
+                "duplicate key value violates unique constraint \"bot_id, target_chat_id, telegram_chat_id\""
+                    .to_string(),
+            ))
+            .boxed();
+        }
+
+        let now = Utc::now();
+        let model = pm_forwarding_topic::Model {
+            id: rows.iter().map(|row| row.id).max().unwrap_or_default() + 1,
+            created_at: now,
+            updated_at: now,
+            bot_id: topic.bot_id,
+            target_chat_id: topic.target_chat_id,
+            message_thread_id: topic.message_thread_id,
+            telegram_chat_id: topic.telegram_chat_id,
+        };
+        rows.push(model.clone());
+
+        futures::future::ok(model).boxed()
+    }
+
+    fn find_by_telegram_chat_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        telegram_chat_id: i64,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_topic::Model>, DbErr>> {
+        let row = self.rows().into_iter().find(|row| {
+            row.bot_id == bot_id
+                && row.target_chat_id == target_chat_id
+                && row.telegram_chat_id == telegram_chat_id
+        });
+        futures::future::ok(row).boxed()
+    }
+
+    fn find_by_message_thread_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        message_thread_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_topic::Model>, DbErr>> {
+        let row = self.rows().into_iter().find(|row| {
+            row.bot_id == bot_id
+                && row.target_chat_id == target_chat_id
+                && row.message_thread_id == message_thread_id
+        });
+        futures::future::ok(row).boxed()
+    }
+
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        let len = rows.len();
+        rows.retain(|row| row.id != id);
+
+        futures::future::ok(rows.len() < len).boxed()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryPmForwardingBlockRepository {
+    rows: Mutex<Vec<pm_forwarding_block::Model>>,
+}
+
+impl InMemoryPmForwardingBlockRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(&self) -> Vec<pm_forwarding_block::Model> {
+        self.rows.lock().unwrap().clone()
+    }
+}
+
+impl PmForwardingBlockRepository for InMemoryPmForwardingBlockRepository {
+    fn upsert(
+        &self,
+        block: NewPmForwardingBlock,
+    ) -> BoxFuture<'_, Result<pm_forwarding_block::Model, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+
+        let now = Utc::now();
+        if let Some(row) = rows.iter_mut().find(|row| {
+            row.bot_id == block.bot_id && row.telegram_chat_id == block.telegram_chat_id
+        }) {
+            row.updated_at = now;
+            row.blocked_until = block.blocked_until;
+            return futures::future::ok(row.clone()).boxed();
+        }
+
+        let model = pm_forwarding_block::Model {
+            id: rows.iter().map(|row| row.id).max().unwrap_or_default() + 1,
+            created_at: now,
+            updated_at: now,
+            bot_id: block.bot_id,
+            telegram_chat_id: block.telegram_chat_id,
+            blocked_until: block.blocked_until,
+        };
+        rows.push(model.clone());
+
+        futures::future::ok(model).boxed()
+    }
+
+    fn find_active(
+        &self,
+        bot_id: i64,
+        telegram_chat_id: i64,
+        now: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_block::Model>, DbErr>> {
+        let row = self.rows().into_iter().find(|row| {
+            row.bot_id == bot_id && row.telegram_chat_id == telegram_chat_id && row.is_active(now)
+        });
+        futures::future::ok(row).boxed()
+    }
+
+    fn list_active(
+        &self,
+        bot_id: i64,
+        now: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Vec<pm_forwarding_block::Model>, DbErr>> {
+        let rows = self
+            .rows()
+            .into_iter()
+            .filter(|row| row.bot_id == bot_id && row.is_active(now))
+            .collect();
+        futures::future::ok(rows).boxed()
+    }
+
+    fn delete(&self, bot_id: i64, telegram_chat_id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        let mut rows = self.rows.lock().unwrap();
+        let len = rows.len();
+        rows.retain(|row| !(row.bot_id == bot_id && row.telegram_chat_id == telegram_chat_id));
+
+        futures::future::ok(rows.len() < len).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_bot_repository() {
+        let repository = InMemoryPmForwardingBotRepository::new();
+        let new_bot = NewPmForwardingBot {
+            bot_token: "123:token".to_string(),
+            bot_webhook_secret: "secret".to_string(),
+            target_chat_id: 1,
+            telegram_user_refer: 2,
+        };
+
+        let bot = repository.create(new_bot.clone()).await.unwrap();
+        assert_eq!(bot.id, 1);
+        assert!(repository.create(new_bot.clone()).await.is_err());
+
+        assert_eq!(
+            repository
+                .find_by_token("123:token".to_string())
+                .await
+                .unwrap(),
+            Some(bot.clone())
+        );
+        assert_eq!(repository.find_by_id(2).await.unwrap(), None);
+        assert_eq!(repository.list_by_user(2).await.unwrap(), vec![bot.clone()]);
+        assert!(repository.list_by_user(3).await.unwrap().is_empty());
+
+        let other = repository
+            .create(NewPmForwardingBot {
+                bot_token: "456:token".to_string(),
+                ..new_bot
+            })
+            .await
+            .unwrap();
+        let updated = repository
+            .update(
+                bot.id,
+                UpdatePmForwardingBot {
+                    bot_token: Some("123:new-token".to_string()),
+                    target_chat_id: Some(3),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.bot_token, "123:new-token");
+        assert_eq!(updated.bot_webhook_secret, "secret");
+        assert_eq!(updated.target_chat_id, 3);
+        assert_eq!(
+            repository
+                .find_by_token("123:new-token".to_string())
+                .await
+                .unwrap(),
+            Some(updated)
+        );
+        assert!(repository
+            .update(
+                bot.id,
+                UpdatePmForwardingBot {
+                    bot_token: Some(other.bot_token.clone()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .is_err());
+        assert!(repository
+            .update(3, UpdatePmForwardingBot::default())
+            .await
+            .is_err());
+
+        assert!(repository.delete(other.id).await.unwrap());
+        assert!(repository.delete(1).await.unwrap());
+        assert!(!repository.delete(1).await.unwrap());
+        assert!(repository.list_all().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_message_repository_delete() {
+        let repository = InMemoryPmForwardingMessageRepository::new();
+        for forward_telegram_message_id in 0..5 {
+            repository
+                .create(NewPmForwardingMessage {
+                    bot_id: forward_telegram_message_id as i64 % 2,
+                    target_chat_id: -100,
+                    telegram_chat_id: 1,
+                    telegram_message_id: 1,
+                    forward_telegram_message_id,
+                })
+                .await
+                .unwrap();
+        }
+
+        let later = Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(
+            repository.delete_created_before(0, later, 2).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            repository.delete_created_before(0, later, 2).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            repository.delete_created_before(0, later, 2).await.unwrap(),
+            0
+        );
+        assert_eq!(repository.rows().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_reply_repository() {
+        let repository = InMemoryPmForwardingReplyRepository::new();
+        let new_reply = NewPmForwardingReply {
+            bot_id: 1,
+            target_chat_id: -100,
+            target_message_id: 100,
+            telegram_chat_id: 42,
+            telegram_message_id: 7,
+        };
+
+        let reply = repository.create(new_reply.clone()).await.unwrap();
+        assert!(repository.create(new_reply.clone()).await.is_err());
+        // target message ids are per bot and target chat
+        repository
+            .create(NewPmForwardingReply {
+                bot_id: 2,
+                ..new_reply.clone()
+            })
+            .await
+            .unwrap();
+        repository
+            .create(NewPmForwardingReply {
+                target_chat_id: -200,
+                ..new_reply
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            repository
+                .find_by_target_message_id(1, -100, 100)
+                .await
+                .unwrap(),
+            Some(reply.clone())
+        );
+        assert_eq!(
+            repository
+                .find_by_target_message_id(1, -100, 101)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert!(repository.delete(reply.id).await.unwrap());
+        assert!(!repository.delete(reply.id).await.unwrap());
+        assert_eq!(repository.rows().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_topic_repository() {
+        let repository = InMemoryPmForwardingTopicRepository::new();
+        let new_topic = NewPmForwardingTopic {
+            bot_id: 1,
+            target_chat_id: -100,
+            message_thread_id: 5,
+            telegram_chat_id: 42,
+        };
+
+        let topic = repository.create(new_topic.clone()).await.unwrap();
+        // one topic per sender, and one sender per topic
+        assert!(repository
+            .create(NewPmForwardingTopic {
+                message_thread_id: 6,
+                ..new_topic.clone()
+            })
+            .await
+            .is_err());
+        assert!(repository
+            .create(NewPmForwardingTopic {
+                telegram_chat_id: 43,
+                ..new_topic.clone()
+            })
+            .await
+            .is_err());
+        // topics are per bot and target chat
+        repository
+            .create(NewPmForwardingTopic {
+                target_chat_id: -200,
+                ..new_topic
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            repository
+                .find_by_telegram_chat_id(1, -100, 42)
+                .await
+                .unwrap(),
+            Some(topic.clone())
+        );
+        assert_eq!(
+            repository
+                .find_by_message_thread_id(1, -100, 5)
+                .await
+                .unwrap(),
+            Some(topic.clone())
+        );
+        assert_eq!(
+            repository
+                .find_by_message_thread_id(2, -100, 5)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert!(repository.delete(topic.id).await.unwrap());
+        assert!(!repository.delete(topic.id).await.unwrap());
+        assert_eq!(repository.rows().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_block_repository() {
+        let repository = InMemoryPmForwardingBlockRepository::new();
+        let now = Utc::now();
+
+        let ban = repository
+            .upsert(NewPmForwardingBlock {
+                bot_id: 1,
+                telegram_chat_id: 42,
+                blocked_until: None,
+            })
+            .await
+            .unwrap();
+        // a mute replaces the ban
+        let mute = repository
+            .upsert(NewPmForwardingBlock {
+                bot_id: 1,
+                telegram_chat_id: 42,
+                blocked_until: Some(now + chrono::Duration::hours(1)),
+            })
+            .await
+            .unwrap();
+        assert_eq!(mute.id, ban.id);
+        assert_eq!(repository.rows().len(), 1);
+
+        assert_eq!(
+            repository.find_active(1, 42, now).await.unwrap(),
+            Some(mute.clone())
+        );
+        // blocks are per bot, and mutes expire
+        assert_eq!(repository.find_active(2, 42, now).await.unwrap(), None);
+        assert_eq!(
+            repository
+                .find_active(1, 42, now + chrono::Duration::hours(2))
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(repository.list_active(1, now).await.unwrap(), vec![mute]);
+
+        assert!(repository.delete(1, 42).await.unwrap());
+        assert!(!repository.delete(1, 42).await.unwrap());
+        assert!(repository.list_active(1, now).await.unwrap().is_empty());
+    }
+}
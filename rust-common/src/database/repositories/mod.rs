@@ -0,0 +1,7 @@
+#[cfg(any(test, feature = "testing"))]
+pub mod memory;
+pub mod pm_forwarding_block;
+pub mod pm_forwarding_bot;
+pub mod pm_forwarding_message;
+pub mod pm_forwarding_reply;
+pub mod pm_forwarding_topic;
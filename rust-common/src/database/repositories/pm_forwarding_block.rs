@@ -0,0 +1,146 @@
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::prelude::*;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveValue, Condition, QueryOrder};
+
+use crate::database::entities::pm_forwarding_block;
+
+/// Fields of a block to insert, the rest are filled by the database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewPmForwardingBlock {
+    pub bot_id: i64,
+    pub telegram_chat_id: i64,
+    /// end of a mute, `None` for a ban
+    pub blocked_until: Option<DateTime<Utc>>,
+}
+
+pub trait PmForwardingBlockRepository: Debug + Send + Sync {
+    /// Block a sender, replacing an earlier ban or mute of them
+    fn upsert(
+        &self,
+        block: NewPmForwardingBlock,
+    ) -> BoxFuture<'_, Result<pm_forwarding_block::Model, DbErr>>;
+
+    /// Find the block of a sender in force at `now`, expired mutes are not found
+    fn find_active(
+        &self,
+        bot_id: i64,
+        telegram_chat_id: i64,
+        now: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_block::Model>, DbErr>>;
+
+    /// List the blocks of the bot in force at `now`, oldest first
+    fn list_active(
+        &self,
+        bot_id: i64,
+        now: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Vec<pm_forwarding_block::Model>, DbErr>>;
+
+    /// Unblock a sender, returns whether they were blocked
+    fn delete(&self, bot_id: i64, telegram_chat_id: i64) -> BoxFuture<'_, Result<bool, DbErr>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SeaOrmPmForwardingBlockRepository {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmPmForwardingBlockRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+/// Blocks in force at `now`, bans and mutes not over yet
+fn active_condition(now: DateTime<Utc>) -> Condition {
+    Condition::any()
+        .add(pm_forwarding_block::Column::BlockedUntil.is_null())
+        .add(pm_forwarding_block::Column::BlockedUntil.gt(now))
+}
+
+impl PmForwardingBlockRepository for SeaOrmPmForwardingBlockRepository {
+    fn upsert(
+        &self,
+        block: NewPmForwardingBlock,
+    ) -> BoxFuture<'_, Result<pm_forwarding_block::Model, DbErr>> {
+        async move {
+            // set by us rather than the column default, sqlite stores the default in another format
+            let now = Utc::now();
+            pm_forwarding_block::Entity::insert(pm_forwarding_block::ActiveModel {
+                created_at: ActiveValue::Set(now),
+                updated_at: ActiveValue::Set(now),
+                bot_id: ActiveValue::Set(block.bot_id),
+                telegram_chat_id: ActiveValue::Set(block.telegram_chat_id),
+                blocked_until: ActiveValue::Set(block.blocked_until),
+                ..Default::default()
+            })
+            .on_conflict(
+                OnConflict::columns([
+                    pm_forwarding_block::Column::BotId,
+                    pm_forwarding_block::Column::TelegramChatId,
+                ])
+                .update_columns([
+                    pm_forwarding_block::Column::UpdatedAt,
+                    pm_forwarding_block::Column::BlockedUntil,
+                ])
+                .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+
+            // the last insert id is not reported for updated rows on every backend
+            pm_forwarding_block::Entity::find()
+                .filter(pm_forwarding_block::Column::BotId.eq(block.bot_id))
+                .filter(pm_forwarding_block::Column::TelegramChatId.eq(block.telegram_chat_id))
+                .one(&self.db)
+                .await?
+                .ok_or_else(|| DbErr::RecordNotFound("Block not found".to_string()))
+        }
+        .boxed()
+    }
+
+    fn find_active(
+        &self,
+        bot_id: i64,
+        telegram_chat_id: i64,
+        now: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_block::Model>, DbErr>> {
+        pm_forwarding_block::Entity::find()
+            .filter(pm_forwarding_block::Column::BotId.eq(bot_id))
+            .filter(pm_forwarding_block::Column::TelegramChatId.eq(telegram_chat_id))
+            .filter(active_condition(now))
+            .one(&self.db)
+            .boxed()
+    }
+
+    fn list_active(
+        &self,
+        bot_id: i64,
+        now: DateTime<Utc>,
+    ) -> BoxFuture<'_, Result<Vec<pm_forwarding_block::Model>, DbErr>> {
+        pm_forwarding_block::Entity::find()
+            .filter(pm_forwarding_block::Column::BotId.eq(bot_id))
+            .filter(active_condition(now))
+            .order_by_asc(pm_forwarding_block::Column::CreatedAt)
+            .order_by_asc(pm_forwarding_block::Column::Id)
+            .all(&self.db)
+            .boxed()
+    }
+
+    fn delete(&self, bot_id: i64, telegram_chat_id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        async move {
+            let result = pm_forwarding_block::Entity::delete_many()
+                .filter(pm_forwarding_block::Column::BotId.eq(bot_id))
+                .filter(pm_forwarding_block::Column::TelegramChatId.eq(telegram_chat_id))
+                .exec(&self.db)
+                .await?;
+
+            Ok(result.rows_affected > 0)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,377 @@
+use std::fmt::Debug;
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::prelude::*;
+use sea_orm::{ActiveValue, TransactionTrait};
+
+use crate::cryptor::{secret_hash, Cryptor};
+use crate::database::entities::{
+    pm_forwarding_block, pm_forwarding_bot, pm_forwarding_message, pm_forwarding_reply,
+    pm_forwarding_topic,
+};
+
+/// Fields of a bot record to insert, the rest are filled by the database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewPmForwardingBot {
+    pub bot_token: String,
+    pub bot_webhook_secret: String,
+    pub target_chat_id: i64,
+    pub telegram_user_refer: i64,
+}
+
+/// Fields of a bot record to change, `None` keeps the current value
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpdatePmForwardingBot {
+    pub bot_token: Option<String>,
+    pub bot_webhook_secret: Option<String>,
+    pub target_chat_id: Option<i64>,
+    pub topics_enabled: Option<bool>,
+    /// `Some(None)` clears the notice
+    pub block_notice: Option<Option<String>>,
+    pub rate_limit: Option<i32>,
+    /// `Some(None)` restores the default text
+    pub welcome_text: Option<Option<String>>,
+    pub ack_text: Option<Option<String>>,
+    pub away_text: Option<Option<String>>,
+    pub error_text: Option<Option<String>>,
+    /// `Some(None)` turns the captcha off
+    pub captcha: Option<Option<String>>,
+    pub captcha_timeout: Option<i32>,
+    pub captcha_retries: Option<i32>,
+    /// `Some(None)` restores the default retention of the settings
+    pub message_retention_days: Option<Option<i32>>,
+}
+
+pub trait PmForwardingBotRepository: Debug + Send + Sync {
+    /// Insert a bot record, fails if the token is already registered
+    fn create(
+        &self,
+        bot: NewPmForwardingBot,
+    ) -> BoxFuture<'_, Result<pm_forwarding_bot::Model, DbErr>>;
+
+    fn find_by_id(&self, id: i64)
+        -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>>;
+
+    fn find_by_token(
+        &self,
+        bot_token: String,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>>;
+
+    /// Find a bot by the hash of its token, see [`crate::cryptor::secret_hash`]
+    fn find_by_token_hash(
+        &self,
+        bot_token_hash: String,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>>;
+
+    /// List bots registered by the telegram user
+    fn list_by_user(
+        &self,
+        telegram_user_refer: i64,
+    ) -> BoxFuture<'_, Result<Vec<pm_forwarding_bot::Model>, DbErr>>;
+
+    fn list_all(&self) -> BoxFuture<'_, Result<Vec<pm_forwarding_bot::Model>, DbErr>>;
+
+    /// Change fields of a bot record, fails if the bot does not exist or the token is registered
+    fn update(
+        &self,
+        id: i64,
+        changes: UpdatePmForwardingBot,
+    ) -> BoxFuture<'_, Result<pm_forwarding_bot::Model, DbErr>>;
+
+    /// Delete a bot record with its message, reply and topic mappings and its blocks, returns whether the bot existed
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>>;
+}
+
+///
+/// Bot repository on sea-orm
+///
+/// The bot token and the webhook secret are encrypted by the cryptor before they are written,
+/// and decrypted after they are read. Bots are looked up by the token hash.
+///
+#[derive(Clone, Debug)]
+pub struct SeaOrmPmForwardingBotRepository {
+    db: DatabaseConnection,
+    cryptor: Cryptor,
+}
+
+/// Encryption contexts of the secret columns, a value copied into another column does not decrypt
+static BOT_TOKEN_CONTEXT: &str = "pm_forwarding_bots.bot_token";
+static BOT_WEBHOOK_SECRET_CONTEXT: &str = "pm_forwarding_bots.bot_webhook_secret";
+
+fn cryptor_error(err: crate::cryptor::CryptorError) -> DbErr {
+    DbErr::Custom(err.to_string())
+}
+
+impl SeaOrmPmForwardingBotRepository {
+    pub fn new(db: DatabaseConnection, cryptor: Cryptor) -> Self {
+        Self { db, cryptor }
+    }
+
+    fn decrypt(&self, model: pm_forwarding_bot::Model) -> Result<pm_forwarding_bot::Model, DbErr> {
+        Ok(pm_forwarding_bot::Model {
+            bot_token: self
+                .cryptor
+                .decrypt(BOT_TOKEN_CONTEXT, &model.bot_token)
+                .map_err(cryptor_error)?,
+            bot_webhook_secret: self
+                .cryptor
+                .decrypt(BOT_WEBHOOK_SECRET_CONTEXT, &model.bot_webhook_secret)
+                .map_err(cryptor_error)?,
+            ..model
+        })
+    }
+
+    fn decrypt_all(
+        &self,
+        models: Vec<pm_forwarding_bot::Model>,
+    ) -> Result<Vec<pm_forwarding_bot::Model>, DbErr> {
+        models
+            .into_iter()
+            .map(|model| self.decrypt(model))
+            .collect()
+    }
+
+    ///
+    /// Encrypt the secrets of every bot with the primary key again
+    ///
+    /// Plaintext secrets stored before encryption was enabled are encrypted, and missing token
+    /// hashes are filled. Rows already under the primary key are skipped.
+    ///
+    /// returns: `Result<u64, DbErr>` count of updated bots
+    ///
+    pub async fn rotate_secrets(&self) -> Result<u64, DbErr> {
+        let mut updated = 0;
+        for model in pm_forwarding_bot::Entity::find().all(&self.db).await? {
+            if !self.cryptor.needs_rotation(&model.bot_token)
+                && !self.cryptor.needs_rotation(&model.bot_webhook_secret)
+                && model.bot_token_hash.is_some()
+            {
+                continue;
+            }
+
+            let id = model.id;
+            let model = self.decrypt(model)?;
+            pm_forwarding_bot::ActiveModel {
+                id: ActiveValue::Unchanged(id),
+                bot_token: ActiveValue::Set(
+                    self.cryptor
+                        .encrypt(BOT_TOKEN_CONTEXT, &model.bot_token)
+                        .map_err(cryptor_error)?,
+                ),
+                bot_webhook_secret: ActiveValue::Set(
+                    self.cryptor
+                        .encrypt(BOT_WEBHOOK_SECRET_CONTEXT, &model.bot_webhook_secret)
+                        .map_err(cryptor_error)?,
+                ),
+                bot_token_hash: ActiveValue::Set(Some(secret_hash(&model.bot_token))),
+                ..Default::default()
+            }
+            .update(&self.db)
+            .await?;
+
+            updated += 1;
+        }
+
+        Ok(updated)
+    }
+}
+
+impl PmForwardingBotRepository for SeaOrmPmForwardingBotRepository {
+    fn create(
+        &self,
+        bot: NewPmForwardingBot,
+    ) -> BoxFuture<'_, Result<pm_forwarding_bot::Model, DbErr>> {
+        async move {
+            // set by us rather than the column default, sqlite stores the default in another format
+            let now = Utc::now();
+            let model = pm_forwarding_bot::ActiveModel {
+                created_at: ActiveValue::Set(now),
+                updated_at: ActiveValue::Set(now),
+                bot_token: ActiveValue::Set(
+                    self.cryptor
+                        .encrypt(BOT_TOKEN_CONTEXT, &bot.bot_token)
+                        .map_err(cryptor_error)?,
+                ),
+                bot_webhook_secret: ActiveValue::Set(
+                    self.cryptor
+                        .encrypt(BOT_WEBHOOK_SECRET_CONTEXT, &bot.bot_webhook_secret)
+                        .map_err(cryptor_error)?,
+                ),
+                bot_token_hash: ActiveValue::Set(Some(secret_hash(&bot.bot_token))),
+                target_chat_id: ActiveValue::Set(bot.target_chat_id),
+                telegram_user_refer: ActiveValue::Set(bot.telegram_user_refer),
+                ..Default::default()
+            }
+            .insert(&self.db)
+            .await?;
+
+            self.decrypt(model)
+        }
+        .boxed()
+    }
+
+    fn find_by_id(
+        &self,
+        id: i64,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>> {
+        async move {
+            pm_forwarding_bot::Entity::find_by_id(id)
+                .one(&self.db)
+                .await?
+                .map(|model| self.decrypt(model))
+                .transpose()
+        }
+        .boxed()
+    }
+
+    fn find_by_token(
+        &self,
+        bot_token: String,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>> {
+        self.find_by_token_hash(secret_hash(&bot_token))
+    }
+
+    fn find_by_token_hash(
+        &self,
+        bot_token_hash: String,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_bot::Model>, DbErr>> {
+        async move {
+            pm_forwarding_bot::Entity::find()
+                .filter(pm_forwarding_bot::Column::BotTokenHash.eq(bot_token_hash))
+                .one(&self.db)
+                .await?
+                .map(|model| self.decrypt(model))
+                .transpose()
+        }
+        .boxed()
+    }
+
+    fn list_by_user(
+        &self,
+        telegram_user_refer: i64,
+    ) -> BoxFuture<'_, Result<Vec<pm_forwarding_bot::Model>, DbErr>> {
+        async move {
+            let models = pm_forwarding_bot::Entity::find()
+                .filter(pm_forwarding_bot::Column::TelegramUserRefer.eq(telegram_user_refer))
+                .all(&self.db)
+                .await?;
+
+            self.decrypt_all(models)
+        }
+        .boxed()
+    }
+
+    fn list_all(&self) -> BoxFuture<'_, Result<Vec<pm_forwarding_bot::Model>, DbErr>> {
+        async move {
+            let models = pm_forwarding_bot::Entity::find().all(&self.db).await?;
+
+            self.decrypt_all(models)
+        }
+        .boxed()
+    }
+
+    fn update(
+        &self,
+        id: i64,
+        changes: UpdatePmForwardingBot,
+    ) -> BoxFuture<'_, Result<pm_forwarding_bot::Model, DbErr>> {
+        async move {
+            let mut model = pm_forwarding_bot::ActiveModel {
+                id: ActiveValue::Unchanged(id),
+                updated_at: ActiveValue::Set(Utc::now()),
+                ..Default::default()
+            };
+
+            if let Some(bot_token) = changes.bot_token {
+                model.bot_token = ActiveValue::Set(
+                    self.cryptor
+                        .encrypt(BOT_TOKEN_CONTEXT, &bot_token)
+                        .map_err(cryptor_error)?,
+                );
+                model.bot_token_hash = ActiveValue::Set(Some(secret_hash(&bot_token)));
+            }
+            if let Some(bot_webhook_secret) = changes.bot_webhook_secret {
+                model.bot_webhook_secret = ActiveValue::Set(
+                    self.cryptor
+                        .encrypt(BOT_WEBHOOK_SECRET_CONTEXT, &bot_webhook_secret)
+                        .map_err(cryptor_error)?,
+                );
+            }
+            if let Some(target_chat_id) = changes.target_chat_id {
+                model.target_chat_id = ActiveValue::Set(target_chat_id);
+            }
+            if let Some(topics_enabled) = changes.topics_enabled {
+                model.topics_enabled = ActiveValue::Set(topics_enabled);
+            }
+            if let Some(block_notice) = changes.block_notice {
+                model.block_notice = ActiveValue::Set(block_notice);
+            }
+            if let Some(rate_limit) = changes.rate_limit {
+                model.rate_limit = ActiveValue::Set(rate_limit);
+            }
+            if let Some(welcome_text) = changes.welcome_text {
+                model.welcome_text = ActiveValue::Set(welcome_text);
+            }
+            if let Some(ack_text) = changes.ack_text {
+                model.ack_text = ActiveValue::Set(ack_text);
+            }
+            if let Some(away_text) = changes.away_text {
+                model.away_text = ActiveValue::Set(away_text);
+            }
+            if let Some(error_text) = changes.error_text {
+                model.error_text = ActiveValue::Set(error_text);
+            }
+            if let Some(captcha) = changes.captcha {
+                model.captcha = ActiveValue::Set(captcha);
+            }
+            if let Some(captcha_timeout) = changes.captcha_timeout {
+                model.captcha_timeout = ActiveValue::Set(captcha_timeout);
+            }
+            if let Some(captcha_retries) = changes.captcha_retries {
+                model.captcha_retries = ActiveValue::Set(captcha_retries);
+            }
+            if let Some(message_retention_days) = changes.message_retention_days {
+                model.message_retention_days = ActiveValue::Set(message_retention_days);
+            }
+
+            let model = model.update(&self.db).await?;
+
+            self.decrypt(model)
+        }
+        .boxed()
+    }
+
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        async move {
+            let txn = self.db.begin().await?;
+
+            // explicit, the foreign key only cascades once the constraints migration is applied
+            pm_forwarding_message::Entity::delete_many()
+                .filter(pm_forwarding_message::Column::BotId.eq(id))
+                .exec(&txn)
+                .await?;
+            pm_forwarding_reply::Entity::delete_many()
+                .filter(pm_forwarding_reply::Column::BotId.eq(id))
+                .exec(&txn)
+                .await?;
+            pm_forwarding_topic::Entity::delete_many()
+                .filter(pm_forwarding_topic::Column::BotId.eq(id))
+                .exec(&txn)
+                .await?;
+            pm_forwarding_block::Entity::delete_many()
+                .filter(pm_forwarding_block::Column::BotId.eq(id))
+                .exec(&txn)
+                .await?;
+            let result = pm_forwarding_bot::Entity::delete_by_id(id)
+                .exec(&txn)
+                .await?;
+
+            txn.commit().await?;
+
+            Ok(result.rows_affected > 0)
+        }
+        .boxed()
+    }
+}
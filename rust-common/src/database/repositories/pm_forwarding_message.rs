@@ -0,0 +1,124 @@
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::prelude::*;
+use sea_orm::{ActiveValue, QuerySelect};
+
+use crate::database::entities::pm_forwarding_message;
+
+/// Fields of a message mapping to insert, the rest are filled by the database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewPmForwardingMessage {
+    pub bot_id: i64,
+    pub target_chat_id: i64,
+    pub telegram_chat_id: i64,
+    pub telegram_message_id: i32,
+    pub forward_telegram_message_id: i32,
+}
+
+pub trait PmForwardingMessageRepository: Debug + Send + Sync {
+    fn create(
+        &self,
+        message: NewPmForwardingMessage,
+    ) -> BoxFuture<'_, Result<pm_forwarding_message::Model, DbErr>>;
+
+    /// Find the mapping of a message the bot forwarded to the target chat
+    fn find_by_forward_message_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        forward_telegram_message_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_message::Model>, DbErr>>;
+
+    /// Delete at most `limit` mappings of the bot created before `before`, returns the count deleted
+    fn delete_created_before(
+        &self,
+        bot_id: i64,
+        before: DateTime<Utc>,
+        limit: u64,
+    ) -> BoxFuture<'_, Result<u64, DbErr>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SeaOrmPmForwardingMessageRepository {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmPmForwardingMessageRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+impl PmForwardingMessageRepository for SeaOrmPmForwardingMessageRepository {
+    fn create(
+        &self,
+        message: NewPmForwardingMessage,
+    ) -> BoxFuture<'_, Result<pm_forwarding_message::Model, DbErr>> {
+        // set by us rather than the column default, sqlite stores the default in another format
+        let now = Utc::now();
+        pm_forwarding_message::ActiveModel {
+            created_at: ActiveValue::Set(now),
+            updated_at: ActiveValue::Set(now),
+            bot_id: ActiveValue::Set(message.bot_id),
+            target_chat_id: ActiveValue::Set(message.target_chat_id),
+            telegram_chat_id: ActiveValue::Set(message.telegram_chat_id),
+            telegram_message_id: ActiveValue::Set(message.telegram_message_id),
+            forward_telegram_message_id: ActiveValue::Set(message.forward_telegram_message_id),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .boxed()
+    }
+
+    fn find_by_forward_message_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        forward_telegram_message_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_message::Model>, DbErr>> {
+        pm_forwarding_message::Entity::find()
+            .filter(pm_forwarding_message::Column::BotId.eq(bot_id))
+            .filter(pm_forwarding_message::Column::TargetChatId.eq(target_chat_id))
+            .filter(
+                pm_forwarding_message::Column::ForwardTelegramMessageId
+                    .eq(forward_telegram_message_id),
+            )
+            .one(&self.db)
+            .boxed()
+    }
+
+    fn delete_created_before(
+        &self,
+        bot_id: i64,
+        before: DateTime<Utc>,
+        limit: u64,
+    ) -> BoxFuture<'_, Result<u64, DbErr>> {
+        async move {
+            // delete by primary key, so a single statement never locks the whole table
+            let ids: Vec<i64> = pm_forwarding_message::Entity::find()
+                .select_only()
+                .column(pm_forwarding_message::Column::Id)
+                .filter(pm_forwarding_message::Column::BotId.eq(bot_id))
+                .filter(pm_forwarding_message::Column::CreatedAt.lt(before))
+                .limit(limit)
+                .into_tuple()
+                .all(&self.db)
+                .await?;
+
+            if ids.is_empty() {
+                return Ok(0);
+            }
+
+            let result = pm_forwarding_message::Entity::delete_many()
+                .filter(pm_forwarding_message::Column::Id.is_in(ids))
+                .exec(&self.db)
+                .await?;
+
+            Ok(result.rows_affected)
+        }
+        .boxed()
+    }
+}
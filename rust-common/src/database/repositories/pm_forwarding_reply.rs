@@ -0,0 +1,136 @@
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::prelude::*;
+use sea_orm::{ActiveValue, QuerySelect};
+
+use crate::database::entities::pm_forwarding_reply;
+
+/// Fields of a reply mapping to insert, the rest are filled by the database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewPmForwardingReply {
+    pub bot_id: i64,
+    pub target_chat_id: i64,
+    pub target_message_id: i32,
+    pub telegram_chat_id: i64,
+    pub telegram_message_id: i32,
+}
+
+pub trait PmForwardingReplyRepository: Debug + Send + Sync {
+    /// Insert a reply mapping, fails if the reply is already mapped
+    fn create(
+        &self,
+        reply: NewPmForwardingReply,
+    ) -> BoxFuture<'_, Result<pm_forwarding_reply::Model, DbErr>>;
+
+    /// Find the copy of a reply in the target chat of the bot
+    fn find_by_target_message_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        target_message_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_reply::Model>, DbErr>>;
+
+    /// Delete a reply mapping, returns whether it existed
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>>;
+
+    /// Delete at most `limit` mappings of the bot created before `before`, returns the count deleted
+    fn delete_created_before(
+        &self,
+        bot_id: i64,
+        before: DateTime<Utc>,
+        limit: u64,
+    ) -> BoxFuture<'_, Result<u64, DbErr>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SeaOrmPmForwardingReplyRepository {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmPmForwardingReplyRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+impl PmForwardingReplyRepository for SeaOrmPmForwardingReplyRepository {
+    fn create(
+        &self,
+        reply: NewPmForwardingReply,
+    ) -> BoxFuture<'_, Result<pm_forwarding_reply::Model, DbErr>> {
+        // set by us rather than the column default, sqlite stores the default in another format
+        let now = Utc::now();
+        pm_forwarding_reply::ActiveModel {
+            created_at: ActiveValue::Set(now),
+            updated_at: ActiveValue::Set(now),
+            bot_id: ActiveValue::Set(reply.bot_id),
+            target_chat_id: ActiveValue::Set(reply.target_chat_id),
+            target_message_id: ActiveValue::Set(reply.target_message_id),
+            telegram_chat_id: ActiveValue::Set(reply.telegram_chat_id),
+            telegram_message_id: ActiveValue::Set(reply.telegram_message_id),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .boxed()
+    }
+
+    fn find_by_target_message_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        target_message_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_reply::Model>, DbErr>> {
+        pm_forwarding_reply::Entity::find()
+            .filter(pm_forwarding_reply::Column::BotId.eq(bot_id))
+            .filter(pm_forwarding_reply::Column::TargetChatId.eq(target_chat_id))
+            .filter(pm_forwarding_reply::Column::TargetMessageId.eq(target_message_id))
+            .one(&self.db)
+            .boxed()
+    }
+
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        async move {
+            let result = pm_forwarding_reply::Entity::delete_by_id(id)
+                .exec(&self.db)
+                .await?;
+
+            Ok(result.rows_affected > 0)
+        }
+        .boxed()
+    }
+
+    fn delete_created_before(
+        &self,
+        bot_id: i64,
+        before: DateTime<Utc>,
+        limit: u64,
+    ) -> BoxFuture<'_, Result<u64, DbErr>> {
+        async move {
+            // delete by primary key, so a single statement never locks the whole table
+            let ids: Vec<i64> = pm_forwarding_reply::Entity::find()
+                .select_only()
+                .column(pm_forwarding_reply::Column::Id)
+                .filter(pm_forwarding_reply::Column::BotId.eq(bot_id))
+                .filter(pm_forwarding_reply::Column::CreatedAt.lt(before))
+                .limit(limit)
+                .into_tuple()
+                .all(&self.db)
+                .await?;
+
+            if ids.is_empty() {
+                return Ok(0);
+            }
+
+            let result = pm_forwarding_reply::Entity::delete_many()
+                .filter(pm_forwarding_reply::Column::Id.is_in(ids))
+                .exec(&self.db)
+                .await?;
+
+            Ok(result.rows_affected)
+        }
+        .boxed()
+    }
+}
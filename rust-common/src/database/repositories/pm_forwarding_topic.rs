@@ -0,0 +1,116 @@
+use std::fmt::Debug;
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::prelude::*;
+use sea_orm::ActiveValue;
+
+use crate::database::entities::pm_forwarding_topic;
+
+/// Fields of a topic mapping to insert, the rest are filled by the database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewPmForwardingTopic {
+    pub bot_id: i64,
+    pub target_chat_id: i64,
+    pub message_thread_id: i32,
+    pub telegram_chat_id: i64,
+}
+
+pub trait PmForwardingTopicRepository: Debug + Send + Sync {
+    /// Insert a topic mapping, fails if the sender or the topic is already mapped
+    fn create(
+        &self,
+        topic: NewPmForwardingTopic,
+    ) -> BoxFuture<'_, Result<pm_forwarding_topic::Model, DbErr>>;
+
+    /// Find the topic of a sender in the target chat of the bot
+    fn find_by_telegram_chat_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        telegram_chat_id: i64,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_topic::Model>, DbErr>>;
+
+    /// Find the sender of a topic in the target chat of the bot
+    fn find_by_message_thread_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        message_thread_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_topic::Model>, DbErr>>;
+
+    /// Delete a topic mapping, returns whether it existed
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SeaOrmPmForwardingTopicRepository {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmPmForwardingTopicRepository {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+impl PmForwardingTopicRepository for SeaOrmPmForwardingTopicRepository {
+    fn create(
+        &self,
+        topic: NewPmForwardingTopic,
+    ) -> BoxFuture<'_, Result<pm_forwarding_topic::Model, DbErr>> {
+        // set by us rather than the column default, sqlite stores the default in another format
+        let now = Utc::now();
+        pm_forwarding_topic::ActiveModel {
+            created_at: ActiveValue::Set(now),
+            updated_at: ActiveValue::Set(now),
+            bot_id: ActiveValue::Set(topic.bot_id),
+            target_chat_id: ActiveValue::Set(topic.target_chat_id),
+            message_thread_id: ActiveValue::Set(topic.message_thread_id),
+            telegram_chat_id: ActiveValue::Set(topic.telegram_chat_id),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .boxed()
+    }
+
+    fn find_by_telegram_chat_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        telegram_chat_id: i64,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_topic::Model>, DbErr>> {
+        pm_forwarding_topic::Entity::find()
+            .filter(pm_forwarding_topic::Column::BotId.eq(bot_id))
+            .filter(pm_forwarding_topic::Column::TargetChatId.eq(target_chat_id))
+            .filter(pm_forwarding_topic::Column::TelegramChatId.eq(telegram_chat_id))
+            .one(&self.db)
+            .boxed()
+    }
+
+    fn find_by_message_thread_id(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        message_thread_id: i32,
+    ) -> BoxFuture<'_, Result<Option<pm_forwarding_topic::Model>, DbErr>> {
+        pm_forwarding_topic::Entity::find()
+            .filter(pm_forwarding_topic::Column::BotId.eq(bot_id))
+            .filter(pm_forwarding_topic::Column::TargetChatId.eq(target_chat_id))
+            .filter(pm_forwarding_topic::Column::MessageThreadId.eq(message_thread_id))
+            .one(&self.db)
+            .boxed()
+    }
+
+    fn delete(&self, id: i64) -> BoxFuture<'_, Result<bool, DbErr>> {
+        async move {
+            let result = pm_forwarding_topic::Entity::delete_by_id(id)
+                .exec(&self.db)
+                .await?;
+
+            Ok(result.rows_affected > 0)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sea_orm::DatabaseConnection;
+
+use crate::health::HealthCheck;
+
+/// Check the redis server answers `PING`
+pub struct RedisHealthCheck {
+    client: redis::Client,
+}
+
+impl RedisHealthCheck {
+    pub fn new(client: redis::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl HealthCheck for RedisHealthCheck {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let mut conn = self.client.get_multiplexed_tokio_connection().await?;
+            redis::cmd("PING")
+                .query_async::<_, String>(&mut conn)
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Check the database connection pool can reach the server
+pub struct DatabaseHealthCheck {
+    db: DatabaseConnection,
+}
+
+impl DatabaseHealthCheck {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+}
+
+impl HealthCheck for DatabaseHealthCheck {
+    fn name(&self) -> &str {
+        "database"
+    }
+
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            self.db.ping().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Check the amqp channel is connected and the update consumer is still active
+pub struct AmqpHealthCheck {
+    channel: lapin::Channel,
+    consumer: lapin::Consumer,
+}
+
+impl AmqpHealthCheck {
+    pub fn new(channel: lapin::Channel, consumer: lapin::Consumer) -> Self {
+        Self { channel, consumer }
+    }
+}
+
+impl HealthCheck for AmqpHealthCheck {
+    fn name(&self) -> &str {
+        "amqp"
+    }
+
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            if !self.channel.status().connected() {
+                return Err(anyhow::anyhow!(
+                    "Channel is not connected: {:?}",
+                    self.channel.status().state()
+                ));
+            }
+
+            let state = self.consumer.state();
+            if state != lapin::ConsumerState::Active
+                && state != lapin::ConsumerState::ActiveWithDelegate
+            {
+                return Err(anyhow::anyhow!("Consumer is not active: {:?}", state));
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Liveness flag of the update dispatcher, flipped by the component around `dispatch`
+#[derive(Clone, Debug)]
+pub struct DispatcherLiveness {
+    name: &'static str,
+    running: Arc<AtomicBool>,
+}
+
+impl Default for DispatcherLiveness {
+    fn default() -> Self {
+        Self::named("dispatcher")
+    }
+}
+
+impl DispatcherLiveness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Liveness flag of another long running task, such as a pool of workers
+    pub fn named(name: &'static str) -> Self {
+        Self {
+            name,
+            running: Arc::default(),
+        }
+    }
+
+    pub fn mark_running(&self) {
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    pub fn mark_stopped(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+impl HealthCheck for DispatcherLiveness {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        let running = self.is_running();
+        async move {
+            if running {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("{} is not running", self.name))
+            }
+        }
+        .boxed()
+    }
+}
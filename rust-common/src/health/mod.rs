@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{join_all, BoxFuture};
+use serde::Serialize;
+
+pub mod checks;
+pub mod server;
+
+/// Probe a health check takes part in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// The process itself is alive and making progress, failing it restarts the pod.
+    Liveness,
+    /// The process can serve traffic, failing it only removes it from load balancing.
+    Readiness,
+}
+
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn check(&self) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+#[derive(Clone, Default)]
+pub struct HealthRegistry {
+    checks: Vec<(Probe, Arc<dyn HealthCheck>)>,
+    timeout: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CheckReport {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+static DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout applied to every single check
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Register a check for the given probe
+    pub fn register<C: HealthCheck + 'static>(mut self, probe: Probe, check: C) -> Self {
+        self.checks.push((probe, Arc::new(check)));
+        self
+    }
+
+    ///
+    /// Run the registered checks concurrently
+    ///
+    /// # Arguments
+    ///
+    /// * `probe`: only run checks of this probe, `None` runs every check
+    ///
+    /// returns: `HealthReport` aggregated report, `Ok` only if every check passed
+    ///
+    pub async fn run(&self, probe: Option<Probe>) -> HealthReport {
+        let timeout = self.timeout.unwrap_or(DEFAULT_CHECK_TIMEOUT);
+
+        let results = join_all(
+            self.checks
+                .iter()
+                .filter(|(p, _)| probe.is_none() || probe == Some(*p))
+                .map(|(_, check)| async move {
+                    let result = match tokio::time::timeout(timeout, check.check()).await {
+                        Ok(result) => result,
+                        Err(_) => Err(anyhow::anyhow!("Check timed out after {:?}", timeout)),
+                    };
+
+                    (check.name().to_string(), result)
+                }),
+        )
+        .await;
+
+        let mut status = HealthStatus::Ok;
+        let mut checks = BTreeMap::new();
+        for (name, result) in results {
+            let report = match result {
+                Ok(_) => CheckReport {
+                    status: HealthStatus::Ok,
+                    error: None,
+                },
+                Err(err) => {
+                    log::warn!("Health check {} failed: {}", name, err);
+                    status = HealthStatus::Error;
+                    CheckReport {
+                        status: HealthStatus::Error,
+                        error: Some(err.to_string()),
+                    }
+                }
+            };
+
+            checks.insert(name, report);
+        }
+
+        HealthReport { status, checks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    struct StaticCheck(&'static str, bool);
+
+    impl HealthCheck for StaticCheck {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+            let healthy = self.1;
+            async move {
+                if healthy {
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!("unhealthy"))
+                }
+            }
+            .boxed()
+        }
+    }
+
+    struct PendingCheck;
+
+    impl HealthCheck for PendingCheck {
+        fn name(&self) -> &str {
+            "pending"
+        }
+
+        fn check(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+            futures::future::pending().boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_registry() {
+        let registry = HealthRegistry::new()
+            .register(Probe::Liveness, StaticCheck("dispatcher", true))
+            .register(Probe::Readiness, StaticCheck("redis", true))
+            .register(Probe::Readiness, StaticCheck("database", false));
+
+        let report = registry.run(Some(Probe::Liveness)).await;
+        assert_eq!(report.status, HealthStatus::Ok);
+        assert_eq!(report.checks.len(), 1);
+
+        let report = registry.run(Some(Probe::Readiness)).await;
+        assert_eq!(report.status, HealthStatus::Error);
+        assert_eq!(report.checks["redis"].status, HealthStatus::Ok);
+        assert_eq!(
+            report.checks["database"].error.as_deref(),
+            Some("unhealthy")
+        );
+
+        let report = registry.run(None).await;
+        assert_eq!(report.status, HealthStatus::Error);
+        assert_eq!(report.checks.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_health_registry_timeout() {
+        let registry = HealthRegistry::new()
+            .with_timeout(Duration::from_millis(10))
+            .register(Probe::Readiness, PendingCheck);
+
+        let report = registry.run(None).await;
+        assert_eq!(report.status, HealthStatus::Error);
+        assert_eq!(report.checks["pending"].status, HealthStatus::Error);
+    }
+}
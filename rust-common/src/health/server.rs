@@ -0,0 +1,73 @@
+use actix_web::dev::Server;
+use actix_web::{web, App, HttpResponse, HttpServer};
+
+use crate::duration::parse_go_duration;
+use crate::health::{HealthRegistry, HealthStatus, Probe};
+use crate::settings::Settings;
+
+static DEFAULT_LISTEN: &str = "0.0.0.0:8090";
+
+async fn respond(registry: &HealthRegistry, probe: Option<Probe>) -> HttpResponse {
+    let report = registry.run(probe).await;
+
+    match report.status {
+        HealthStatus::Ok => HttpResponse::Ok().json(report),
+        HealthStatus::Error => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+async fn healthz(registry: web::Data<HealthRegistry>) -> HttpResponse {
+    respond(&registry, None).await
+}
+
+async fn readyz(registry: web::Data<HealthRegistry>) -> HttpResponse {
+    respond(&registry, Some(Probe::Readiness)).await
+}
+
+async fn livez(registry: web::Data<HealthRegistry>) -> HttpResponse {
+    respond(&registry, Some(Probe::Liveness)).await
+}
+
+///
+/// Create the health server serving `/healthz`, `/readyz` and `/livez`
+///
+/// # Arguments
+///
+/// * `settings`: application settings, `health.listen` defaults to `0.0.0.0:8090`
+/// * `registry`: registered health checks
+///
+/// returns: `Result<Server, Error>` the server, it must be awaited to run. It ignores signals,
+/// stop it through its handle once the application shuts down
+///
+pub fn new_health_server(settings: &Settings, registry: HealthRegistry) -> std::io::Result<Server> {
+    let health_settings = settings.health.clone().unwrap_or_default();
+
+    let registry = match health_settings
+        .timeout
+        .as_ref()
+        .and_then(|timeout| parse_go_duration(timeout).ok())
+    {
+        Some(timeout) => registry.with_timeout(timeout),
+        None => registry,
+    };
+
+    let listen = health_settings
+        .listen
+        .unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+    log::info!("Health server listening on {}", listen);
+
+    let registry = web::Data::new(registry);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(registry.clone())
+            .route("/healthz", web::get().to(healthz))
+            .route("/readyz", web::get().to(readyz))
+            .route("/livez", web::get().to(livez))
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(listen)?
+    .run();
+
+    Ok(server)
+}
@@ -1,6 +1,8 @@
 pub mod bot;
+pub mod cryptor;
 pub mod database;
 pub mod duration;
+pub mod health;
 pub mod mq;
 pub mod observability;
 pub mod redis;
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use redis::aio::MultiplexedConnection;
+use uuid::Uuid;
+
+/// Delete the key only if it still holds our token, so an expired lock taken over by another
+/// holder is never released by us
+static RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+else
+    return 0
+end
+"#;
+
+///
+/// Lock shared across replicas, held until released or the ttl expires
+///
+/// # Examples
+///
+/// ```ignore
+/// let lock = RedisLock::new(conn, format!("{}-lock-{}", service_name, job), ttl);
+/// if lock.try_acquire().await? {
+///     run_job().await;
+///     lock.release().await?;
+/// }
+/// ```
+pub struct RedisLock {
+    conn: MultiplexedConnection,
+    key: String,
+    token: String,
+    ttl: Duration,
+}
+
+impl RedisLock {
+    pub fn new(conn: MultiplexedConnection, key: String, ttl: Duration) -> Self {
+        Self {
+            conn,
+            key,
+            token: Uuid::new_v4().to_string(),
+            ttl,
+        }
+    }
+
+    /// Try to take the lock without waiting, returns whether it was taken
+    pub async fn try_acquire(&mut self) -> redis::RedisResult<bool> {
+        let result: Option<String> = redis::cmd("SET")
+            .arg(&self.key)
+            .arg(&self.token)
+            .arg("NX")
+            .arg("PX")
+            .arg(self.ttl.as_millis() as u64)
+            .query_async(&mut self.conn)
+            .await?;
+
+        Ok(result.is_some())
+    }
+
+    /// Release the lock, returns whether it was still held
+    pub async fn release(&mut self) -> redis::RedisResult<bool> {
+        let deleted: i64 = redis::Script::new(RELEASE_SCRIPT)
+            .key(&self.key)
+            .arg(&self.token)
+            .invoke_async(&mut self.conn)
+            .await?;
+
+        Ok(deleted == 1)
+    }
+}
@@ -1,2 +1,3 @@
 pub mod client;
+pub mod lock;
 pub(crate) mod utils;
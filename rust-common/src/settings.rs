@@ -1,4 +1,5 @@
 use std::env;
+use std::fmt::{Debug, Formatter};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -19,6 +20,9 @@ pub struct Settings {
     pub database: Option<Database>,
     pub redis: Option<Redis>,
     pub mq: Option<Mq>,
+    pub health: Option<Health>,
+    pub pm_forwarding: Option<PmForwarding>,
+    pub encryption: Option<Encryption>,
 }
 
 impl Settings {
@@ -54,9 +58,10 @@ impl Settings {
     }
 }
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum DatabaseType {
+    #[default]
     #[serde(rename = "postgres")]
     Postgres,
     #[serde(rename = "mysql")]
@@ -80,6 +85,19 @@ pub enum DatabaseSSLMode {
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseSqliteMode {
+    #[serde(rename = "ro")]
+    ReadOnly,
+    #[serde(rename = "rw")]
+    ReadWrite,
+    #[serde(rename = "rwc")]
+    ReadWriteCreate,
+    #[serde(rename = "memory")]
+    Memory,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct Database {
     #[serde(rename = "type")]
     pub database_type: DatabaseType,
@@ -91,9 +109,63 @@ pub struct Database {
     pub charset: Option<String>,
     #[serde(rename = "sslmode")]
     pub ssl_mode: Option<DatabaseSSLMode>,
+    /// path of the CA certificate used to verify the server
+    #[serde(rename = "sslrootcert")]
+    pub ssl_root_cert: Option<String>,
+    /// path of the client certificate
+    #[serde(rename = "sslcert")]
+    pub ssl_cert: Option<String>,
+    /// path of the client certificate key
+    #[serde(rename = "sslkey")]
+    pub ssl_key: Option<String>,
+    /// sqlite only, the open mode of the database file
+    pub mode: Option<DatabaseSqliteMode>,
+    /// not supported, a prefix set is ignored with a warning at startup, see `schema`
+    pub table_prefix: Option<String>,
+    pub pool: Option<DatabasePool>,
+    pub statement_log_level: Option<String>,
+    /// postgres only, the `search_path` of every connection
+    pub schema: Option<String>,
+    /// apply pending migrations when a component connects
+    pub auto_migrate: Option<bool>,
 }
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+/// Passwords are redacted, settings end up in logs and traces
+impl Debug for Database {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Database")
+            .field("database_type", &self.database_type)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("name", &self.name)
+            .field("charset", &self.charset)
+            .field("ssl_mode", &self.ssl_mode)
+            .field("ssl_root_cert", &self.ssl_root_cert)
+            .field("ssl_cert", &self.ssl_cert)
+            .field("ssl_key", &self.ssl_key)
+            .field("mode", &self.mode)
+            .field("table_prefix", &self.table_prefix)
+            .field("pool", &self.pool)
+            .field("statement_log_level", &self.statement_log_level)
+            .field("schema", &self.schema)
+            .field("auto_migrate", &self.auto_migrate)
+            .finish()
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default, Debug)]
+pub struct DatabasePool {
+    pub max_connections: Option<u32>,
+    pub min_connections: Option<u32>,
+    pub connect_timeout: Option<String>,
+    pub idle_timeout: Option<String>,
+    pub acquire_timeout: Option<String>,
+    pub max_lifetime: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Mq {
     pub host: Option<String>,
     pub port: Option<u16>,
@@ -102,6 +174,18 @@ pub struct Mq {
     pub vhost: Option<String>,
 }
 
+impl Debug for Mq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mq")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("vhost", &self.vhost)
+            .finish()
+    }
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Observability {
     pub trace: Option<Trace>,
@@ -161,7 +245,7 @@ pub enum RedisMode {
     Cluster,
 }
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Redis {
     pub mode: Option<RedisMode>,
     pub host: Option<String>,
@@ -171,6 +255,19 @@ pub struct Redis {
     pub db: Option<u8>,
 }
 
+impl Debug for Redis {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redis")
+            .field("mode", &self.mode)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("db", &self.db)
+            .finish()
+    }
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Server {
     pub network: Option<String>,
@@ -178,14 +275,82 @@ pub struct Server {
     pub port: Option<u16>,
 }
 
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default, Debug)]
+pub struct Health {
+    pub listen: Option<String>,
+    pub timeout: Option<String>,
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Encryption {
+    /// key new values are encrypted with
+    pub primary_key_id: String,
+    /// every key still used by stored values
+    pub keys: Vec<EncryptionKey>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncryptionKey {
+    pub id: String,
+    /// base64 of 32 random bytes
+    pub key: String,
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default, Debug)]
+pub struct PmForwarding {
+    /// url the bot api reaches this handler at, child bot webhooks are set under it
+    pub public_base_url: Option<String>,
+    /// updates processed concurrently by this replica
+    pub workers: Option<usize>,
+    pub retention: Option<Retention>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Default, Debug)]
+pub struct Retention {
+    /// days to keep message mappings of bots without their own policy, unset keeps them forever
+    pub days: Option<u32>,
+    /// interval between pruning runs
+    pub interval: Option<String>,
+    /// rows deleted per statement
+    pub batch_size: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct TelegramBot {
     pub token: String,
     pub api_url: Option<String>,
     pub webhook: Option<Webhook>,
+    pub error_report: Option<ErrorReport>,
+}
+
+impl Debug for TelegramBot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TelegramBot")
+            .field("token", &"<redacted>")
+            .field("api_url", &self.api_url)
+            .field("webhook", &self.webhook)
+            .field("error_report", &self.error_report)
+            .finish()
+    }
 }
 
 #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ErrorReport {
+    pub chat_id: i64,
+    pub dedup_window: Option<String>,
+    pub rate_limit: Option<usize>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Webhook {
     pub url: Option<String>,
     pub max_connections: Option<i64>,
@@ -195,6 +360,24 @@ pub struct Webhook {
     pub secret_token: Option<String>,
 }
 
+impl Debug for Webhook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhook")
+            .field("url", &self.url)
+            .field("max_connections", &self.max_connections)
+            .field("ip_address", &self.ip_address)
+            .field("allowed_updates", &self.allowed_updates)
+            .field("drop_pending_updates", &self.drop_pending_updates)
+            .field("secret_token", &redacted(&self.secret_token))
+            .finish()
+    }
+}
+
+/// Set secrets show up as redacted, unset ones as `None`
+fn redacted(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| "<redacted>")
+}
+
 #[cfg(test)]
 mod tests {
     #[allow(unused_imports)]
@@ -293,4 +476,50 @@ mod tests {
             assert_eq!(settings.version, "0.0.1");
         }
     }
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let settings = Settings::new(
+            r#"
+                namespace: "pegasus-bot"
+                version: "0.0.1"
+                debug: false
+                telegram_bot:
+                  token: "123456:bot-token"
+                  webhook:
+                    secret_token: "webhook-secret"
+                database:
+                  type: postgres
+                  host: localhost
+                  port: 5432
+                  password: "database-password"
+                redis:
+                  password: "redis-password"
+                mq:
+                  password: "mq-password"
+                encryption:
+                  primary_key_id: "k1"
+                  keys:
+                    - id: "k1"
+                      key: "encryption-key"
+            "#,
+        );
+        assert_eq!(
+            settings.database.as_ref().unwrap().password.as_deref(),
+            Some("database-password")
+        );
+
+        let debug = format!("{:?}", settings);
+        for secret in [
+            "bot-token",
+            "webhook-secret",
+            "database-password",
+            "redis-password",
+            "mq-password",
+            "encryption-key",
+        ] {
+            assert!(!debug.contains(secret), "{} leaked", secret);
+        }
+        assert!(debug.contains("host: \"localhost\""));
+    }
 }
@@ -4,11 +4,10 @@ use fast_qr::convert::Builder;
 use fastping_rs::Pinger;
 use fastping_rs::PingResult::{Idle, Receive};
 use moka::future::Cache;
-use opentelemetry::{global, KeyValue};
-use opentelemetry::trace::{SpanKind, TraceContextExt, Tracer};
 use teloxide::prelude::*;
 use teloxide::types::InputFile;
 use teloxide::utils::command::BotCommands;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::utils::parse_target;
 
@@ -44,24 +43,13 @@ macro_rules! match_error {
 
 pub(crate) async fn qrcode_handler(
     bot: Bot,
-    update: Update,
     message: Message,
     text: String,
     cache: Cache<String, Vec<u8>>,
 ) -> anyhow::Result<()> {
-    let tracer = global::tracer("pegasus/rust-components/network-functions-handler/handlers");
-    let parent_cx = update.cx.unwrap_or_default();
-    let span = tracer
-        .span_builder("qrcode_handler")
-        .with_kind(SpanKind::Internal)
-        .start_with_context(&tracer, &parent_cx);
-    let cx = parent_cx.with_span(span);
-
     if text.is_empty() {
-        let err = anyhow::anyhow!("Text is empty");
         send_error_message!(bot, message, "Text is empty");
-        cx.span().record_error(err.as_ref());
-        return Err(err);
+        return Err(anyhow::anyhow!("Text is empty"));
     }
 
     // Check if QRCode already exists in cache
@@ -76,7 +64,7 @@ pub(crate) async fn qrcode_handler(
         .send()
         .await?;
 
-        cx.span().set_attribute(KeyValue::new("cache_hit", true));
+        tracing::Span::current().set_attribute("cache_hit", true);
 
         return Ok(());
     }
@@ -114,29 +102,14 @@ pub(crate) async fn qrcode_handler(
     Ok(())
 }
 
-pub(crate) async fn ping_handler(
-    bot: Bot,
-    update: Update,
-    message: Message,
-    target: String,
-) -> anyhow::Result<()> {
-    let tracer = global::tracer("pegasus/rust-components/network-functions-handler/handlers");
-    let parent_tx = update.cx.unwrap_or_default();
-    let span = tracer
-        .span_builder("ping_handler")
-        .with_kind(SpanKind::Internal)
-        .start_with_context(&tracer, &parent_tx);
-    let cx = parent_tx.with_span(span);
-
+pub(crate) async fn ping_handler(bot: Bot, message: Message, target: String) -> anyhow::Result<()> {
     if target.is_empty() {
-        let err = anyhow::anyhow!("Target is empty");
         send_error_message!(bot, message, "Usage: /ping <target>");
-        cx.span().record_error(err.as_ref());
-        return Err(err);
+        return Err(anyhow::anyhow!("Target is empty"));
     }
 
     let target_ip = match_error!(
-        parse_target(cx.clone(), &target).await,
+        parse_target(tracing::Span::current().context(), &target).await,
         bot,
         message,
         "Failed to parse target: {}"
@@ -144,18 +117,14 @@ pub(crate) async fn ping_handler(
     
     // do not ping loopback address
     if target_ip.is_loopback() {
-        let err = anyhow::anyhow!("Target is loopback address");
         send_error_message!(bot, message, "Target is loopback address");
-        cx.span().record_error(err.as_ref());
-        return Err(err);
+        return Err(anyhow::anyhow!("Target is loopback address"));
     }
     
     // do not ping unspecified address
     if target_ip.is_unspecified() {
-        let err = anyhow::anyhow!("Target is unspecified address");
         send_error_message!(bot, message, "Target is unspecified address");
-        cx.span().record_error(err.as_ref());
-        return Err(err);
+        return Err(anyhow::anyhow!("Target is unspecified address"));
     }
 
     let (pinger, results) = match_error!(
@@ -173,9 +142,7 @@ pub(crate) async fn ping_handler(
             Idle { addr } => {
                 let err = format!("Failed to ping target: {}", addr);
                 send_error_message!(bot, message, &err);
-                cx.span()
-                    .record_error(anyhow::anyhow!("Failed to ping target: {}", addr).as_ref());
-                return Err(anyhow::anyhow!("Failed to ping target: {}", err));
+                return Err(anyhow::anyhow!(err));
             }
             Receive { addr, rtt } => {
                 bot.send_message(
@@ -190,8 +157,6 @@ pub(crate) async fn ping_handler(
         Err(e) => {
             let err = format!("Failed to receive result: {}", e);
             send_error_message!(bot, message, err.clone());
-            cx.span()
-                .record_error(anyhow::anyhow!(err.clone()).as_ref());
             return Err(anyhow::anyhow!(err));
         }
     }
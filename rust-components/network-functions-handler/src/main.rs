@@ -3,7 +3,11 @@ use std::env;
 use opentelemetry::global;
 
 use pegasus_common::bot::channel::MqUpdateListener;
+use pegasus_common::bot::error::new_error_handler;
 use pegasus_common::bot::new_bot;
+use pegasus_common::health::checks::DispatcherLiveness;
+use pegasus_common::health::server::new_health_server;
+use pegasus_common::health::{HealthRegistry, Probe};
 use pegasus_common::mq::connection::new_amqp_connection;
 use pegasus_common::{observability, settings};
 
@@ -27,9 +31,25 @@ async fn main() -> anyhow::Result<()> {
     let bot = new_bot(settings.telegram_bot.as_ref().unwrap());
     let listener = MqUpdateListener::new(service_name, amqp_conn, settings).await?;
 
+    let liveness = DispatcherLiveness::new();
+    let health_server = new_health_server(
+        settings,
+        HealthRegistry::new()
+            .register(Probe::Readiness, listener.health_check())
+            .register(Probe::Liveness, liveness.clone()),
+    )?;
+
     log::info!("Application started");
 
-    run(bot, listener).await;
+    let error_handler = new_error_handler(bot.clone(), service_name, settings);
+    let health_handle = health_server.handle();
+    let run_bot = async move {
+        run(bot, listener, error_handler, liveness).await;
+        // the health server ignores signals, it stops with the dispatcher
+        health_handle.stop(true).await;
+    };
+    let (_, r) = tokio::join!(run_bot, health_server);
+    r?;
 
     log::info!("Shutting down tracer provider");
     global::shutdown_tracer_provider();
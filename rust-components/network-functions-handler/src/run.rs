@@ -1,13 +1,22 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use moka::future::Cache;
+use teloxide::error_handlers::ErrorHandler;
 use teloxide::prelude::*;
 use teloxide::update_listeners::UpdateListener;
 
+use pegasus_common::bot::instrument::instrument;
+use pegasus_common::health::checks::DispatcherLiveness;
+
 use crate::handlers::{BotCommand, ping_handler, qrcode_handler};
 
-pub(crate) async fn run<'a, B, UListener>(bot: B, listener: UListener)
-where
+pub(crate) async fn run<'a, B, UListener>(
+    bot: B,
+    listener: UListener,
+    error_handler: Arc<dyn ErrorHandler<anyhow::Error> + Send + Sync>,
+    liveness: DispatcherLiveness,
+) where
     B: Requester + Clone + Send + Sync + 'static,
     UListener: UpdateListener + 'a,
     UListener::Err: Debug,
@@ -15,19 +24,32 @@ where
     let handler = dptree::entry().branch(
         Update::filter_message()
             .filter_command::<BotCommand>()
-            .branch(dptree::case![BotCommand::QRCode(string)].endpoint(qrcode_handler))
-            .branch(dptree::case![BotCommand::Ping(string)].endpoint(ping_handler)),
+            .branch(
+                dptree::case![BotCommand::QRCode(string)]
+                    .chain(instrument("qrcode_handler"))
+                    .endpoint(qrcode_handler),
+            )
+            .branch(
+                dptree::case![BotCommand::Ping(string)]
+                    .chain(instrument("ping_handler"))
+                    .endpoint(ping_handler),
+            ),
     );
 
     let cache: Cache<String, Vec<u8>> = Cache::new(1000);
 
+    liveness.mark_running();
+
     Dispatcher::builder(bot, handler)
         .dependencies(dptree::deps![cache])
         .distribution_function(|_| None::<std::convert::Infallible>)
+        .error_handler(error_handler)
         .build()
         .dispatch_with_listener(
             listener,
             LoggingErrorHandler::with_custom_text("An error from the update listener"),
         )
         .await;
+
+    liveness.mark_stopped();
 }
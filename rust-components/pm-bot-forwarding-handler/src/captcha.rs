@@ -0,0 +1,550 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use redis::aio::MultiplexedConnection;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use pegasus_common::duration::parse_go_duration;
+
+/// Prefix of the data of captcha buttons, the option follows
+pub static CAPTCHA_CALLBACK_PREFIX: &str = "captcha:";
+static MIN_CAPTCHA_TIMEOUT: Duration = Duration::from_secs(30);
+static MAX_CAPTCHA_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+/// Tapping at random passes `retries + 1` in every `options` captchas, a third at most
+static MAX_CAPTCHA_RETRIES: u32 = 2;
+/// Expired captchas are kept this long, so their wrong answers count against the next one
+static EXPIRED_CAPTCHA_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Emojis of the emoji captcha, with the names they are asked by
+static CAPTCHA_EMOJIS: [(&str, &str); 16] = [
+    ("🐶", "dog"),
+    ("🐱", "cat"),
+    ("🍎", "apple"),
+    ("🚗", "car"),
+    ("⚽", "ball"),
+    ("🌵", "cactus"),
+    ("🎸", "guitar"),
+    ("🚀", "rocket"),
+    ("🍕", "pizza"),
+    ("🌙", "moon"),
+    ("🐟", "fish"),
+    ("🌻", "sunflower"),
+    ("🔑", "key"),
+    ("🔔", "bell"),
+    ("🎈", "balloon"),
+    ("🍌", "banana"),
+];
+static EMOJI_OPTIONS: usize = 9;
+static MATH_OPTIONS: usize = 9;
+
+/// Challenge a bot sends first-time senders, stored by name on the bot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptchaKind {
+    /// sum of two numbers
+    Math,
+    /// emoji named in the question
+    Emoji,
+}
+
+impl CaptchaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CaptchaKind::Math => "math",
+            CaptchaKind::Emoji => "emoji",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "math" => Some(CaptchaKind::Math),
+            "emoji" => Some(CaptchaKind::Emoji),
+            _ => None,
+        }
+    }
+}
+
+/// Captcha of a bot as set by its owner, the timeout and retries kept as they are if not given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptchaSettings {
+    pub kind: CaptchaKind,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+///
+/// Parse the captcha settings sent by the owner, as `math`, `emoji 10m` or `math 5m 3`
+///
+/// # Arguments
+///
+/// * `text`: kind, then the time to solve a captcha and the new challenges after wrong answers
+///
+/// returns: `Result<Option<CaptchaSettings>, Error>` none for `off`
+///
+pub fn parse_captcha_settings(text: &str) -> anyhow::Result<Option<CaptchaSettings>> {
+    let mut args = text.split_whitespace();
+    let kind = match args.next() {
+        Some("off") => return Ok(None),
+        Some(name) => CaptchaKind::parse(name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown captcha {}, use math, emoji or off", name))?,
+        None => return Err(anyhow::anyhow!("Missing captcha, use math, emoji or off")),
+    };
+
+    let timeout = args
+        .next()
+        .map(|timeout| {
+            parse_go_duration(timeout)
+                .ok()
+                .filter(|timeout| (MIN_CAPTCHA_TIMEOUT..=MAX_CAPTCHA_TIMEOUT).contains(timeout))
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid captcha timeout {}, use 30s to 24h such as 5m",
+                        timeout
+                    )
+                })
+        })
+        .transpose()?;
+    let retries = args
+        .next()
+        .map(|retries| {
+            retries
+                .parse::<u32>()
+                .ok()
+                .filter(|retries| *retries <= MAX_CAPTCHA_RETRIES)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid captcha retries {}, use 0 to {}",
+                        retries,
+                        MAX_CAPTCHA_RETRIES
+                    )
+                })
+        })
+        .transpose()?;
+
+    if args.next().is_some() {
+        return Err(anyhow::anyhow!("Too many captcha settings"));
+    }
+
+    Ok(Some(CaptchaSettings {
+        kind,
+        timeout,
+        retries,
+    }))
+}
+
+/// Question of a captcha with its options, one button each
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Challenge {
+    pub question: String,
+    pub options: Vec<String>,
+    pub answer: String,
+}
+
+impl Challenge {
+    pub fn new(kind: CaptchaKind) -> Self {
+        let mut rng = rand::thread_rng();
+
+        let (question, answer, mut options) = match kind {
+            CaptchaKind::Math => {
+                let (a, b) = (rng.gen_range(1..=9), rng.gen_range(1..=9));
+                let answer = a + b;
+                let mut options = vec![answer];
+                while options.len() < MATH_OPTIONS {
+                    let option = rng.gen_range(2..=18);
+                    if !options.contains(&option) {
+                        options.push(option);
+                    }
+                }
+
+                (
+                    format!("What is {} + {}?", a, b),
+                    answer.to_string(),
+                    options.iter().map(i32::to_string).collect::<Vec<_>>(),
+                )
+            }
+            CaptchaKind::Emoji => {
+                let emojis = CAPTCHA_EMOJIS
+                    .choose_multiple(&mut rng, EMOJI_OPTIONS)
+                    .collect::<Vec<_>>();
+                let (answer, name) = emojis[0];
+
+                (
+                    format!("Tap the {}", name),
+                    answer.to_string(),
+                    emojis.iter().map(|(emoji, _)| emoji.to_string()).collect(),
+                )
+            }
+        };
+        options.shuffle(&mut rng);
+
+        Self {
+            question,
+            options,
+            answer,
+        }
+    }
+
+    pub fn keyboard(&self) -> InlineKeyboardMarkup {
+        InlineKeyboardMarkup::new(self.options.chunks(3).map(|row| {
+            row.iter()
+                .map(|option| {
+                    InlineKeyboardButton::callback(
+                        option.clone(),
+                        format!("{}{}", CAPTCHA_CALLBACK_PREFIX, option),
+                    )
+                })
+                .collect::<Vec<_>>()
+        }))
+    }
+}
+
+/// Verification of a sender of a bot, times are unix timestamps
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case", tag = "state", content = "data")]
+pub enum CaptchaState {
+    /// challenge sent, waiting for the answer
+    Pending {
+        message_id: i32,
+        answer: String,
+        /// wrong answers so far
+        attempts: u32,
+        expires_at: i64,
+    },
+    Verified,
+    /// out of retries, no new challenge until then
+    Failed {
+        until: i64,
+    },
+}
+
+impl CaptchaState {
+    /// How long to keep the state, none to keep it for good
+    fn ttl(&self, now: i64) -> Option<u64> {
+        match self {
+            CaptchaState::Pending { expires_at, .. } => {
+                Some((*expires_at - now).max(1) as u64 + EXPIRED_CAPTCHA_TTL.as_secs())
+            }
+            CaptchaState::Failed { until } => Some((*until - now).max(1) as u64),
+            CaptchaState::Verified => None,
+        }
+    }
+}
+
+/// What an answer to a captcha does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptchaAnswer {
+    Verified,
+    /// a new challenge is due, `attempts` counts this answer
+    Wrong {
+        attempts: u32,
+        expires_at: i64,
+    },
+    OutOfRetries,
+    /// the captcha timed out or was replaced, its buttons are stale
+    Expired,
+}
+
+///
+/// Check the answer of a sender to their captcha
+///
+/// # Arguments
+///
+/// * `state`: verification of the sender
+/// * `message_id`: message of the captcha the button belongs to
+/// * `answer`: option the sender chose
+/// * `now`: unix timestamp
+/// * `retries`: new challenges a sender gets after wrong answers
+///
+/// returns: `CaptchaAnswer`
+///
+pub fn check_answer(
+    state: Option<&CaptchaState>,
+    message_id: i32,
+    answer: &str,
+    now: i64,
+    retries: u32,
+) -> CaptchaAnswer {
+    match state {
+        Some(CaptchaState::Pending {
+            message_id: pending_id,
+            answer: expected,
+            attempts,
+            expires_at,
+        }) if *pending_id == message_id && *expires_at > now => {
+            if answer == expected {
+                CaptchaAnswer::Verified
+            } else if *attempts >= retries {
+                CaptchaAnswer::OutOfRetries
+            } else {
+                CaptchaAnswer::Wrong {
+                    attempts: attempts + 1,
+                    expires_at: *expires_at,
+                }
+            }
+        }
+        _ => CaptchaAnswer::Expired,
+    }
+}
+
+///
+/// Captcha verification of the senders of each bot, shared by the replicas
+///
+/// Stored as json per sender, the way [`pegasus_common::bot::state::RedisStorage`] stores
+/// dialogues, pending and failed captchas expire on their own.
+///
+pub trait CaptchaStore: Debug + Send + Sync {
+    fn get(&self, bot_id: i64, chat_id: i64)
+        -> BoxFuture<'_, anyhow::Result<Option<CaptchaState>>>;
+
+    fn set(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        state: CaptchaState,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+fn state_key(service_name: &str, bot_id: i64, chat_id: i64) -> String {
+    format!("{}-captcha-{}-{}", service_name, bot_id, chat_id)
+}
+
+#[derive(Clone)]
+pub struct RedisCaptchaStore {
+    service_name: &'static str,
+    conn: MultiplexedConnection,
+}
+
+impl Debug for RedisCaptchaStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisCaptchaStore")
+            .field("service_name", &self.service_name)
+            .finish()
+    }
+}
+
+impl RedisCaptchaStore {
+    pub fn new(service_name: &'static str, conn: MultiplexedConnection) -> Self {
+        Self { service_name, conn }
+    }
+}
+
+impl CaptchaStore for RedisCaptchaStore {
+    fn get(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Option<CaptchaState>>> {
+        let key = state_key(self.service_name, bot_id, chat_id);
+
+        async move {
+            let state: Option<Vec<u8>> = redis::cmd("GET")
+                .arg(&key)
+                .query_async(&mut self.conn.clone())
+                .await?;
+
+            Ok(state
+                .map(|state| serde_json::from_slice(&state))
+                .transpose()?)
+        }
+        .boxed()
+    }
+
+    fn set(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        state: CaptchaState,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let key = state_key(self.service_name, bot_id, chat_id);
+
+        async move {
+            let mut cmd = redis::cmd("SET");
+            cmd.arg(&key).arg(serde_json::to_vec(&state)?);
+            // a plain set drops the ttl of a pending captcha once verified
+            if let Some(ttl) = state.ttl(chrono::Utc::now().timestamp()) {
+                cmd.arg("EX").arg(ttl);
+            }
+            cmd.query_async::<_, ()>(&mut self.conn.clone()).await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// In-memory captcha store, states never expire
+    #[derive(Debug, Default)]
+    pub(crate) struct InMemoryCaptchaStore {
+        states: Mutex<HashMap<(i64, i64), CaptchaState>>,
+    }
+
+    impl CaptchaStore for InMemoryCaptchaStore {
+        fn get(
+            &self,
+            bot_id: i64,
+            chat_id: i64,
+        ) -> BoxFuture<'_, anyhow::Result<Option<CaptchaState>>> {
+            let state = self.states.lock().unwrap().get(&(bot_id, chat_id)).cloned();
+
+            futures::future::ok(state).boxed()
+        }
+
+        fn set(
+            &self,
+            bot_id: i64,
+            chat_id: i64,
+            state: CaptchaState,
+        ) -> BoxFuture<'_, anyhow::Result<()>> {
+            self.states.lock().unwrap().insert((bot_id, chat_id), state);
+
+            futures::future::ok(()).boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_captcha_settings() {
+        assert_eq!(parse_captcha_settings("off").unwrap(), None);
+        assert_eq!(
+            parse_captcha_settings("math").unwrap(),
+            Some(CaptchaSettings {
+                kind: CaptchaKind::Math,
+                timeout: None,
+                retries: None,
+            })
+        );
+        assert_eq!(
+            parse_captcha_settings(" emoji 10m 2 ").unwrap(),
+            Some(CaptchaSettings {
+                kind: CaptchaKind::Emoji,
+                timeout: Some(Duration::from_secs(600)),
+                retries: Some(2),
+            })
+        );
+
+        assert!(parse_captcha_settings("").is_err());
+        assert!(parse_captcha_settings("quiz").is_err());
+        assert!(parse_captcha_settings("math 5s").is_err());
+        assert!(parse_captcha_settings("math 48h").is_err());
+        assert!(parse_captcha_settings("math 5m 3").is_err());
+        assert!(parse_captcha_settings("math 5m 1 more").is_err());
+    }
+
+    #[test]
+    fn test_challenge() {
+        for kind in [CaptchaKind::Math, CaptchaKind::Emoji] {
+            let challenge = Challenge::new(kind);
+            assert!(challenge.options.contains(&challenge.answer));
+
+            let mut options = challenge.options.clone();
+            options.dedup();
+            options.sort();
+            options.dedup();
+            assert_eq!(options.len(), challenge.options.len());
+        }
+
+        let challenge = Challenge::new(CaptchaKind::Math);
+        let (a, b) = challenge
+            .question
+            .strip_prefix("What is ")
+            .and_then(|question| question.strip_suffix('?'))
+            .and_then(|question| question.split_once(" + "))
+            .unwrap();
+        assert_eq!(
+            a.parse::<i32>().unwrap() + b.parse::<i32>().unwrap(),
+            challenge.answer.parse::<i32>().unwrap()
+        );
+        assert_eq!(challenge.options.len(), MATH_OPTIONS);
+        assert_eq!(
+            Challenge::new(CaptchaKind::Emoji).options.len(),
+            EMOJI_OPTIONS
+        );
+
+        // callback data is limited to 64 bytes
+        let keyboard = Challenge::new(CaptchaKind::Emoji).keyboard();
+        assert_eq!(keyboard.inline_keyboard.len(), 3);
+        assert!(keyboard
+            .inline_keyboard
+            .iter()
+            .flatten()
+            .all(|button| { format!("{:?}", button.kind).contains(CAPTCHA_CALLBACK_PREFIX) }));
+    }
+
+    #[test]
+    fn test_check_answer() {
+        let pending = |attempts| CaptchaState::Pending {
+            message_id: 5,
+            answer: "7".to_string(),
+            attempts,
+            expires_at: 100,
+        };
+
+        assert_eq!(
+            check_answer(Some(&pending(0)), 5, "7", 50, 2),
+            CaptchaAnswer::Verified
+        );
+        assert_eq!(
+            check_answer(Some(&pending(1)), 5, "8", 50, 2),
+            CaptchaAnswer::Wrong {
+                attempts: 2,
+                expires_at: 100
+            }
+        );
+        assert_eq!(
+            check_answer(Some(&pending(2)), 5, "8", 50, 2),
+            CaptchaAnswer::OutOfRetries
+        );
+
+        // timed out, replaced or settled already
+        assert_eq!(
+            check_answer(Some(&pending(0)), 5, "7", 100, 2),
+            CaptchaAnswer::Expired
+        );
+        assert_eq!(
+            check_answer(Some(&pending(0)), 4, "7", 50, 2),
+            CaptchaAnswer::Expired
+        );
+        assert_eq!(
+            check_answer(Some(&CaptchaState::Verified), 5, "7", 50, 2),
+            CaptchaAnswer::Expired
+        );
+        assert_eq!(check_answer(None, 5, "7", 50, 2), CaptchaAnswer::Expired);
+    }
+
+    #[test]
+    fn test_captcha_state() {
+        let state = CaptchaState::Failed { until: 160 };
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(json, r#"{"state":"failed","data":{"until":160}}"#);
+        assert_eq!(serde_json::from_str::<CaptchaState>(&json).unwrap(), state);
+
+        assert_eq!(state.ttl(100), Some(60));
+        // kept past expiring for the wrong answers
+        let pending = CaptchaState::Pending {
+            message_id: 5,
+            answer: "7".to_string(),
+            attempts: 1,
+            expires_at: 160,
+        };
+        assert_eq!(pending.ttl(100), Some(60 + 24 * 60 * 60));
+        assert_eq!(CaptchaState::Verified.ttl(100), None);
+        assert_eq!(
+            state_key("pm-bot-forwarding-handler", 1, 42),
+            "pm-bot-forwarding-handler-captcha-1-42"
+        );
+    }
+}
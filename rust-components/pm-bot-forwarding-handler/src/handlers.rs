@@ -1,12 +1,14 @@
 use teloxide::dispatching::dialogue::GetChatId;
 use teloxide::prelude::*;
 use teloxide::types::InlineKeyboardMarkup;
-use tracing::span;
-use tracing_opentelemetry::OpenTelemetrySpanExt;
+use teloxide::utils::html;
 
 use pegasus_common::bot::state::RedisStorage;
 
+use crate::captcha::parse_captcha_settings;
+use crate::rate_limit::FLOOD_MUTE;
 use crate::services::forwarding_bot::{ForwardingBotService, IForwardingBotService};
+use crate::texts::{parse_text_input, BotText, DEFAULT_TEXT_COMMAND, NO_TEXT_COMMAND};
 
 #[derive(Clone, Default, serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case", tag = "state", content = "data")]
@@ -24,21 +26,72 @@ pub(crate) enum BotState {
     },
     ChooseBot,
     ChooseBotAction(i64),
+    DeleteConfirmation(i64),
+    EditReceiveTargetChat(i64),
+    EditReceiveBotToken(i64),
+    EditReceiveRateLimit(i64),
+    EditReceiveCaptcha(i64),
+    EditReceiveRetention(i64),
+    EditReceiveText {
+        bot_id: i64,
+        kind: BotText,
+    },
 }
 
 type BotDialog = Dialogue<BotState, RedisStorage>;
 
+/// Whether the text looks like a bot token from BotFather
+fn is_valid_bot_token(text: &str) -> bool {
+    let bot_token_reg = regex::Regex::new(r"^[0-9]+:[a-zA-Z0-9_-]+$").unwrap();
+    bot_token_reg.is_match(text)
+}
+
+/// Parse a chat id sent as text
+fn parse_chat_id(text: &str) -> Option<i64> {
+    let target_reg = regex::Regex::new(r"^-?[0-9]+$").unwrap();
+    if !target_reg.is_match(text) {
+        return None;
+    }
+
+    text.parse::<i64>().ok()
+}
+
+/// Parse message retention days sent as text, `default` restores the default retention
+fn parse_retention_days(text: &str) -> Option<Option<i32>> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("default") {
+        return Some(None);
+    }
+
+    text.parse::<i32>().ok().map(Some)
+}
+
+/// Bot chosen in the action menu, replies to the menu message if the dialogue is elsewhere
+async fn chosen_bot_id(
+    bot: &Bot,
+    parent_msg: &Message,
+    dialogue: &BotDialog,
+) -> anyhow::Result<i64> {
+    match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::ChooseBotAction(bot_id) => Ok(bot_id),
+        _ => {
+            bot.send_message(parent_msg.chat.id, "Unexpected dialogue state")
+                .reply_to_message_id(parent_msg.id)
+                .await?;
+            Err(anyhow::anyhow!("Unexpected dialogue state"))
+        }
+    }
+}
+
 pub async fn start_handler(
     bot: Bot,
-    update: Update,
     message: Message,
     bot_dialog: BotDialog,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "start_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     bot_dialog.reset().await.ok();
 
     bot.send_message(
@@ -62,15 +115,9 @@ You can manage your forwarding bots via these buttons.
 
 pub async fn create_process_handler(
     bot: Bot,
-    update: Update,
     callback_query: CallbackQuery,
     dialogue: BotDialog,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "create_process_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let message = callback_query
         .message
         .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
@@ -88,22 +135,15 @@ pub async fn create_process_handler(
 
 pub async fn receive_bot_token_handler(
     bot: Bot,
-    update: Update,
     message: Message,
     dialogue: BotDialog,
     forwarding_bot_service: ForwardingBotService,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "receive_bot_token_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let bot_token = message
         .text()
         .ok_or_else(|| anyhow::anyhow!("No text in message"))?;
 
-    let bot_token_reg = regex::Regex::new(r"^[0-9]+:[a-zA-Z0-9_-]+$").unwrap();
-    if !bot_token_reg.is_match(bot_token) {
+    if !is_valid_bot_token(bot_token) {
         bot.send_message(
             message.chat.id,
             "Invalid bot token, please send a valid bot token",
@@ -157,15 +197,9 @@ pub async fn receive_bot_token_handler(
 
 pub async fn receive_message_target_handler(
     bot: Bot,
-    update: Update,
     message: Message,
     dialogue: BotDialog,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "receive_message_target_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let state = dialogue
         .get()
         .await?
@@ -182,21 +216,19 @@ pub async fn receive_message_target_handler(
         .text()
         .ok_or_else(|| anyhow::anyhow!("No text in message"))?;
 
-    let target_reg = regex::Regex::new(r"^-?[0-9]+$").unwrap();
-    if !target_reg.is_match(target) {
-        bot.send_message(
-            message.chat.id,
-            "Invalid target chat id, please send a valid chat id",
-        )
-        .await
-        .map_err(|err| anyhow::anyhow!("Failed to send message: {}", err))?;
-
-        return Ok(());
-    }
+    let target = match parse_chat_id(target) {
+        Some(target) => target,
+        None => {
+            bot.send_message(
+                message.chat.id,
+                "Invalid target chat id, please send a valid chat id",
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to send message: {}", err))?;
 
-    let target = target
-        .parse::<i64>()
-        .map_err(|err| anyhow::anyhow!("Failed to parse target chat id: {}", err))?;
+            return Ok(());
+        }
+    };
 
     bot.send_message(
         message.chat.id,
@@ -206,7 +238,8 @@ pub async fn receive_message_target_handler(
         Bot token: <code>{}</code>
         Target chat id: <code>{}</code>
         "#,
-            bot_token, target
+            html::escape(&bot_token),
+            target
         ),
     )
     .parse_mode(teloxide::types::ParseMode::Html)
@@ -224,16 +257,11 @@ pub async fn receive_message_target_handler(
 }
 
 pub async fn cancel_handler(bot: Bot, update: Update, dialogue: BotDialog) -> anyhow::Result<()> {
-    let parent_cx = update.cx.clone().unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "cancel_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let chat_id = update
         .chat_id()
         .ok_or_else(|| anyhow::anyhow!("No chat id in update"))?;
 
-    bot.send_message(chat_id, "Bot creation cancelled.").await?;
+    bot.send_message(chat_id, "Cancelled.").await?;
 
     dialogue.reset().await?;
 
@@ -242,16 +270,10 @@ pub async fn cancel_handler(bot: Bot, update: Update, dialogue: BotDialog) -> an
 
 pub async fn receive_confirmation_handler(
     bot: Bot,
-    update: Update,
     callback_query: CallbackQuery,
     dialogue: BotDialog,
     forwarding_bot_service: ForwardingBotService,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "receive_confirmation_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let parent_msg = callback_query
         .message
         .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
@@ -304,16 +326,10 @@ pub async fn receive_confirmation_handler(
 
 pub async fn list_process_handler(
     bot: Bot,
-    update: Update,
     callback_query: CallbackQuery,
     dialogue: BotDialog,
     forwarding_bot_service: ForwardingBotService,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "list_process_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let message = callback_query
         .message
         .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
@@ -355,15 +371,9 @@ pub async fn list_process_handler(
 
 pub async fn choose_bot_handler(
     bot: Bot,
-    update: Update,
     callback_query: CallbackQuery,
     dialogue: BotDialog,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let app_root = span!(tracing::Level::INFO, "choose_bot_handler");
-    app_root.set_parent(parent_cx);
-    let _guard = app_root.enter();
-
     let message = callback_query
         .message
         .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
@@ -382,10 +392,39 @@ pub async fn choose_bot_handler(
         message.id,
         format!("Choose action for bot: {}", bot_id),
     )
-    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
-        teloxide::types::InlineKeyboardButton::callback("Reinitialize", "forward_bot_reinitialize"),
-        teloxide::types::InlineKeyboardButton::callback("Delete", "forward_bot_delete"),
-    ]]))
+    .reply_markup(InlineKeyboardMarkup::new(vec![
+        vec![
+            teloxide::types::InlineKeyboardButton::callback(
+                "Reinitialize",
+                "forward_bot_reinitialize",
+            ),
+            teloxide::types::InlineKeyboardButton::callback("Delete", "forward_bot_delete"),
+        ],
+        vec![
+            teloxide::types::InlineKeyboardButton::callback(
+                "Change target chat",
+                "forward_bot_edit_target",
+            ),
+            teloxide::types::InlineKeyboardButton::callback(
+                "Replace token",
+                "forward_bot_replace_token",
+            ),
+            teloxide::types::InlineKeyboardButton::callback(
+                "Rotate secret",
+                "forward_bot_rotate_secret",
+            ),
+        ],
+        vec![
+            teloxide::types::InlineKeyboardButton::callback(
+                "Toggle topics",
+                "forward_bot_toggle_topics",
+            ),
+            teloxide::types::InlineKeyboardButton::callback("Rate limit", "forward_bot_rate_limit"),
+            teloxide::types::InlineKeyboardButton::callback("Texts", "forward_bot_texts"),
+            teloxide::types::InlineKeyboardButton::callback("Captcha", "forward_bot_captcha"),
+            teloxide::types::InlineKeyboardButton::callback("Retention", "forward_bot_retention"),
+        ],
+    ]))
     .await?;
 
     // update dialogue state
@@ -396,16 +435,10 @@ pub async fn choose_bot_handler(
 
 pub async fn bot_reinitialize_handler(
     bot: Bot,
-    update: Update,
     callback_query: CallbackQuery,
     dialogue: BotDialog,
     forwarding_bot_service: ForwardingBotService,
 ) -> anyhow::Result<()> {
-    let parent_cx = update.cx.unwrap_or_default();
-    let span = span!(tracing::Level::INFO, "bot_reinitialize_handler");
-    span.set_parent(parent_cx);
-    let _guard = span.enter();
-
     let parent_msg = callback_query
         .message
         .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
@@ -452,3 +485,840 @@ pub async fn bot_reinitialize_handler(
 
     Ok(())
 }
+
+pub async fn bot_delete_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!(
+            "Delete bot {}? Its webhook is removed and its forwarded message history is lost.",
+            bot_id
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Confirm", "forward_bot_delete_confirm"),
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::DeleteConfirmation(bot_id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn bot_delete_confirmation_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::DeleteConfirmation(bot_id) => bot_id,
+        _ => {
+            bot.send_message(parent_msg.chat.id, "Unexpected dialogue state")
+                .reply_to_message_id(parent_msg.id)
+                .await?;
+            return Err(anyhow::anyhow!("Unexpected dialogue state"));
+        }
+    };
+
+    if let Err(err) = forwarding_bot_service
+        .delete_bot(bot_id, callback_query.from.id.0)
+        .await
+    {
+        bot.send_message(parent_msg.chat.id, format!("Failed to delete bot: {}", err))
+            .reply_to_message_id(parent_msg.id)
+            .await?;
+        dialogue.reset().await?;
+        return Err(err);
+    }
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!("Bot deleted successfully, id: {}", bot_id),
+    )
+    .await?;
+
+    dialogue.reset().await?;
+
+    Ok(())
+}
+
+pub async fn bot_edit_target_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!("Please, send me the new target chat id of bot {}", bot_id),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::EditReceiveTargetChat(bot_id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn receive_new_target_handler(
+    bot: Bot,
+    message: Message,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let bot_id = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::EditReceiveTargetChat(bot_id) => bot_id,
+        _ => return Err(anyhow::anyhow!("Unexpected dialogue state")),
+    };
+
+    let target = match message.text().and_then(parse_chat_id) {
+        Some(target) => target,
+        None => {
+            bot.send_message(
+                message.chat.id,
+                "Invalid target chat id, please send a valid chat id",
+            )
+            .await?;
+
+            return Ok(());
+        }
+    };
+
+    let user_id = message
+        .from()
+        .ok_or_else(|| anyhow::anyhow!("No sender in message"))?
+        .id
+        .0;
+
+    dialogue.reset().await?;
+
+    match forwarding_bot_service
+        .change_target_chat(bot_id, user_id, target)
+        .await
+    {
+        Ok((model, topics_disabled)) => {
+            let mut text = format!(
+                "Target chat of bot {} changed to {}",
+                model.id, model.target_chat_id
+            );
+            if topics_disabled {
+                text.push_str(
+                    "\n\nThe new target chat is not a forum supergroup, so topics were turned off",
+                );
+            }
+            bot.send_message(message.chat.id, text).await?;
+        }
+        Err(err) => {
+            bot.send_message(
+                message.chat.id,
+                format!("Failed to change target chat: {}", err),
+            )
+            .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn bot_replace_token_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!(
+            "Please, send me the new token of bot {}, as given by BotFather after revoking the old one",
+            bot_id
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::EditReceiveBotToken(bot_id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn receive_new_bot_token_handler(
+    bot: Bot,
+    message: Message,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let bot_id = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::EditReceiveBotToken(bot_id) => bot_id,
+        _ => return Err(anyhow::anyhow!("Unexpected dialogue state")),
+    };
+
+    let bot_token = match message.text().filter(|text| is_valid_bot_token(text)) {
+        Some(bot_token) => bot_token,
+        None => {
+            bot.send_message(
+                message.chat.id,
+                "Invalid bot token, please send a valid bot token",
+            )
+            .await?;
+
+            return Ok(());
+        }
+    };
+
+    let user_id = message
+        .from()
+        .ok_or_else(|| anyhow::anyhow!("No sender in message"))?
+        .id
+        .0;
+
+    dialogue.reset().await?;
+
+    match forwarding_bot_service
+        .replace_token(bot_id, user_id, bot_token.to_string())
+        .await
+    {
+        Ok(model) => {
+            bot.send_message(
+                message.chat.id,
+                format!("Token of bot {} replaced successfully", model.id),
+            )
+            .await?;
+        }
+        Err(err) => {
+            bot.send_message(message.chat.id, format!("Failed to replace token: {}", err))
+                .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn bot_rotate_secret_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    dialogue.reset().await?;
+
+    if let Err(err) = forwarding_bot_service
+        .rotate_webhook_secret(bot_id, callback_query.from.id.0)
+        .await
+    {
+        bot.send_message(
+            parent_msg.chat.id,
+            format!("Failed to rotate webhook secret: {}", err),
+        )
+        .reply_to_message_id(parent_msg.id)
+        .await?;
+        return Err(err);
+    }
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!("Webhook secret of bot {} rotated successfully", bot_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn bot_toggle_topics_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    dialogue.reset().await?;
+
+    let model = match forwarding_bot_service
+        .toggle_topics(bot_id, callback_query.from.id.0)
+        .await
+    {
+        Ok(model) => model,
+        Err(err) => {
+            bot.send_message(
+                parent_msg.chat.id,
+                format!("Failed to toggle topics: {}", err),
+            )
+            .reply_to_message_id(parent_msg.id)
+            .await?;
+            return Err(err);
+        }
+    };
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        if model.topics_enabled {
+            format!(
+                "Topics enabled for bot {}, each sender gets a topic in the target chat",
+                bot_id
+            )
+        } else {
+            format!(
+                "Topics disabled for bot {}, messages are forwarded to the target chat",
+                bot_id
+            )
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn bot_rate_limit_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!(
+            "Please, send me how many messages a sender may send to bot {} per minute, 0 turns the limit off. Senders sending twice as many are muted for {} minutes",
+            bot_id,
+            FLOOD_MUTE.as_secs() / 60
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::EditReceiveRateLimit(bot_id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn receive_rate_limit_handler(
+    bot: Bot,
+    message: Message,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let bot_id = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::EditReceiveRateLimit(bot_id) => bot_id,
+        _ => return Err(anyhow::anyhow!("Unexpected dialogue state")),
+    };
+
+    let rate_limit = match message
+        .text()
+        .and_then(|text| text.trim().parse::<i32>().ok())
+    {
+        Some(rate_limit) => rate_limit,
+        None => {
+            bot.send_message(
+                message.chat.id,
+                "Invalid rate limit, please send a number of messages per minute",
+            )
+            .await?;
+
+            return Ok(());
+        }
+    };
+
+    let user_id = message
+        .from()
+        .ok_or_else(|| anyhow::anyhow!("No sender in message"))?
+        .id
+        .0;
+
+    dialogue.reset().await?;
+
+    match forwarding_bot_service
+        .change_rate_limit(bot_id, user_id, rate_limit)
+        .await
+    {
+        Ok(model) if model.rate_limit == 0 => {
+            bot.send_message(
+                message.chat.id,
+                format!("Rate limit of bot {} turned off", model.id),
+            )
+            .await?;
+        }
+        Ok(model) => {
+            bot.send_message(
+                message.chat.id,
+                format!(
+                    "Rate limit of bot {} set to {} messages per minute",
+                    model.id, model.rate_limit
+                ),
+            )
+            .await?;
+        }
+        Err(err) => {
+            bot.send_message(
+                message.chat.id,
+                format!("Failed to change rate limit: {}", err),
+            )
+            .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn bot_retention_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!(
+            "Please, send me for how many days bot {} keeps its forwarded messages, 0 keeps them forever. Replies to older messages no longer reach their senders. Send default to use the default retention",
+            bot_id
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::EditReceiveRetention(bot_id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn receive_retention_handler(
+    bot: Bot,
+    message: Message,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let bot_id = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::EditReceiveRetention(bot_id) => bot_id,
+        _ => return Err(anyhow::anyhow!("Unexpected dialogue state")),
+    };
+
+    let days = match message.text().and_then(parse_retention_days) {
+        Some(days) => days,
+        None => {
+            bot.send_message(
+                message.chat.id,
+                "Invalid retention, please send a number of days or default",
+            )
+            .await?;
+
+            return Ok(());
+        }
+    };
+
+    let user_id = message
+        .from()
+        .ok_or_else(|| anyhow::anyhow!("No sender in message"))?
+        .id
+        .0;
+
+    dialogue.reset().await?;
+
+    match forwarding_bot_service
+        .change_retention(bot_id, user_id, days)
+        .await
+    {
+        Ok(model) => {
+            let text = match model.message_retention_days {
+                None => format!(
+                    "Bot {} now keeps messages for the default retention",
+                    model.id
+                ),
+                Some(0) => format!("Bot {} now keeps messages forever", model.id),
+                Some(days) => format!("Bot {} now keeps messages for {} days", model.id, days),
+            };
+            bot.send_message(message.chat.id, text).await?;
+        }
+        Err(err) => {
+            bot.send_message(
+                message.chat.id,
+                format!("Failed to change retention: {}", err),
+            )
+            .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn bot_captcha_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!(
+            "Please, send me the captcha first-time senders of bot {} solve before their messages are forwarded: math or emoji, optionally followed by the time to solve it and the retries after wrong answers, such as \"math 5m 1\". Send off to turn it off",
+            bot_id
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::EditReceiveCaptcha(bot_id))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn receive_captcha_handler(
+    bot: Bot,
+    message: Message,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let bot_id = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::EditReceiveCaptcha(bot_id) => bot_id,
+        _ => return Err(anyhow::anyhow!("Unexpected dialogue state")),
+    };
+
+    let settings = match parse_captcha_settings(message.text().unwrap_or_default()) {
+        Ok(settings) => settings,
+        Err(err) => {
+            bot.send_message(message.chat.id, format!("Invalid captcha: {}", err))
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let user_id = message
+        .from()
+        .ok_or_else(|| anyhow::anyhow!("No sender in message"))?
+        .id
+        .0;
+
+    dialogue.reset().await?;
+
+    match forwarding_bot_service
+        .change_captcha(bot_id, user_id, settings)
+        .await
+    {
+        Ok(model) => {
+            let text = match model.captcha {
+                Some(captcha) => format!(
+                    "Bot {} now sends first-time senders a {} captcha, solved within {} seconds with {} retries",
+                    model.id, captcha, model.captcha_timeout, model.captcha_retries
+                ),
+                None => format!("Captcha of bot {} turned off", model.id),
+            };
+            bot.send_message(message.chat.id, text).await?;
+        }
+        Err(err) => {
+            bot.send_message(
+                message.chat.id,
+                format!("Failed to change captcha: {}", err),
+            )
+            .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn bot_texts_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    // the dialogue stays at the action menu, the text is chosen next
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!("Choose the text of bot {} to change", bot_id),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(
+        BotText::ALL
+            .chunks(2)
+            .map(|texts| {
+                texts
+                    .iter()
+                    .map(|text| {
+                        teloxide::types::InlineKeyboardButton::callback(
+                            text.label(),
+                            text.callback_data(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .chain(std::iter::once(vec![
+                teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+            ])),
+    ))
+    .await?;
+
+    Ok(())
+}
+
+pub async fn bot_edit_text_handler(
+    bot: Bot,
+    callback_query: CallbackQuery,
+    dialogue: BotDialog,
+) -> anyhow::Result<()> {
+    let parent_msg = callback_query
+        .message
+        .ok_or_else(|| anyhow::anyhow!("No message in callback query"))?;
+
+    let bot_id = chosen_bot_id(&bot, &parent_msg, &dialogue).await?;
+
+    let kind = callback_query
+        .data
+        .as_deref()
+        .and_then(BotText::from_callback_data)
+        .ok_or_else(|| anyhow::anyhow!("Invalid text"))?;
+
+    bot.answer_callback_query(callback_query.id).await?;
+
+    // without a default text, restoring the default sends nothing too
+    let default_hint = kind
+        .default_text()
+        .map(|default_text| format!("{} for \"{}\" or ", DEFAULT_TEXT_COMMAND, default_text))
+        .unwrap_or_default();
+    bot.edit_message_text(
+        parent_msg.chat.id,
+        parent_msg.id,
+        format!(
+            "Please, send me the {} text of bot {}, {}{} to send nothing",
+            kind.label().to_lowercase(),
+            bot_id,
+            default_hint,
+            NO_TEXT_COMMAND
+        ),
+    )
+    .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+        teloxide::types::InlineKeyboardButton::callback("Cancel", "forward_bot_cancel"),
+    ]]))
+    .await?;
+
+    dialogue
+        .update(BotState::EditReceiveText { bot_id, kind })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn receive_text_handler(
+    bot: Bot,
+    message: Message,
+    dialogue: BotDialog,
+    forwarding_bot_service: ForwardingBotService,
+) -> anyhow::Result<()> {
+    let (bot_id, kind) = match dialogue
+        .get()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No dialogue state"))?
+    {
+        BotState::EditReceiveText { bot_id, kind } => (bot_id, kind),
+        _ => return Err(anyhow::anyhow!("Unexpected dialogue state")),
+    };
+
+    let text = match message.text() {
+        Some(text) => parse_text_input(text),
+        None => {
+            bot.send_message(message.chat.id, "Please, send the text as a text message")
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let user_id = message
+        .from()
+        .ok_or_else(|| anyhow::anyhow!("No sender in message"))?
+        .id
+        .0;
+
+    dialogue.reset().await?;
+
+    match forwarding_bot_service
+        .change_text(bot_id, user_id, kind, text)
+        .await
+    {
+        Ok(model) => {
+            bot.send_message(
+                message.chat.id,
+                format!(
+                    "{} text of bot {} changed, it sends {}",
+                    kind.label(),
+                    model.id,
+                    match kind.text(&model) {
+                        Some(text) => format!("\"{}\"", text),
+                        None => "nothing".to_string(),
+                    }
+                ),
+            )
+            .await?;
+        }
+        Err(err) => {
+            bot.send_message(message.chat.id, format!("Failed to change text: {}", err))
+                .await?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_is_valid_bot_token() {
+        assert!(is_valid_bot_token("123456:ABC-def_ghi"));
+        assert!(!is_valid_bot_token("123456"));
+        assert!(!is_valid_bot_token("abc:def"));
+        assert!(!is_valid_bot_token("123456:abc def"));
+    }
+
+    #[test]
+    fn test_parse_chat_id() {
+        assert_eq!(parse_chat_id("-1001234567890"), Some(-1001234567890));
+        assert_eq!(parse_chat_id("42"), Some(42));
+        assert_eq!(parse_chat_id("@channel"), None);
+        assert_eq!(parse_chat_id("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn test_parse_retention_days() {
+        assert_eq!(parse_retention_days("30"), Some(Some(30)));
+        assert_eq!(parse_retention_days(" 0 "), Some(Some(0)));
+        assert_eq!(parse_retention_days("Default"), Some(None));
+        assert_eq!(parse_retention_days("forever"), None);
+    }
+}
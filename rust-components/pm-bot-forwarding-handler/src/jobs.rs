@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions};
+use tokio::time::MissedTickBehavior;
+
+use pegasus_common::duration::parse_go_duration;
+use pegasus_common::health::checks::DispatcherLiveness;
+use pegasus_common::redis::lock::RedisLock;
+use pegasus_common::settings::Settings;
+
+use crate::queue::{
+    declare_update_queue, process_update, QueuedUpdate, UpdateClaims, UpdateOutcome,
+    UPDATE_QUEUE_NAME,
+};
+use crate::services::forwarding_message::ForwardingMessageService;
+use crate::services::retention::{IRetentionService, RetentionService};
+
+static DEFAULT_RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Taken off the lock ttl, so the lock runs out before the next tick of a replica that took it
+static RETENTION_LOCK_MARGIN: Duration = Duration::from_secs(1);
+static DEFAULT_WORKERS: usize = 4;
+/// Delay before an update claimed by another delivery is looked at again
+static IN_PROGRESS_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+///
+/// Prune expired messages periodically
+///
+/// Each run takes a redis lock held for just under the interval without releasing it, so the job
+/// runs once per interval across all replicas.
+///
+/// # Arguments
+///
+/// * `service_name`: service name, prefix of the lock key
+/// * `redis_client`: redis client the lock is taken with
+/// * `retention_service`: retention service
+/// * `settings`: application settings, `pm_forwarding.retention.interval` defaults to `1h`
+///
+/// returns: `()` never returns, spawn it
+///
+pub async fn run_retention_job(
+    service_name: &'static str,
+    redis_client: ::redis::Client,
+    retention_service: RetentionService,
+    settings: Settings,
+) {
+    let interval = settings
+        .pm_forwarding
+        .as_ref()
+        .and_then(|pm_forwarding| pm_forwarding.retention.as_ref())
+        .and_then(|retention| retention.interval.as_ref())
+        .and_then(|interval| parse_go_duration(interval).ok())
+        .filter(|interval| !interval.is_zero())
+        .unwrap_or(DEFAULT_RETENTION_INTERVAL);
+
+    let lock_ttl = retention_lock_ttl(interval);
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        let conn = match redis_client.get_multiplexed_tokio_connection().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                log::error!("Failed to connect to redis for the retention job: {}", err);
+                continue;
+            }
+        };
+
+        let mut lock = RedisLock::new(conn, format!("{}-lock-retention", service_name), lock_ttl);
+        match lock.try_acquire().await {
+            Ok(true) => {}
+            Ok(false) => {
+                log::debug!("Retention job already ran on another replica");
+                continue;
+            }
+            Err(err) => {
+                log::error!("Failed to take the retention job lock: {}", err);
+                continue;
+            }
+        }
+
+        match retention_service.prune_expired_messages().await {
+            Ok(deleted) => log::info!("Pruned {} expired messages", deleted),
+            Err(err) => log::error!("Failed to prune expired messages: {}", err),
+        }
+    }
+}
+
+/// Lock ttl of the retention job, shorter than the interval even for intervals under the margin
+fn retention_lock_ttl(interval: Duration) -> Duration {
+    interval
+        .saturating_sub(RETENTION_LOCK_MARGIN)
+        .max(interval / 2)
+}
+
+///
+/// Process the updates queued by the webhook with a pool of workers
+///
+/// Deliveries are acknowledged once processed or skipped as duplicates. Failed deliveries are
+/// requeued once, then dropped. Deliveries of updates still in progress are requeued after a
+/// delay, until the update is processed or its claim runs out.
+///
+/// # Arguments
+///
+/// * `channel`: amqp channel the updates are consumed on
+/// * `claims`: claims of updates being or already processed
+/// * `forwarding_message_service`: service processing the updates
+/// * `settings`: application settings, `pm_forwarding.workers` defaults to `4`
+/// * `liveness`: liveness flag, running while updates are consumed
+///
+/// returns: `Result<(), lapin::Error>` returns once the consumer is cancelled
+///
+pub async fn run_update_workers(
+    channel: lapin::Channel,
+    claims: impl UpdateClaims,
+    forwarding_message_service: ForwardingMessageService,
+    settings: Settings,
+    liveness: DispatcherLiveness,
+) -> Result<(), lapin::Error> {
+    let workers = settings
+        .pm_forwarding
+        .as_ref()
+        .and_then(|pm_forwarding| pm_forwarding.workers)
+        .filter(|workers| *workers > 0)
+        .unwrap_or(DEFAULT_WORKERS);
+
+    declare_update_queue(&channel).await?;
+    channel
+        .basic_qos(
+            u16::try_from(workers).unwrap_or(u16::MAX),
+            BasicQosOptions::default(),
+        )
+        .await?;
+    let consumer = channel
+        .basic_consume(
+            UPDATE_QUEUE_NAME,
+            &format!("{}-updates", settings.instance_id.as_ref().unwrap()),
+            BasicConsumeOptions::default(),
+            Default::default(),
+        )
+        .await?;
+    log::info!("Processing updates with {} workers", workers);
+    liveness.mark_running();
+
+    let (claims, forwarding_message_service) = (&claims, &forwarding_message_service);
+    consumer
+        .for_each_concurrent(workers, |delivery| async move {
+            let delivery = match delivery {
+                Ok(delivery) => delivery,
+                Err(err) => {
+                    log::error!("Failed to receive an update: {}", err);
+                    return;
+                }
+            };
+
+            let outcome = match serde_json::from_slice::<QueuedUpdate>(&delivery.data) {
+                Ok(update) => process_update(claims, forwarding_message_service, update).await,
+                Err(err) => {
+                    // never processable, acknowledged so it is not redelivered
+                    log::error!("Dropping a malformed update: {}", err);
+                    UpdateOutcome::Processed
+                }
+            };
+
+            let result = match outcome {
+                UpdateOutcome::Processed | UpdateOutcome::Duplicate => {
+                    delivery.ack(BasicAckOptions::default()).await
+                }
+                UpdateOutcome::InProgress => {
+                    // waits outside of the worker, the slot is free for other updates meanwhile
+                    tokio::spawn(async move {
+                        tokio::time::sleep(IN_PROGRESS_RETRY_DELAY).await;
+                        let requeue = BasicNackOptions {
+                            requeue: true,
+                            ..Default::default()
+                        };
+                        if let Err(err) = delivery.nack(requeue).await {
+                            log::error!("Failed to requeue an update delivery: {}", err);
+                        }
+                    });
+                    Ok(())
+                }
+                UpdateOutcome::Failed => {
+                    delivery
+                        .nack(BasicNackOptions {
+                            requeue: !delivery.redelivered,
+                            ..Default::default()
+                        })
+                        .await
+                }
+            };
+            if let Err(err) = result {
+                log::error!("Failed to settle an update delivery: {}", err);
+            }
+        })
+        .await;
+
+    liveness.mark_stopped();
+    log::error!("Update consumer cancelled, no more updates are processed");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_retention_lock_ttl() {
+        assert_eq!(
+            retention_lock_ttl(Duration::from_secs(60 * 60)),
+            Duration::from_secs(60 * 60 - 1)
+        );
+        assert_eq!(
+            retention_lock_ttl(Duration::from_secs(1)),
+            Duration::from_millis(500)
+        );
+    }
+}
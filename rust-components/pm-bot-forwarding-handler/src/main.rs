@@ -1,20 +1,38 @@
 use std::env;
+use std::sync::Arc;
 
 use actix_web::{App, HttpServer};
 use actix_web_opentelemetry::RequestTracing;
 use opentelemetry::global;
 
 use pegasus_common::bot::channel::MqUpdateListener;
+use pegasus_common::bot::error::new_error_handler;
 use pegasus_common::bot::new_bot;
 use pegasus_common::bot::state::new_state_storage;
+use pegasus_common::cryptor::Cryptor;
+use pegasus_common::health::checks::{DatabaseHealthCheck, DispatcherLiveness, RedisHealthCheck};
+use pegasus_common::health::server::new_health_server;
+use pegasus_common::health::{HealthRegistry, Probe};
 use pegasus_common::mq::connection::new_amqp_connection;
 use pegasus_common::{database, observability, redis, settings};
+use pegasus_migration::Migrator;
 
+use crate::captcha::RedisCaptchaStore;
+use crate::media_group::RedisMediaGroupBuffer;
+use crate::queue::{AmqpUpdateQueue, RedisUpdateClaims, UpdateQueue};
+use crate::rate_limit::RedisRateLimiter;
 use crate::run::run;
 
+mod captcha;
 mod handlers;
+mod jobs;
+mod media_group;
+mod moderation;
+mod queue;
+mod rate_limit;
 mod run;
 mod services;
+mod texts;
 mod web;
 
 #[tokio::main]
@@ -31,9 +49,13 @@ async fn main() -> anyhow::Result<()> {
     }));
 
     let amqp_conn = new_amqp_connection(settings).await;
-    let db = database::init_conn(settings.database.as_ref().unwrap()).await?;
+    let db = database::init_conn::<Migrator>(settings.database.as_ref().unwrap()).await?;
     let redis_client = redis::client::new_client(settings);
 
+    let update_queue: Arc<dyn UpdateQueue> =
+        Arc::new(AmqpUpdateQueue::new(amqp_conn.create_channel().await?).await?);
+    let update_workers_channel = amqp_conn.create_channel().await?;
+
     let bot = new_bot(settings.telegram_bot.as_ref().unwrap());
     let listener = MqUpdateListener::new(service_name, amqp_conn, settings).await?;
     let redis_storage = new_state_storage(
@@ -45,31 +67,119 @@ async fn main() -> anyhow::Result<()> {
     )
     .await;
 
-    let forwarding_bot_service =
-        services::forwarding_bot::ForwardingBotService::new(db.clone(), settings.clone());
-    let forwarding_message_service =
-        services::forwarding_message::ForwardingMessageService::new(db.clone(), settings.clone());
+    let cryptor = Cryptor::new(settings.encryption.as_ref())?;
+    let forwarding_bot_service = services::forwarding_bot::ForwardingBotService::new(
+        db.clone(),
+        cryptor.clone(),
+        settings.clone(),
+    );
+    let media_groups = RedisMediaGroupBuffer::new(
+        service_name,
+        redis_client.get_multiplexed_tokio_connection().await?,
+    );
+    let rate_limiter = RedisRateLimiter::new(
+        service_name,
+        redis_client.get_multiplexed_tokio_connection().await?,
+    );
+    let captchas = RedisCaptchaStore::new(
+        service_name,
+        redis_client.get_multiplexed_tokio_connection().await?,
+    );
+    let forwarding_message_service = services::forwarding_message::ForwardingMessageService::new(
+        db.clone(),
+        cryptor.clone(),
+        Arc::new(media_groups),
+        Arc::new(rate_limiter),
+        Arc::new(captchas),
+        settings.clone(),
+    );
+    let retention_service =
+        services::retention::RetentionService::new(db.clone(), cryptor, settings.clone());
+
+    let liveness = DispatcherLiveness::new();
+    let workers_liveness = DispatcherLiveness::named("update-workers");
+    let health_server = new_health_server(
+        settings,
+        HealthRegistry::new()
+            .register(Probe::Readiness, listener.health_check())
+            .register(
+                Probe::Readiness,
+                RedisHealthCheck::new(redis_client.clone()),
+            )
+            .register(Probe::Readiness, DatabaseHealthCheck::new(db.clone()))
+            .register(Probe::Liveness, liveness.clone())
+            .register(Probe::Liveness, workers_liveness.clone()),
+    )?;
+
+    tokio::spawn(jobs::run_retention_job(
+        service_name,
+        redis_client.clone(),
+        retention_service,
+        settings.clone(),
+    ));
+
+    let update_claims = RedisUpdateClaims::new(
+        service_name,
+        redis_client.get_multiplexed_tokio_connection().await?,
+    );
+    let run_update_workers = jobs::run_update_workers(
+        update_workers_channel,
+        update_claims,
+        forwarding_message_service,
+        settings.clone(),
+        workers_liveness,
+    );
+    // the liveness probe restarts the service once the workers stop
+    tokio::spawn(async move {
+        if let Err(err) = run_update_workers.await {
+            log::error!("Update workers stopped: {}", err);
+        }
+    });
 
     log::info!("Application started");
 
-    let run_bot = run(bot, listener, redis_storage, db, settings.clone());
+    let error_handler = new_error_handler(bot.clone(), service_name, settings);
+    let run_bot = run(
+        bot,
+        listener,
+        error_handler,
+        redis_storage,
+        forwarding_bot_service.clone(),
+        liveness,
+    );
+    let health_handle = health_server.handle();
+    let run_bot = async move {
+        let result = run_bot.await;
+        // the health server ignores signals, it stops with the dispatcher
+        health_handle.stop(true).await;
+        result
+    };
+    let listen_address = web::listen_address(settings);
+    log::info!(
+        "Webhook server listening on {}:{}",
+        listen_address.0,
+        listen_address.1
+    );
     let run_web_server = HttpServer::new(move || {
         App::new()
             .wrap(RequestTracing::default())
-            .wrap(actix_web::middleware::Logger::default())
+            .wrap(
+                actix_web::middleware::Logger::new(
+                    r#"%a "%{redacted_request}xi" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
+                )
+                .custom_request_replace("redacted_request", web::redacted_request_line),
+            )
             .app_data(actix_web::web::Data::new(forwarding_bot_service.clone()))
-            .app_data(actix_web::web::Data::new(
-                forwarding_message_service.clone(),
-            ))
+            .app_data(actix_web::web::Data::from(update_queue.clone()))
             .service(web::forwarding_bot_update_handler)
     })
-    .bind(("0.0.0.0", 8080))
-    .unwrap()
+    .bind(listen_address)?
     .run();
 
-    let (r1, r2) = tokio::join!(run_bot, run_web_server);
+    let (r1, r2, r3) = tokio::join!(run_bot, run_web_server, health_server);
     r1?;
     r2?;
+    r3?;
 
     log::info!("Shutting down tracer provider");
     global::shutdown_tracer_provider();
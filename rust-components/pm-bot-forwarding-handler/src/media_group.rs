@@ -0,0 +1,540 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use redis::aio::MultiplexedConnection;
+use teloxide::types::{
+    InputFile, InputMedia, InputMediaAnimation, InputMediaAudio, InputMediaDocument,
+    InputMediaPhoto, InputMediaVideo, MediaKind, Message, MessageKind,
+};
+
+/// Time to collect the messages of a media group, telegram sends them in quick succession
+pub static MEDIA_GROUP_WAIT: Duration = Duration::from_millis(1500);
+/// Buffers of groups that were never forwarded expire after this, as do the marks of forwarded
+/// groups
+static BUFFER_TTL: Duration = Duration::from_secs(60);
+
+/// Add a message to its group unless the group was forwarded, the first message pushed leads
+/// the group until it gives up, pushing the same message again is a no-op
+static PUSH_SCRIPT: &str = r#"
+if redis.call("EXISTS", KEYS[3]) == 1 then
+    return -1
+end
+redis.call("HSET", KEYS[1], ARGV[1], ARGV[2])
+redis.call("EXPIRE", KEYS[1], ARGV[3])
+redis.call("SET", KEYS[2], ARGV[1], "NX", "EX", ARGV[3])
+if redis.call("GET", KEYS[2]) == ARGV[1] then
+    return 1
+end
+return 0
+"#;
+
+/// Part a message plays in its media group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaGroupPart {
+    /// leads the group, forwards all of them once the rest arrived
+    First,
+    /// forwarded by the message leading the group
+    Collected,
+    /// arrived after the group was forwarded, forwarded on its own
+    Late,
+}
+
+///
+/// Messages of media groups being collected, so an album is forwarded as an album
+///
+/// Telegram delivers every message of a media group as its own update, possibly to different
+/// replicas. The first message of a group forwards all of them once the rest arrived. The
+/// messages are kept until the album is forwarded, if that fails the next message pushed leads
+/// the group and forwards all of them again.
+///
+pub trait MediaGroupBuffer: Debug + Send + Sync {
+    /// Add a message of a media group
+    fn push(&self, bot_id: i64, message: &Message)
+        -> BoxFuture<'_, anyhow::Result<MediaGroupPart>>;
+
+    /// Messages of the media group collected so far, in the order they were sent
+    fn messages(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<Message>>>;
+
+    /// Drop the messages of a forwarded media group, messages pushed later are late
+    fn finish(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Give up leading a media group that failed to forward, keeping its messages
+    fn release(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+fn buffer_key(service_name: &str, bot_id: i64, chat_id: i64, media_group_id: &str) -> String {
+    format!(
+        "{}-media-group-{}-{}-{}",
+        service_name, bot_id, chat_id, media_group_id
+    )
+}
+
+#[derive(Clone)]
+pub struct RedisMediaGroupBuffer {
+    service_name: &'static str,
+    conn: MultiplexedConnection,
+}
+
+impl Debug for RedisMediaGroupBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisMediaGroupBuffer")
+            .field("service_name", &self.service_name)
+            .finish()
+    }
+}
+
+impl RedisMediaGroupBuffer {
+    pub fn new(service_name: &'static str, conn: MultiplexedConnection) -> Self {
+        Self { service_name, conn }
+    }
+}
+
+impl MediaGroupBuffer for RedisMediaGroupBuffer {
+    fn push(
+        &self,
+        bot_id: i64,
+        message: &Message,
+    ) -> BoxFuture<'_, anyhow::Result<MediaGroupPart>> {
+        let key = message.media_group_id().map(|media_group_id| {
+            buffer_key(self.service_name, bot_id, message.chat.id.0, media_group_id)
+        });
+        let (message_id, payload) = (message.id.0, serde_json::to_string(message));
+
+        async move {
+            let key = key.ok_or_else(|| anyhow::anyhow!("Message is not in a media group"))?;
+
+            let part: i64 = redis::Script::new(PUSH_SCRIPT)
+                .key(&key)
+                .key(format!("{}-leader", key))
+                .key(format!("{}-forwarded", key))
+                .arg(message_id)
+                .arg(payload?)
+                .arg(BUFFER_TTL.as_secs())
+                .invoke_async(&mut self.conn.clone())
+                .await?;
+
+            Ok(match part {
+                1 => MediaGroupPart::First,
+                0 => MediaGroupPart::Collected,
+                _ => MediaGroupPart::Late,
+            })
+        }
+        .boxed()
+    }
+
+    fn messages(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<Message>>> {
+        let key = buffer_key(self.service_name, bot_id, chat_id, media_group_id);
+
+        async move {
+            let payloads: Vec<String> = redis::cmd("HVALS")
+                .arg(&key)
+                .query_async(&mut self.conn.clone())
+                .await?;
+
+            let mut messages = payloads
+                .iter()
+                .map(|payload| serde_json::from_str::<Message>(payload))
+                .collect::<Result<Vec<_>, _>>()?;
+            messages.sort_by_key(|message| message.id.0);
+
+            Ok(messages)
+        }
+        .boxed()
+    }
+
+    fn finish(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let key = buffer_key(self.service_name, bot_id, chat_id, media_group_id);
+
+        async move {
+            redis::pipe()
+                .atomic()
+                .set_ex(format!("{}-forwarded", key), 1, BUFFER_TTL.as_secs())
+                .ignore()
+                .del(&[format!("{}-leader", key), key])
+                .ignore()
+                .query_async::<_, ()>(&mut self.conn.clone())
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn release(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let key = buffer_key(self.service_name, bot_id, chat_id, media_group_id);
+
+        async move {
+            redis::cmd("DEL")
+                .arg(format!("{}-leader", key))
+                .query_async::<_, ()>(&mut self.conn.clone())
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+///
+/// Media to send a message again, or to edit a sent message into, captions preserved
+///
+/// # Arguments
+///
+/// * `message`: message with a photo, video, animation, audio or document
+///
+/// returns: `Result<InputMedia, Error>` fails on messages of other kinds
+///
+pub fn input_media(message: &Message) -> anyhow::Result<InputMedia> {
+    let media_kind = match &message.kind {
+        MessageKind::Common(common_message) => &common_message.media_kind,
+        _ => return Err(anyhow::anyhow!("Unsupported message kind")),
+    };
+
+    Ok(match media_kind {
+        MediaKind::Photo(m) => InputMedia::Photo(InputMediaPhoto {
+            caption: m.caption.clone(),
+            caption_entities: Some(m.caption_entities.clone()),
+            has_spoiler: m.has_media_spoiler,
+            ..InputMediaPhoto::new(InputFile::file_id(
+                &m.photo
+                    .last()
+                    .ok_or_else(|| anyhow::anyhow!("Missing photo size"))?
+                    .file
+                    .id,
+            ))
+        }),
+        MediaKind::Video(m) => InputMedia::Video(InputMediaVideo {
+            caption: m.caption.clone(),
+            caption_entities: Some(m.caption_entities.clone()),
+            has_spoiler: m.has_media_spoiler,
+            ..InputMediaVideo::new(InputFile::file_id(&m.video.file.id))
+        }),
+        MediaKind::Animation(m) => InputMedia::Animation(InputMediaAnimation {
+            caption: m.caption.clone(),
+            caption_entities: Some(m.caption_entities.clone()),
+            has_spoiler: m.has_media_spoiler,
+            ..InputMediaAnimation::new(InputFile::file_id(&m.animation.file.id))
+        }),
+        MediaKind::Audio(m) => InputMedia::Audio(InputMediaAudio {
+            caption: m.caption.clone(),
+            caption_entities: Some(m.caption_entities.clone()),
+            ..InputMediaAudio::new(InputFile::file_id(&m.audio.file.id))
+        }),
+        MediaKind::Document(m) => InputMedia::Document(InputMediaDocument {
+            caption: m.caption.clone(),
+            caption_entities: Some(m.caption_entities.clone()),
+            ..InputMediaDocument::new(InputFile::file_id(&m.document.file.id))
+        }),
+        _ => return Err(anyhow::anyhow!("Unsupported media kind")),
+    })
+}
+
+/// Media to send the messages of a media group again as an album, see [`input_media`]
+pub fn album_media(messages: &[Message]) -> anyhow::Result<Vec<InputMedia>> {
+    messages.iter().map(input_media).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Bot, chat and media group ids
+    type GroupKey = (i64, i64, String);
+    /// Messages by id and the message leading the group
+    type Group = (BTreeMap<i32, Message>, Option<i32>);
+
+    /// In-memory media group buffer
+    #[derive(Debug, Default)]
+    pub(crate) struct InMemoryMediaGroupBuffer {
+        groups: Mutex<HashMap<GroupKey, Group>>,
+        forwarded: Mutex<HashSet<GroupKey>>,
+    }
+
+    impl MediaGroupBuffer for InMemoryMediaGroupBuffer {
+        fn push(
+            &self,
+            bot_id: i64,
+            message: &Message,
+        ) -> BoxFuture<'_, anyhow::Result<MediaGroupPart>> {
+            let result = message
+                .media_group_id()
+                .ok_or_else(|| anyhow::anyhow!("Message is not in a media group"))
+                .map(|media_group_id| {
+                    let group_key = (bot_id, message.chat.id.0, media_group_id.to_string());
+                    if self.forwarded.lock().unwrap().contains(&group_key) {
+                        return MediaGroupPart::Late;
+                    }
+
+                    let mut groups = self.groups.lock().unwrap();
+                    let (messages, leader) = groups.entry(group_key).or_default();
+                    messages.insert(message.id.0, message.clone());
+                    if *leader.get_or_insert(message.id.0) == message.id.0 {
+                        MediaGroupPart::First
+                    } else {
+                        MediaGroupPart::Collected
+                    }
+                });
+
+            futures::future::ready(result).boxed()
+        }
+
+        fn messages(
+            &self,
+            bot_id: i64,
+            chat_id: i64,
+            media_group_id: &str,
+        ) -> BoxFuture<'_, anyhow::Result<Vec<Message>>> {
+            let messages = self
+                .groups
+                .lock()
+                .unwrap()
+                .get(&(bot_id, chat_id, media_group_id.to_string()))
+                .map(|(messages, _)| messages.values().cloned().collect())
+                .unwrap_or_default();
+
+            futures::future::ok(messages).boxed()
+        }
+
+        fn finish(
+            &self,
+            bot_id: i64,
+            chat_id: i64,
+            media_group_id: &str,
+        ) -> BoxFuture<'_, anyhow::Result<()>> {
+            let group_key = (bot_id, chat_id, media_group_id.to_string());
+            self.groups.lock().unwrap().remove(&group_key);
+            self.forwarded.lock().unwrap().insert(group_key);
+
+            futures::future::ok(()).boxed()
+        }
+
+        fn release(
+            &self,
+            bot_id: i64,
+            chat_id: i64,
+            media_group_id: &str,
+        ) -> BoxFuture<'_, anyhow::Result<()>> {
+            if let Some((_, leader)) =
+                self.groups
+                    .lock()
+                    .unwrap()
+                    .get_mut(&(bot_id, chat_id, media_group_id.to_string()))
+            {
+                *leader = None;
+            }
+
+            futures::future::ok(()).boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageEntityKind;
+
+    use super::testing::InMemoryMediaGroupBuffer;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn album_message(message_id: i32, media: serde_json::Value) -> Message {
+        let mut message = serde_json::json!({
+            "message_id": message_id,
+            "date": 1700000000,
+            "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+            "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+            "media_group_id": "album"
+        });
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(media.as_object().unwrap().clone());
+
+        // from a string, message kinds do not deserialize from a `serde_json::Value`
+        serde_json::from_str(&message.to_string()).unwrap()
+    }
+
+    fn photo(message_id: i32, caption: Option<&str>) -> Message {
+        let mut media = serde_json::json!({
+            "photo": [
+                {"file_id": "small", "file_unique_id": "s", "width": 90, "height": 90},
+                {"file_id": "large", "file_unique_id": "l", "width": 900, "height": 900}
+            ]
+        });
+        if let Some(caption) = caption {
+            media["caption"] = caption.into();
+            media["caption_entities"] =
+                serde_json::json!([{"type": "bold", "offset": 0, "length": 4}]);
+        }
+
+        album_message(message_id, media)
+    }
+
+    #[test]
+    fn test_buffer_key() {
+        assert_eq!(
+            buffer_key("pm-bot-forwarding-handler", 1, 42, "album"),
+            "pm-bot-forwarding-handler-media-group-1-42-album"
+        );
+    }
+
+    #[test]
+    fn test_buffered_message_roundtrip() {
+        // as buffered in redis
+        let message = photo(7, Some("look at this"));
+        let decoded =
+            serde_json::from_str::<Message>(&serde_json::to_string(&message).unwrap()).unwrap();
+
+        assert_eq!(decoded.id, message.id);
+        assert_eq!(decoded.media_group_id(), Some("album"));
+        assert_eq!(decoded.caption(), Some("look at this"));
+        assert_eq!(album_media(&[decoded]).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_media_group_buffer() {
+        let buffer = InMemoryMediaGroupBuffer::default();
+        let ids = |messages: Vec<Message>| {
+            messages
+                .iter()
+                .map(|message| message.id.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            buffer.push(1, &photo(8, None)).await.unwrap(),
+            MediaGroupPart::First
+        );
+        assert_eq!(
+            buffer.push(1, &photo(7, None)).await.unwrap(),
+            MediaGroupPart::Collected
+        );
+        // redelivered
+        assert_eq!(
+            buffer.push(1, &photo(8, None)).await.unwrap(),
+            MediaGroupPart::First
+        );
+        // groups are per bot
+        assert_eq!(
+            buffer.push(2, &photo(9, None)).await.unwrap(),
+            MediaGroupPart::First
+        );
+        assert_eq!(
+            ids(buffer.messages(1, 42, "album").await.unwrap()),
+            vec![7, 8]
+        );
+
+        // failed to forward, the next message leads the group with every message kept
+        buffer.release(1, 42, "album").await.unwrap();
+        assert_eq!(
+            buffer.push(1, &photo(10, None)).await.unwrap(),
+            MediaGroupPart::First
+        );
+        assert_eq!(
+            buffer.push(1, &photo(8, None)).await.unwrap(),
+            MediaGroupPart::Collected
+        );
+        assert_eq!(
+            ids(buffer.messages(1, 42, "album").await.unwrap()),
+            vec![7, 8, 10]
+        );
+
+        buffer.finish(1, 42, "album").await.unwrap();
+        assert!(buffer.messages(1, 42, "album").await.unwrap().is_empty());
+        assert_eq!(
+            buffer.push(1, &photo(11, None)).await.unwrap(),
+            MediaGroupPart::Late
+        );
+        assert!(buffer.messages(1, 42, "album").await.unwrap().is_empty());
+
+        let text = serde_json::from_str::<Message>(
+            &serde_json::json!({
+                "message_id": 10,
+                "date": 1700000000,
+                "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+                "text": "hello"
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert!(buffer.push(1, &text).await.is_err());
+    }
+
+    #[test]
+    fn test_album_media() {
+        let media = album_media(&[
+            photo(7, Some("look at this")),
+            album_message(
+                8,
+                serde_json::json!({
+                    "video": {
+                        "file_id": "video", "file_unique_id": "v",
+                        "width": 640, "height": 480, "duration": 3,
+                        "mime_type": "video/mp4"
+                    }
+                }),
+            ),
+        ])
+        .unwrap();
+
+        match &media[0] {
+            InputMedia::Photo(photo) => {
+                assert_eq!(photo.caption.as_deref(), Some("look at this"));
+                assert_eq!(
+                    photo.caption_entities.as_ref().unwrap()[0].kind,
+                    MessageEntityKind::Bold
+                );
+                // the largest size
+                assert!(format!("{:?}", photo.media).contains("large"));
+            }
+            other => panic!("Expected a photo, got {:?}", other),
+        }
+        assert!(matches!(&media[1], InputMedia::Video(video) if video.caption.is_none()));
+
+        let voice = album_message(
+            9,
+            serde_json::json!({
+                "voice": {
+                    "file_id": "voice", "file_unique_id": "o",
+                    "duration": 3, "mime_type": "audio/ogg"
+                }
+            }),
+        );
+        assert!(album_media(&[voice]).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use pegasus_common::database::entities::pm_forwarding_block;
+use pegasus_common::duration::parse_go_duration;
+
+/// Command of the target chat to block senders, replied to a forwarded message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModerationCommand {
+    /// `/ban`, block the sender until unbanned
+    Ban,
+    /// `/unban`, lift a ban or a mute
+    Unban,
+    /// `/mute 1h`, block the sender for a while
+    Mute(Duration),
+    /// `/blocked`, list the blocked senders
+    Blocked,
+    /// `/blocknotice text`, text sent to blocked senders, none to drop their messages silently
+    BlockNotice(Option<String>),
+}
+
+///
+/// Parse a moderation command, `/ban`, `/ban@bot` and so on
+///
+/// # Arguments
+///
+/// * `text`: text of a message in the target chat
+///
+/// returns: `Option<Result<ModerationCommand, Error>>` none for other texts, an error for a
+/// moderation command with invalid arguments
+///
+pub fn parse_moderation_command(text: &str) -> Option<anyhow::Result<ModerationCommand>> {
+    let text = text.trim();
+    let (command, args) = text
+        .split_once(char::is_whitespace)
+        .map_or((text, ""), |(command, args)| (command, args.trim()));
+    let command = command
+        .split_once('@')
+        .map_or(command, |(command, _)| command);
+
+    Some(match command {
+        "/ban" => Ok(ModerationCommand::Ban),
+        "/unban" => Ok(ModerationCommand::Unban),
+        "/mute" => parse_mute_duration(args).map(ModerationCommand::Mute),
+        "/blocked" => Ok(ModerationCommand::Blocked),
+        "/blocknotice" => Ok(ModerationCommand::BlockNotice(
+            Some(args.to_string()).filter(|notice| !notice.is_empty()),
+        )),
+        _ => return None,
+    })
+}
+
+/// Duration of a mute, as `30m`, `1h` or `7d`
+fn parse_mute_duration(text: &str) -> anyhow::Result<Duration> {
+    let duration = match text.strip_suffix('d') {
+        Some(days) => days
+            .parse::<u64>()
+            .ok()
+            .and_then(|days| days.checked_mul(24 * 60 * 60))
+            .map(Duration::from_secs),
+        None => parse_go_duration(text).ok(),
+    };
+
+    duration
+        .filter(|duration| duration.as_secs() > 0)
+        .ok_or_else(|| {
+            anyhow::anyhow!("Invalid mute duration, use /mute 30m, /mute 1h or /mute 7d")
+        })
+}
+
+///
+/// Blocked senders as csv, to export the blocklist of a bot
+///
+/// # Arguments
+///
+/// * `blocks`: blocks of the bot
+///
+/// returns: `String` one line per sender, with a header
+///
+pub fn blocks_csv(blocks: &[pm_forwarding_block::Model]) -> String {
+    let mut csv = String::from("telegram_chat_id,blocked_at,blocked_until\n");
+    for block in blocks {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            block.telegram_chat_id,
+            block.created_at.to_rfc3339(),
+            block
+                .blocked_until
+                .map(|until| until.to_rfc3339())
+                .unwrap_or_default()
+        ));
+    }
+
+    csv
+}
+
+/// Line of a blocked sender in the list sent to the target chat
+pub fn block_line(block: &pm_forwarding_block::Model) -> String {
+    match block.blocked_until {
+        Some(until) => format!(
+            "{} muted until {}",
+            block.telegram_chat_id,
+            format_time(until)
+        ),
+        None => format!("{} banned", block.telegram_chat_id),
+    }
+}
+
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_moderation_command() {
+        let parse = |text| parse_moderation_command(text).map(|command| command.ok());
+
+        assert_eq!(parse("/ban"), Some(Some(ModerationCommand::Ban)));
+        assert_eq!(parse("/ban@pm_bot"), Some(Some(ModerationCommand::Ban)));
+        assert_eq!(parse("/unban"), Some(Some(ModerationCommand::Unban)));
+        assert_eq!(
+            parse("/mute 1h"),
+            Some(Some(ModerationCommand::Mute(Duration::from_secs(3600))))
+        );
+        assert_eq!(
+            parse("/mute@pm_bot 7d"),
+            Some(Some(ModerationCommand::Mute(Duration::from_secs(
+                7 * 24 * 3600
+            ))))
+        );
+        assert_eq!(parse("/blocked"), Some(Some(ModerationCommand::Blocked)));
+        assert_eq!(
+            parse("/blocknotice You are blocked"),
+            Some(Some(ModerationCommand::BlockNotice(Some(
+                "You are blocked".to_string()
+            ))))
+        );
+        assert_eq!(
+            parse("/blocknotice"),
+            Some(Some(ModerationCommand::BlockNotice(None)))
+        );
+
+        // invalid arguments
+        assert_eq!(parse("/mute"), Some(None));
+        assert_eq!(parse("/mute 0m"), Some(None));
+        assert_eq!(parse("/mute forever"), Some(None));
+
+        // not moderation commands
+        assert_eq!(parse("/banana"), None);
+        assert_eq!(parse("/delete"), None);
+        assert_eq!(parse("ban"), None);
+    }
+
+    #[test]
+    fn test_blocks_csv() {
+        let created_at = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let block = |telegram_chat_id, blocked_until| pm_forwarding_block::Model {
+            id: telegram_chat_id,
+            created_at,
+            updated_at: created_at,
+            bot_id: 1,
+            telegram_chat_id,
+            blocked_until,
+        };
+        let blocks = [
+            block(42, None),
+            block(43, Some(created_at + chrono::Duration::hours(1))),
+        ];
+
+        assert_eq!(
+            blocks_csv(&blocks),
+            "telegram_chat_id,blocked_at,blocked_until\n\
+             42,2024-06-01T12:00:00+00:00,\n\
+             43,2024-06-01T12:00:00+00:00,2024-06-01T13:00:00+00:00\n"
+        );
+        assert_eq!(block_line(&blocks[0]), "42 banned");
+        assert_eq!(
+            block_line(&blocks[1]),
+            "43 muted until 2024-06-01 13:00 UTC"
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use lapin::options::{BasicPublishOptions, QueueDeclareOptions};
+use lapin::BasicProperties;
+use redis::aio::MultiplexedConnection;
+use teloxide::types::Update;
+
+use crate::services::forwarding_message::IForwardingMessageService;
+
+/// Durable queue of webhook updates waiting for the workers
+pub static UPDATE_QUEUE_NAME: &str = "pm_forwarding_updates";
+/// Telegram gives up redelivering an update after a day
+static CLAIM_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Updates are processed in seconds, the claim of a worker that stopped meanwhile runs out soon
+static PROCESSING_TTL: Duration = Duration::from_secs(2 * 60);
+
+/// Claims an update while it is processed, the value is `done` once it was processed
+static CLAIM_SCRIPT: &str = r#"
+if redis.call("SET", KEYS[1], "processing", "NX", "EX", ARGV[1]) then
+  return "claimed"
+end
+return redis.call("GET", KEYS[1]) or "processing"
+"#;
+
+/// Update received by the webhook of a bot
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct QueuedUpdate {
+    pub bot_id: i64,
+    pub update: Update,
+}
+
+pub trait UpdateQueue: Debug + Send + Sync {
+    /// Hand the update over to the workers, once this returns the update is not lost
+    fn publish(&self, update: QueuedUpdate) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// Update queue on a durable amqp queue, published with confirms
+#[derive(Clone, Debug)]
+pub struct AmqpUpdateQueue {
+    channel: lapin::Channel,
+}
+
+impl AmqpUpdateQueue {
+    pub async fn new(channel: lapin::Channel) -> Result<Self, lapin::Error> {
+        declare_update_queue(&channel).await?;
+        channel
+            .confirm_select(lapin::options::ConfirmSelectOptions::default())
+            .await?;
+
+        Ok(Self { channel })
+    }
+}
+
+/// Declare the update queue, publishers and consumers declare it alike
+pub async fn declare_update_queue(channel: &lapin::Channel) -> Result<(), lapin::Error> {
+    channel
+        .queue_declare(
+            UPDATE_QUEUE_NAME,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            Default::default(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+impl UpdateQueue for AmqpUpdateQueue {
+    fn publish(&self, update: QueuedUpdate) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let payload = serde_json::to_vec(&update)?;
+            let confirm = self
+                .channel
+                .basic_publish(
+                    "",
+                    UPDATE_QUEUE_NAME,
+                    BasicPublishOptions::default(),
+                    &payload,
+                    // persistent
+                    BasicProperties::default().with_delivery_mode(2),
+                )
+                .await?
+                .await?;
+
+            if confirm.is_nack() {
+                return Err(anyhow::anyhow!(
+                    "Update {} rejected by the broker",
+                    update.update.id
+                ));
+            }
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+///
+/// Claims of updates being or already processed, so redelivered updates are skipped
+///
+/// Telegram redelivers an update until the webhook answers, and the queue redelivers it when a
+/// worker stops before acknowledging it.
+///
+pub trait UpdateClaims: Debug + Send + Sync {
+    /// Claim the update while it is processed
+    fn claim(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<Claim>>;
+
+    /// Mark a claimed update processed, redeliveries of it are skipped from then on
+    fn finish(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<()>>;
+
+    /// Release the claim of an update that failed, so a redelivery processes it again
+    fn release(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// Claim taken on an update
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Claim {
+    Claimed,
+    /// processed by an earlier delivery
+    Processed,
+    /// claimed by a delivery still being processed, or by a worker that stopped before finishing
+    InProgress,
+}
+
+fn claim_key(service_name: &str, bot_id: i64, update_id: i32) -> String {
+    format!("{}-update-{}-{}", service_name, bot_id, update_id)
+}
+
+#[derive(Clone)]
+pub struct RedisUpdateClaims {
+    service_name: &'static str,
+    conn: MultiplexedConnection,
+}
+
+impl Debug for RedisUpdateClaims {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisUpdateClaims")
+            .field("service_name", &self.service_name)
+            .finish()
+    }
+}
+
+impl RedisUpdateClaims {
+    pub fn new(service_name: &'static str, conn: MultiplexedConnection) -> Self {
+        Self { service_name, conn }
+    }
+}
+
+impl UpdateClaims for RedisUpdateClaims {
+    fn claim(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<Claim>> {
+        async move {
+            let claim: String = redis::Script::new(CLAIM_SCRIPT)
+                .key(claim_key(self.service_name, bot_id, update_id))
+                .arg(PROCESSING_TTL.as_secs())
+                .invoke_async(&mut self.conn.clone())
+                .await?;
+
+            Ok(match claim.as_str() {
+                "claimed" => Claim::Claimed,
+                "done" => Claim::Processed,
+                _ => Claim::InProgress,
+            })
+        }
+        .boxed()
+    }
+
+    fn finish(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            redis::cmd("SET")
+                .arg(claim_key(self.service_name, bot_id, update_id))
+                .arg("done")
+                .arg("EX")
+                .arg(CLAIM_TTL.as_secs())
+                .query_async::<_, ()>(&mut self.conn.clone())
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn release(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            redis::cmd("DEL")
+                .arg(claim_key(self.service_name, bot_id, update_id))
+                .query_async::<_, ()>(&mut self.conn.clone())
+                .await?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// What became of a queued update
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    Processed,
+    /// processed by an earlier delivery
+    Duplicate,
+    /// claimed by a delivery still being processed, a redelivery checks again later
+    InProgress,
+    /// claim released, a redelivery processes it again
+    Failed,
+}
+
+///
+/// Process an update unless an earlier delivery of it was processed already
+///
+/// # Arguments
+///
+/// * `claims`: claims of updates being or already processed
+/// * `forwarding_message_service`: service processing the update
+/// * `update`: queued update
+///
+/// returns: `UpdateOutcome`
+///
+pub async fn process_update<S: IForwardingMessageService>(
+    claims: &dyn UpdateClaims,
+    forwarding_message_service: &S,
+    update: QueuedUpdate,
+) -> UpdateOutcome {
+    let (bot_id, update_id) = (update.bot_id, update.update.id);
+
+    match claims.claim(bot_id, update_id).await {
+        Ok(Claim::Claimed) => {}
+        Ok(Claim::Processed) => {
+            log::debug!("Skipping duplicate update {} of bot {}", update_id, bot_id);
+            return UpdateOutcome::Duplicate;
+        }
+        Ok(Claim::InProgress) => {
+            log::debug!("Update {} of bot {} is in progress", update_id, bot_id);
+            return UpdateOutcome::InProgress;
+        }
+        Err(err) => {
+            log::error!(
+                "Failed to claim update {} of bot {}: {}",
+                update_id,
+                bot_id,
+                err
+            );
+            return UpdateOutcome::Failed;
+        }
+    }
+
+    match forwarding_message_service
+        .handle_update_income(bot_id, update.update)
+        .await
+    {
+        Ok(_) => {
+            // not finished, the update is processed again once the claim runs out
+            if let Err(err) = claims.finish(bot_id, update_id).await {
+                log::error!(
+                    "Failed to finish update {} of bot {}: {}",
+                    update_id,
+                    bot_id,
+                    err
+                );
+            }
+            UpdateOutcome::Processed
+        }
+        Err(err) => {
+            log::error!(
+                "Error handling update {} of bot {}: {}",
+                update_id,
+                bot_id,
+                err
+            );
+            if let Err(err) = claims.release(bot_id, update_id).await {
+                log::error!(
+                    "Failed to release update {} of bot {}: {}",
+                    update_id,
+                    bot_id,
+                    err
+                );
+            }
+            UpdateOutcome::Failed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::Entry;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    /// In-memory update claims, claims never run out
+    #[derive(Debug, Default)]
+    struct InMemoryUpdateClaims {
+        /// whether the update was processed, by update
+        claims: Mutex<HashMap<(i64, i32), bool>>,
+    }
+
+    impl UpdateClaims for InMemoryUpdateClaims {
+        fn claim(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<Claim>> {
+            let claim = match self.claims.lock().unwrap().entry((bot_id, update_id)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(false);
+                    Claim::Claimed
+                }
+                Entry::Occupied(entry) if *entry.get() => Claim::Processed,
+                Entry::Occupied(_) => Claim::InProgress,
+            };
+            futures::future::ok(claim).boxed()
+        }
+
+        fn finish(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<()>> {
+            self.claims
+                .lock()
+                .unwrap()
+                .insert((bot_id, update_id), true);
+            futures::future::ok(()).boxed()
+        }
+
+        fn release(&self, bot_id: i64, update_id: i32) -> BoxFuture<'_, anyhow::Result<()>> {
+            self.claims.lock().unwrap().remove(&(bot_id, update_id));
+            futures::future::ok(()).boxed()
+        }
+    }
+
+    /// Counts handled updates, failing the first `failures` of them
+    #[derive(Debug, Default)]
+    struct CountingService {
+        handled: Arc<AtomicUsize>,
+        failures: usize,
+    }
+
+    impl IForwardingMessageService for CountingService {
+        async fn handle_update_income(&self, _bot_id: i64, _update: Update) -> anyhow::Result<()> {
+            let handled = self.handled.fetch_add(1, Ordering::SeqCst);
+            if handled < self.failures {
+                return Err(anyhow::anyhow!("Telegram is down"));
+            }
+
+            Ok(())
+        }
+    }
+
+    fn queued_update(bot_id: i64, update_id: i32) -> QueuedUpdate {
+        // from a string, update kinds do not deserialize from a `serde_json::Value`
+        let update = serde_json::from_str::<Update>(
+            &serde_json::json!({
+            "update_id": update_id,
+            "message": {
+                "message_id": 7,
+                "date": 1700000000,
+                "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+                "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+                "text": "hello"
+            }
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        QueuedUpdate { bot_id, update }
+    }
+
+    #[test]
+    fn test_queued_update_roundtrip() {
+        let update = queued_update(1, 100);
+        let decoded =
+            serde_json::from_slice::<QueuedUpdate>(&serde_json::to_vec(&update).unwrap()).unwrap();
+
+        assert_eq!(decoded.bot_id, 1);
+        assert_eq!(decoded.update.id, 100);
+        assert_eq!(decoded.update.chat().map(|chat| chat.id.0), Some(42));
+    }
+
+    #[test]
+    fn test_claim_key() {
+        assert_eq!(
+            claim_key("pm-bot-forwarding-handler", 1, 100),
+            "pm-bot-forwarding-handler-update-1-100"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_update_once() {
+        let claims = InMemoryUpdateClaims::default();
+        let service = CountingService::default();
+
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::Processed
+        );
+        // redelivered by telegram or the queue
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::Duplicate
+        );
+        // update ids are per bot
+        assert_eq!(
+            process_update(&claims, &service, queued_update(2, 100)).await,
+            UpdateOutcome::Processed
+        );
+
+        assert_eq!(service.handled.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_process_update_retry_after_failure() {
+        let claims = InMemoryUpdateClaims::default();
+        let service = CountingService {
+            failures: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::Failed
+        );
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::Processed
+        );
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::Duplicate
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_update_in_progress() {
+        let claims = InMemoryUpdateClaims::default();
+        let service = CountingService::default();
+
+        // claimed by another worker, or by one that stopped before finishing it
+        assert_eq!(claims.claim(1, 100).await.unwrap(), Claim::Claimed);
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::InProgress
+        );
+        assert_eq!(service.handled.load(Ordering::SeqCst), 0);
+
+        claims.finish(1, 100).await.unwrap();
+        assert_eq!(
+            process_update(&claims, &service, queued_update(1, 100)).await,
+            UpdateOutcome::Duplicate
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use redis::aio::MultiplexedConnection;
+
+/// A bucket refills completely in this window, the limit of a bot is per minute
+pub static RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+/// Senders flooding a bot are muted this long
+pub static FLOOD_MUTE: Duration = Duration::from_secs(30 * 60);
+
+/// Take a token from a bucket refilled continuously, the tokens may drop below zero down to
+/// minus the capacity so the overdraft tells throttling from flooding. The bucket remembers the
+/// sender was told about the limit until a message is allowed again. Mirrored by
+/// `testing::take_token` for the tests
+static TAKE_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call("HMGET", KEYS[1], "tokens", "at", "notified")
+local tokens = tonumber(bucket[1]) or capacity
+local at = tonumber(bucket[2]) or now
+local notified = bucket[3] == "1"
+tokens = math.min(capacity, tokens + math.max(now - at, 0) * capacity / window)
+tokens = math.max(tokens - 1, -capacity)
+local decision = "allowed"
+if math.floor(tokens) <= -capacity then
+  decision = "flood"
+elseif tokens < 0 then
+  decision = notified and "throttled" or "throttled_first"
+end
+redis.call("HSET", KEYS[1], "tokens", tostring(tokens), "at", now, "notified", tokens < 0 and "1" or "0")
+redis.call("PEXPIRE", KEYS[1], window * 2)
+return decision
+"#;
+
+/// Outcome of a message against the rate limit of its sender
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateDecision {
+    Allowed,
+    /// dropped, `first` for the first message over the limit, the sender is told once
+    Throttled {
+        first: bool,
+    },
+    /// twice the limit, the sender is muted
+    Flood,
+}
+
+///
+/// Token buckets of the senders of each bot, shared by the replicas
+///
+pub trait RateLimiter: Debug + Send + Sync {
+    /// Take a token for a message of a sender, returns the decision on the message
+    fn take(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        capacity: u32,
+    ) -> BoxFuture<'_, anyhow::Result<RateDecision>>;
+}
+
+fn bucket_key(service_name: &str, bot_id: i64, chat_id: i64) -> String {
+    format!("{}-rate-limit-{}-{}", service_name, bot_id, chat_id)
+}
+
+#[derive(Clone)]
+pub struct RedisRateLimiter {
+    service_name: &'static str,
+    conn: MultiplexedConnection,
+}
+
+impl Debug for RedisRateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisRateLimiter")
+            .field("service_name", &self.service_name)
+            .finish()
+    }
+}
+
+impl RedisRateLimiter {
+    pub fn new(service_name: &'static str, conn: MultiplexedConnection) -> Self {
+        Self { service_name, conn }
+    }
+
+    async fn take_at(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        capacity: u32,
+        now: i64,
+    ) -> anyhow::Result<RateDecision> {
+        let decision: String = redis::Script::new(TAKE_SCRIPT)
+            .key(bucket_key(self.service_name, bot_id, chat_id))
+            .arg(capacity)
+            .arg(RATE_LIMIT_WINDOW.as_millis() as u64)
+            .arg(now)
+            .invoke_async(&mut self.conn.clone())
+            .await?;
+
+        match decision.as_str() {
+            "allowed" => Ok(RateDecision::Allowed),
+            "throttled_first" => Ok(RateDecision::Throttled { first: true }),
+            "throttled" => Ok(RateDecision::Throttled { first: false }),
+            "flood" => Ok(RateDecision::Flood),
+            decision => Err(anyhow::anyhow!("Unexpected rate decision {}", decision)),
+        }
+    }
+}
+
+impl RateLimiter for RedisRateLimiter {
+    fn take(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        capacity: u32,
+    ) -> BoxFuture<'_, anyhow::Result<RateDecision>> {
+        async move {
+            self.take_at(
+                bot_id,
+                chat_id,
+                capacity,
+                chrono::Utc::now().timestamp_millis(),
+            )
+            .await
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Token bucket of a sender, as kept in redis
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) struct Bucket {
+        pub(crate) tokens: f64,
+        /// unix time in milliseconds the bucket was last taken from
+        pub(crate) at: i64,
+        /// whether the sender was told about the limit since its last allowed message
+        pub(crate) notified: bool,
+    }
+
+    ///
+    /// Take a token for a message from the bucket of its sender, the logic of the redis script
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket`: bucket of the sender, `None` for a full one
+    /// * `capacity`: messages a sender may send per minute
+    /// * `now`: unix time in milliseconds
+    ///
+    /// returns: `(Bucket, RateDecision)` bucket after taking the token, decision on the message
+    ///
+    pub(crate) fn take_token(
+        bucket: Option<Bucket>,
+        capacity: u32,
+        now: i64,
+    ) -> (Bucket, RateDecision) {
+        let capacity = capacity as f64;
+        let window = RATE_LIMIT_WINDOW.as_millis() as f64;
+        let bucket = bucket.unwrap_or(Bucket {
+            tokens: capacity,
+            at: now,
+            notified: false,
+        });
+
+        let tokens =
+            capacity.min(bucket.tokens + (now - bucket.at).max(0) as f64 * capacity / window);
+        let tokens = (tokens - 1.0).max(-capacity);
+        let decision = if tokens.floor() <= -capacity {
+            RateDecision::Flood
+        } else if tokens < 0.0 {
+            RateDecision::Throttled {
+                first: !bucket.notified,
+            }
+        } else {
+            RateDecision::Allowed
+        };
+
+        let bucket = Bucket {
+            tokens,
+            at: now,
+            notified: tokens < 0.0,
+        };
+        (bucket, decision)
+    }
+
+    /// In-memory rate limiter, buckets never refill
+    #[derive(Debug, Default)]
+    pub(crate) struct InMemoryRateLimiter {
+        buckets: Mutex<HashMap<(i64, i64), Bucket>>,
+    }
+
+    impl RateLimiter for InMemoryRateLimiter {
+        fn take(
+            &self,
+            bot_id: i64,
+            chat_id: i64,
+            capacity: u32,
+        ) -> BoxFuture<'_, anyhow::Result<RateDecision>> {
+            let mut buckets = self.buckets.lock().unwrap();
+            // time stands still, so nothing refills
+            let (bucket, decision) =
+                take_token(buckets.get(&(bot_id, chat_id)).copied(), capacity, 0);
+            buckets.insert((bot_id, chat_id), bucket);
+
+            futures::future::ok(decision).boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{take_token, InMemoryRateLimiter};
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_bucket_key() {
+        assert_eq!(
+            bucket_key("pm-bot-forwarding-handler", 1, 42),
+            "pm-bot-forwarding-handler-rate-limit-1-42"
+        );
+    }
+
+    /// Messages sent at the given times into one bucket
+    fn decisions(capacity: u32, times: &[i64]) -> Vec<RateDecision> {
+        let mut bucket = None;
+        times
+            .iter()
+            .map(|now| {
+                let (taken, decision) = take_token(bucket, capacity, *now);
+                bucket = Some(taken);
+                decision
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_take_token() {
+        use RateDecision::*;
+
+        assert_eq!(
+            decisions(3, &[0, 0, 0, 0, 0, 0]),
+            vec![
+                Allowed,
+                Allowed,
+                Allowed,
+                Throttled { first: true },
+                Throttled { first: false },
+                Flood
+            ]
+        );
+        // a limit of one floods right after the first message over it
+        assert_eq!(decisions(1, &[0, 0]), vec![Allowed, Flood]);
+
+        // a partial refill leaves the tokens fractional below zero, the sender is told once
+        assert_eq!(
+            decisions(3, &[0, 0, 0, 0, 30_000, 40_000]),
+            vec![
+                Allowed,
+                Allowed,
+                Allowed,
+                Throttled { first: true },
+                Throttled { first: false },
+                Throttled { first: false },
+            ]
+        );
+        // and told again after a message got through
+        assert_eq!(
+            decisions(3, &[0, 0, 0, 0, 60_000, 60_000, 60_000]),
+            vec![
+                Allowed,
+                Allowed,
+                Allowed,
+                Throttled { first: true },
+                Allowed,
+                Allowed,
+                Throttled { first: true },
+            ]
+        );
+    }
+
+    /// Run against a redis server, such as `PEGASUS_TEST_REDIS_URL=redis://127.0.0.1:6379/`
+    #[tokio::test]
+    #[ignore]
+    async fn test_take_script() {
+        let url = std::env::var("PEGASUS_TEST_REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string());
+        let conn = redis::Client::open(url)
+            .unwrap()
+            .get_multiplexed_tokio_connection()
+            .await
+            .unwrap();
+        let limiter = RedisRateLimiter::new("pegasus-test", conn.clone());
+
+        for (chat_id, times) in [
+            vec![0, 0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0, 30_000, 40_000],
+            vec![0, 0, 0, 0, 60_000, 60_000, 60_000],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let chat_id = chat_id as i64;
+            redis::cmd("DEL")
+                .arg(bucket_key("pegasus-test", 1, chat_id))
+                .query_async::<_, ()>(&mut conn.clone())
+                .await
+                .unwrap();
+
+            let mut taken = Vec::new();
+            for now in &times {
+                taken.push(limiter.take_at(1, chat_id, 3, *now).await.unwrap());
+            }
+            assert_eq!(taken, decisions(3, &times));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_rate_limiter() {
+        let limiter = InMemoryRateLimiter::default();
+
+        let mut decisions = Vec::new();
+        for _ in 0..5 {
+            decisions.push(limiter.take(1, 42, 2).await.unwrap());
+        }
+        assert_eq!(
+            decisions,
+            vec![
+                RateDecision::Allowed,
+                RateDecision::Allowed,
+                RateDecision::Throttled { first: true },
+                RateDecision::Flood,
+                RateDecision::Flood,
+            ]
+        );
+
+        // buckets are per bot and sender
+        assert_eq!(limiter.take(2, 42, 2).await.unwrap(), RateDecision::Allowed);
+        assert_eq!(limiter.take(1, 43, 2).await.unwrap(), RateDecision::Allowed);
+    }
+}
@@ -1,26 +1,33 @@
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use sea_orm::DatabaseConnection;
+use teloxide::error_handlers::ErrorHandler;
 use teloxide::prelude::*;
 use teloxide::update_listeners::UpdateListener;
 
+use pegasus_common::bot::instrument::instrument;
 use pegasus_common::bot::state::RedisStorage;
-use pegasus_common::settings::Settings;
+use pegasus_common::health::checks::DispatcherLiveness;
 
 use crate::handlers::{
-    bot_reinitialize_handler, cancel_handler, choose_bot_handler, create_process_handler,
-    list_process_handler, receive_bot_token_handler, receive_confirmation_handler,
-    receive_message_target_handler, start_handler, BotState,
+    bot_captcha_handler, bot_delete_confirmation_handler, bot_delete_handler,
+    bot_edit_target_handler, bot_edit_text_handler, bot_rate_limit_handler,
+    bot_reinitialize_handler, bot_replace_token_handler, bot_retention_handler,
+    bot_rotate_secret_handler, bot_texts_handler, bot_toggle_topics_handler, cancel_handler,
+    choose_bot_handler, create_process_handler, list_process_handler, receive_bot_token_handler,
+    receive_captcha_handler, receive_confirmation_handler, receive_message_target_handler,
+    receive_new_bot_token_handler, receive_new_target_handler, receive_rate_limit_handler,
+    receive_retention_handler, receive_text_handler, start_handler, BotState,
 };
 use crate::services::forwarding_bot::ForwardingBotService;
 
 pub(crate) async fn run<'a, UListener>(
     bot: Bot,
     listener: UListener,
+    error_handler: Arc<dyn ErrorHandler<anyhow::Error> + Send + Sync>,
     redis_storage: Arc<RedisStorage>,
-    db: DatabaseConnection,
-    settings: Settings,
+    forwarding_bot_service: ForwardingBotService,
+    liveness: DispatcherLiveness,
 ) -> anyhow::Result<()>
 where
     UListener: UpdateListener + 'a,
@@ -33,16 +40,53 @@ where
                 .branch(
                     dptree::entry()
                         .filter(|m: Message| m.text().unwrap_or_default() == "/pm_forwarding_bot")
+                        .chain(instrument("start_handler"))
+                        .endpoint(start_handler),
+                )
+                .branch(
+                    dptree::case![BotState::Start]
+                        .chain(instrument("start_handler"))
                         .endpoint(start_handler),
                 )
-                .branch(dptree::case![BotState::Start].endpoint(start_handler))
                 .branch(
                     dptree::case![BotState::CreationReceiveBotToken]
+                        .chain(instrument("receive_bot_token_handler"))
                         .endpoint(receive_bot_token_handler),
                 )
                 .branch(
                     dptree::case![BotState::CreationReceiveMessageTarget { bot_token }]
+                        .chain(instrument("receive_message_target_handler"))
                         .endpoint(receive_message_target_handler),
+                )
+                .branch(
+                    dptree::case![BotState::EditReceiveTargetChat(i64)]
+                        .chain(instrument("receive_new_target_handler"))
+                        .endpoint(receive_new_target_handler),
+                )
+                .branch(
+                    dptree::case![BotState::EditReceiveBotToken(i64)]
+                        .chain(instrument("receive_new_bot_token_handler"))
+                        .endpoint(receive_new_bot_token_handler),
+                )
+                .branch(
+                    dptree::case![BotState::EditReceiveRateLimit(i64)]
+                        .chain(instrument("receive_rate_limit_handler"))
+                        .endpoint(receive_rate_limit_handler),
+                )
+                .branch(
+                    dptree::case![BotState::EditReceiveCaptcha(i64)]
+                        .chain(instrument("receive_captcha_handler"))
+                        .endpoint(receive_captcha_handler),
+                )
+                .branch(
+                    dptree::case![BotState::EditReceiveRetention(i64)]
+                        .chain(instrument("receive_retention_handler"))
+                        .endpoint(receive_retention_handler),
+                )
+                .branch(
+                    dptree::case![BotState::EditReceiveText { bot_id, kind }]
+                        .chain(instrument("receive_text_handler"))
+                        .endpoint(receive_text_handler),
                 ),
         )
         .branch(
@@ -53,11 +97,13 @@ where
                         .filter(|c: CallbackQuery| {
                             c.data.unwrap_or_default() == "forward_bot_creation"
                         })
+                        .chain(instrument("create_process_handler"))
                         .endpoint(create_process_handler),
                 )
                 .branch(
                     dptree::case![BotState::WaitingTopMenu]
                         .filter(|c: CallbackQuery| c.data.unwrap_or_default() == "forward_bot_list")
+                        .chain(instrument("list_process_handler"))
                         .endpoint(list_process_handler),
                 )
                 .branch(
@@ -67,6 +113,7 @@ where
                                 .unwrap_or_default()
                                 .starts_with("forward_bot_list_bot_")
                         })
+                        .chain(instrument("choose_bot_handler"))
                         .endpoint(choose_bot_handler),
                 )
                 .branch(
@@ -74,13 +121,103 @@ where
                         .filter(|c: CallbackQuery| {
                             c.data.unwrap_or_default() == "forward_bot_reinitialize"
                         })
+                        .chain(instrument("bot_reinitialize_handler"))
                         .endpoint(bot_reinitialize_handler),
                 )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_delete"
+                        })
+                        .chain(instrument("bot_delete_handler"))
+                        .endpoint(bot_delete_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_edit_target"
+                        })
+                        .chain(instrument("bot_edit_target_handler"))
+                        .endpoint(bot_edit_target_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_replace_token"
+                        })
+                        .chain(instrument("bot_replace_token_handler"))
+                        .endpoint(bot_replace_token_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_rotate_secret"
+                        })
+                        .chain(instrument("bot_rotate_secret_handler"))
+                        .endpoint(bot_rotate_secret_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_toggle_topics"
+                        })
+                        .chain(instrument("bot_toggle_topics_handler"))
+                        .endpoint(bot_toggle_topics_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_rate_limit"
+                        })
+                        .chain(instrument("bot_rate_limit_handler"))
+                        .endpoint(bot_rate_limit_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_texts"
+                        })
+                        .chain(instrument("bot_texts_handler"))
+                        .endpoint(bot_texts_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default().starts_with("forward_bot_text_")
+                        })
+                        .chain(instrument("bot_edit_text_handler"))
+                        .endpoint(bot_edit_text_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_captcha"
+                        })
+                        .chain(instrument("bot_captcha_handler"))
+                        .endpoint(bot_captcha_handler),
+                )
+                .branch(
+                    dptree::case![BotState::ChooseBotAction(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_retention"
+                        })
+                        .chain(instrument("bot_retention_handler"))
+                        .endpoint(bot_retention_handler),
+                )
+                .branch(
+                    dptree::case![BotState::DeleteConfirmation(i64)]
+                        .filter(|c: CallbackQuery| {
+                            c.data.unwrap_or_default() == "forward_bot_delete_confirm"
+                        })
+                        .chain(instrument("bot_delete_confirmation_handler"))
+                        .endpoint(bot_delete_confirmation_handler),
+                )
                 .branch(
                     dptree::case![BotState::CreationReceiveConfirmation { bot_token, target }]
                         .filter(|c: CallbackQuery| {
                             c.data.unwrap_or_default() == "forward_bot_creation_confirm"
                         })
+                        .chain(instrument("receive_confirmation_handler"))
                         .endpoint(receive_confirmation_handler),
                 )
                 .branch(
@@ -88,16 +225,17 @@ where
                         .filter(|c: CallbackQuery| {
                             c.data.unwrap_or_default() == "forward_bot_cancel"
                         })
+                        .chain(instrument("cancel_handler"))
                         .endpoint(cancel_handler),
                 ),
         );
 
+    liveness.mark_running();
+
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![
-            redis_storage,
-            ForwardingBotService::new(db.clone(), settings.clone())
-        ])
+        .dependencies(dptree::deps![redis_storage, forwarding_bot_service])
         .distribution_function(|_| None::<std::convert::Infallible>)
+        .error_handler(error_handler)
         .build()
         .dispatch_with_listener(
             listener,
@@ -105,5 +243,7 @@ where
         )
         .await;
 
+    liveness.mark_stopped();
+
     Ok(())
 }
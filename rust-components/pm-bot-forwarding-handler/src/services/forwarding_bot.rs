@@ -1,27 +1,60 @@
+use std::sync::Arc;
+
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use reqwest::Url;
 use sea_orm::prelude::*;
-use sea_orm::ActiveValue;
 use teloxide::prelude::*;
+use teloxide::types::{
+    Chat, ChatKind, ChatMemberKind, ChatPublic, PublicChatKind, PublicChatSupergroup,
+};
 use tracing::span;
 
+use pegasus_common::cryptor::{secret_hash, Cryptor};
 use pegasus_common::database::entities;
+use pegasus_common::database::repositories::pm_forwarding_bot::{
+    NewPmForwardingBot, PmForwardingBotRepository, SeaOrmPmForwardingBotRepository,
+    UpdatePmForwardingBot,
+};
 use pegasus_common::settings::Settings;
 
+use crate::captcha::CaptchaSettings;
+use crate::texts::{BotText, MAX_TEXT_LEN};
+
+/// Address of the handler inside the docker-compose network, with a bot api server alongside
+static DEFAULT_PUBLIC_BASE_URL: &str = "http://pm-bot-forwarding-handler:8080/";
+/// Highest rate limit, in messages per minute of a sender
+pub static MAX_RATE_LIMIT: i32 = 1000;
+/// Longest message retention, in days
+pub static MAX_RETENTION_DAYS: i32 = 3650;
+/// Cloud bot api, bots are logged out of it before using a local bot api server
+static CLOUD_API_URL: &str = "https://api.telegram.org/";
+
 #[derive(Clone, Debug)]
 pub struct ForwardingBotService {
-    db: DatabaseConnection,
+    bots: Arc<dyn PmForwardingBotRepository>,
     settings: Settings,
+    cloud_api_url: Url,
 }
 
 impl ForwardingBotService {
-    pub fn new(db: DatabaseConnection, settings: Settings) -> Self {
-        Self { db, settings }
+    pub fn new(db: DatabaseConnection, cryptor: Cryptor, settings: Settings) -> Self {
+        Self::with_repository(
+            Arc::new(SeaOrmPmForwardingBotRepository::new(db, cryptor)),
+            settings,
+        )
+    }
+
+    pub fn with_repository(bots: Arc<dyn PmForwardingBotRepository>, settings: Settings) -> Self {
+        Self {
+            bots,
+            settings,
+            cloud_api_url: Url::parse(CLOUD_API_URL).unwrap(),
+        }
     }
 
     /// Create a new bot client with the given token
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self, token))]
     fn new_bot_client(&self, token: &str) -> anyhow::Result<Bot> {
         let api_url = self
             .settings
@@ -34,19 +67,167 @@ impl ForwardingBotService {
         let client = Bot::new(token).set_api_url(Url::parse(&api_url)?);
         Ok(client)
     }
+
+    /// Url the bot api posts updates of the bot to, named by the webhook id rather than the token
+    fn webhook_url(&self, bot_token: &str) -> anyhow::Result<Url> {
+        let mut base_url = self
+            .settings
+            .pm_forwarding
+            .as_ref()
+            .and_then(|pm_forwarding| pm_forwarding.public_base_url.clone())
+            .unwrap_or_else(|| DEFAULT_PUBLIC_BASE_URL.to_string());
+
+        // without the trailing slash, joining would replace the last path segment
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+
+        let base_url = Url::parse(&base_url)
+            .map_err(|err| anyhow::anyhow!("Invalid public base url {}: {}", base_url, err))?;
+        if !matches!(base_url.scheme(), "http" | "https") {
+            return Err(anyhow::anyhow!(
+                "Invalid public base url {}: scheme must be http or https",
+                base_url
+            ));
+        }
+
+        Ok(base_url.join(&format!("webhook/{}", webhook_id(bot_token)))?)
+    }
+
+    /// Find a bot registered by the user, bots of other users are not found
+    async fn find_user_bot(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        self.bots
+            .find_by_id(bot_id)
+            .await?
+            .filter(|bot| bot.telegram_user_refer == telegram_user_id as i64)
+            .ok_or_else(|| anyhow::anyhow!("Bot record not found"))
+    }
+
+    ///
+    /// Set the webhook of the bot as changed, then save the changes
+    ///
+    /// A bot the bot api refuses keeps its record as it was, a record failing to save gets its
+    /// webhook set back.
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: bot record
+    /// * `changes`: changes of the bot, the token and the webhook secret reach the bot api
+    ///
+    /// returns: `Result<Model, Error>` updated bot record
+    ///
+    async fn initialize_and_update(
+        &self,
+        bot: entities::pm_forwarding_bot::Model,
+        changes: UpdatePmForwardingBot,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        let changed = entities::pm_forwarding_bot::Model {
+            bot_token: changes
+                .bot_token
+                .clone()
+                .unwrap_or_else(|| bot.bot_token.clone()),
+            bot_webhook_secret: changes
+                .bot_webhook_secret
+                .clone()
+                .unwrap_or_else(|| bot.bot_webhook_secret.clone()),
+            ..bot.clone()
+        };
+        self.initialize(&changed).await.map_err(|err| {
+            log::error!("Error initializing bot: {}", err);
+            err
+        })?;
+
+        match self.bots.update(bot.id, changes).await {
+            Ok(updated) => Ok(updated),
+            Err(err) => {
+                if let Err(err) = self.set_webhook(&bot).await {
+                    log::error!("Error setting webhook back: {}", err);
+                }
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Log the bot out of the cloud bot api if it moves to a local bot api server, then set its
+    /// webhook
+    async fn initialize(&self, bot: &entities::pm_forwarding_bot::Model) -> anyhow::Result<()> {
+        // bots move to a local bot api server only once logged out of the cloud one
+        if !is_cloud_api_url(&self.new_bot_client(&bot.bot_token)?.api_url()) {
+            Bot::new(&bot.bot_token)
+                .set_api_url(self.cloud_api_url.clone())
+                .log_out()
+                .await
+                .map_err(|err| {
+                    log::error!("Error logging out bot: {}", err);
+                })
+                .ok();
+        }
+
+        self.set_webhook(bot).await
+    }
+
+    ///
+    /// Check the bot can post into a chat, before it becomes the target chat
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: bot record
+    /// * `chat_id`: chat id
+    ///
+    /// returns: `Result<Chat, Error>` the chat, error if the bot can not see it or post into it
+    ///
+    async fn check_target_chat(
+        &self,
+        bot: &entities::pm_forwarding_bot::Model,
+        chat_id: i64,
+    ) -> anyhow::Result<Chat> {
+        let client = self.new_bot_client(&bot.bot_token)?;
+        let chat = client.get_chat(ChatId(chat_id)).await.map_err(|err| {
+            anyhow::anyhow!(
+                "The bot can not see chat {}, add it first: {}",
+                chat_id,
+                err
+            )
+        })?;
+        // the bot can message users who started it, which the bot api found the chat for
+        if chat.is_private() {
+            return Ok(chat);
+        }
+
+        let bot_user_id = token_bot_id(&bot.bot_token)
+            .and_then(|bot_user_id| bot_user_id.parse().ok())
+            .map(UserId)
+            .ok_or_else(|| anyhow::anyhow!("Malformed bot token"))?;
+        let member = client
+            .get_chat_member(chat.id, bot_user_id)
+            .await
+            .map_err(|err| anyhow::anyhow!("Failed to get the bot in chat {}: {}", chat_id, err))?;
+        if !can_post(&chat, &member.kind) {
+            return Err(anyhow::anyhow!(
+                "The bot can not post in chat {}, let it send messages there first",
+                chat_id
+            ));
+        }
+
+        Ok(chat)
+    }
 }
 
 pub trait IForwardingBotService {
     ///
-    /// Create a new bot record
+    /// Create a new bot record and initialize the bot
     ///
     /// # Arguments
     ///
     /// * `bot_token`: bot token
-    /// * `target_chat_id`: target chat id
+    /// * `target_chat_id`: target chat id, the bot must be able to post into it
     /// * `user_id`: telegram user id
     ///
-    /// returns: `Result<Model, Error>`
+    /// returns: `Result<Model, Error>` bot record, nothing is kept if the bot could not be set up
     ///
     async fn create_bot_record(
         &self,
@@ -56,18 +237,18 @@ pub trait IForwardingBotService {
     ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
 
     ///
-    /// Get bot record by token
+    /// Find the bot record a webhook url belongs to
     ///
     /// # Arguments
     ///
-    /// * `bot_token`: bot token
+    /// * `webhook_id`: webhook id in the url, a bot token in urls set before webhook ids
     ///
-    /// returns: `Result<Model, Error>` bot record model
+    /// returns: `Result<Option<Model>, Error>` bot record model, `None` if no bot has the url
     ///
-    async fn get_bot_record_by_token(
+    async fn find_bot_by_webhook_id(
         &self,
-        bot_token: String,
-    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+        webhook_id: String,
+    ) -> anyhow::Result<Option<entities::pm_forwarding_bot::Model>>;
 
     ///
     /// Check if token exists
@@ -81,7 +262,8 @@ pub trait IForwardingBotService {
     async fn check_token_exist(&self, bot_token: String) -> anyhow::Result<bool>;
 
     ///
-    /// Initialize bot, log out the bot and set the webhook to local api server
+    /// Initialize bot, set the webhook of the bot, logged out of the cloud bot api first when
+    /// running on a local bot api server
     ///
     /// # Arguments
     ///
@@ -91,6 +273,17 @@ pub trait IForwardingBotService {
     ///
     async fn initialize_bot(&self, bot: i64) -> anyhow::Result<()>;
 
+    ///
+    /// Point the webhook of the bot to its current url, without logging the bot out
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: bot record
+    ///
+    /// returns: `Result<(), Error>`
+    ///
+    async fn set_webhook(&self, bot: &entities::pm_forwarding_bot::Model) -> anyhow::Result<()>;
+
     ///
     /// List bots by telegram user id
     ///
@@ -104,6 +297,214 @@ pub trait IForwardingBotService {
         &self,
         telegram_user_id: u64,
     ) -> anyhow::Result<Vec<entities::pm_forwarding_bot::Model>>;
+
+    ///
+    /// Delete a bot of the user, remove its webhook and its message records
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can delete it
+    ///
+    /// returns: `Result<(), Error>`
+    ///
+    async fn delete_bot(&self, bot_id: i64, telegram_user_id: u64) -> anyhow::Result<()>;
+
+    ///
+    /// Forward the messages of a bot of the user to another chat
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    /// * `target_chat_id`: new target chat id
+    ///
+    /// returns: `Result<(Model, bool), Error>` updated bot record and whether topics were switched
+    /// off for a new target chat that is not a forum supergroup, error if the bot can not post
+    /// into the chat or its webhook can not be set
+    ///
+    async fn change_target_chat(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        target_chat_id: i64,
+    ) -> anyhow::Result<(entities::pm_forwarding_bot::Model, bool)>;
+
+    ///
+    /// Replace the token of a bot of the user, after the token was revoked in BotFather
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    /// * `bot_token`: new token of the same bot
+    ///
+    /// returns: `Result<Model, Error>` updated bot record, error if the token belongs to another
+    /// bot, is registered already or is rejected by the bot api
+    ///
+    async fn replace_token(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        bot_token: String,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+
+    ///
+    /// Generate a new webhook secret for a bot of the user
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    ///
+    /// returns: `Result<Model, Error>` updated bot record, the old secret is kept if the webhook
+    /// can not be set with the new one
+    ///
+    async fn rotate_webhook_secret(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+
+    ///
+    /// Switch a bot of the user between one flat target chat and one forum topic per sender
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    ///
+    /// returns: `Result<Model, Error>` updated bot record, error if topics are switched on while
+    /// the target chat is not a forum supergroup
+    ///
+    async fn toggle_topics(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+
+    ///
+    /// Limit the messages each sender may send to a bot of the user, senders sending twice as
+    /// many are muted for a while
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    /// * `rate_limit`: messages per minute, `0` turns rate limiting off
+    ///
+    /// returns: `Result<Model, Error>` updated bot record, error if the limit is out of range
+    ///
+    async fn change_rate_limit(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        rate_limit: i32,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+
+    ///
+    /// Set a text a bot of the user sends its senders
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    /// * `kind`: text to set
+    /// * `text`: new text, empty to send nothing, `None` restores the default text
+    ///
+    /// returns: `Result<Model, Error>` updated bot record, error if the text is too long
+    ///
+    async fn change_text(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        kind: BotText,
+        text: Option<String>,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+
+    ///
+    /// Set how long the message mappings of a bot of the user are kept, replies to older
+    /// messages are no longer delivered to their senders
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    /// * `days`: days to keep messages, `0` keeps them forever, `None` restores the default of
+    ///   the settings
+    ///
+    /// returns: `Result<Model, Error>` updated bot record, error if the days are out of range
+    ///
+    async fn change_retention(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        days: Option<i32>,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+
+    ///
+    /// Turn the captcha first-time senders of a bot of the user solve on or off
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id
+    /// * `telegram_user_id`: telegram user id, only the user who registered the bot can change it
+    /// * `settings`: captcha to send, `None` turns it off and keeps its timeout and retries
+    ///
+    /// returns: `Result<Model, Error>` updated bot record
+    ///
+    async fn change_captcha(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        settings: Option<CaptchaSettings>,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model>;
+}
+
+/// Opaque id of the bot in its webhook url, the token hash, so the token stays out of access logs
+pub fn webhook_id(bot_token: &str) -> String {
+    secret_hash(bot_token)
+}
+
+/// Whether the webhook id is a bot token, as in the urls set before webhook ids were introduced
+pub fn is_legacy_webhook_id(webhook_id: &str) -> bool {
+    webhook_id.contains(':')
+}
+
+/// Id of the bot a token belongs to, the part before the colon
+fn token_bot_id(bot_token: &str) -> Option<&str> {
+    bot_token.split_once(':').map(|(bot_id, _)| bot_id)
+}
+
+/// Whether the chat is a supergroup with topics enabled
+fn is_forum(chat: &Chat) -> bool {
+    matches!(
+        &chat.kind,
+        ChatKind::Public(ChatPublic {
+            kind: PublicChatKind::Supergroup(PublicChatSupergroup { is_forum: true, .. }),
+            ..
+        })
+    )
+}
+
+/// Whether a member of the chat may post messages into it, channels only take admins' posts
+fn can_post(chat: &Chat, member: &ChatMemberKind) -> bool {
+    if chat.is_channel() {
+        return member.can_post_messages();
+    }
+
+    match member {
+        ChatMemberKind::Restricted(restricted) => {
+            restricted.is_member && restricted.can_send_messages
+        }
+        ChatMemberKind::Left | ChatMemberKind::Banned(_) => false,
+        _ => true,
+    }
+}
+
+/// Whether the url is the cloud bot api rather than a local bot api server
+fn is_cloud_api_url(api_url: &Url) -> bool {
+    api_url.host_str() == Some("api.telegram.org")
 }
 
 /// Generate a random webhook secret
@@ -116,81 +517,81 @@ fn random_webhook_secret() -> String {
 }
 
 impl IForwardingBotService for ForwardingBotService {
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self, bot_token))]
     async fn create_bot_record(
         &self,
         bot_token: String,
         target_chat_id: i64,
         user_id: u64,
     ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
-        let bot = entities::pm_forwarding_bot::ActiveModel {
-            bot_token: ActiveValue::Set(bot_token),
-            bot_webhook_secret: ActiveValue::Set(random_webhook_secret()),
-            target_chat_id: ActiveValue::Set(target_chat_id),
-            telegram_user_refer: ActiveValue::Set(user_id as i64),
-            ..Default::default()
-        }
-        .insert(&self.db)
-        .await
-        .map_err(|err| {
-            log::error!("Error creating bot record: {}", err);
-            err
-        })?;
+        let bot = self
+            .bots
+            .create(NewPmForwardingBot {
+                bot_token,
+                bot_webhook_secret: random_webhook_secret(),
+                target_chat_id,
+                telegram_user_refer: user_id as i64,
+            })
+            .await
+            .map_err(|err| {
+                log::error!("Error creating bot record: {}", err);
+                err
+            })?;
 
-        self.initialize_bot(bot.id).await.map_err(|err| {
+        let initialized = async {
+            self.check_target_chat(&bot, target_chat_id).await?;
+            self.initialize(&bot).await
+        }
+        .await;
+        if let Err(err) = initialized {
             log::error!("Error initializing bot: {}", err);
-            err
-        })?;
+            // a bot without its webhook would block registering the token again
+            if let Err(err) = self.bots.delete(bot.id).await {
+                log::error!("Error deleting bot record: {}", err);
+            }
+            return Err(err);
+        }
 
         Ok(bot)
     }
 
-    #[tracing::instrument(err)]
-    async fn get_bot_record_by_token(
+    #[tracing::instrument(err, skip(self, webhook_id))]
+    async fn find_bot_by_webhook_id(
         &self,
-        bot_token: String,
-    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
-        let bot = entities::pm_forwarding_bot::Entity::find()
-            .filter(entities::pm_forwarding_bot::Column::BotToken.eq(bot_token))
-            .one(&self.db)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("Bot not found"))?;
+        webhook_id: String,
+    ) -> anyhow::Result<Option<entities::pm_forwarding_bot::Model>> {
+        let bot = if is_legacy_webhook_id(&webhook_id) {
+            self.bots.find_by_token(webhook_id).await?
+        } else {
+            self.bots.find_by_token_hash(webhook_id).await?
+        };
 
         Ok(bot)
     }
 
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self, bot_token))]
     async fn check_token_exist(&self, bot_token: String) -> anyhow::Result<bool> {
-        let bot = entities::pm_forwarding_bot::Entity::find()
-            .filter(entities::pm_forwarding_bot::Column::BotToken.eq(bot_token))
-            .one(&self.db)
-            .await?;
+        let bot = self.bots.find_by_token(bot_token).await?;
 
         Ok(bot.is_some())
     }
 
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self))]
     async fn initialize_bot(&self, bot_id: i64) -> anyhow::Result<()> {
-        let bot = entities::pm_forwarding_bot::Entity::find_by_id(bot_id)
-            .one(&self.db)
+        let bot = self
+            .bots
+            .find_by_id(bot_id)
             .await?
             .ok_or_else(|| anyhow::anyhow!("Bot record not found"))?;
 
-        let client = Bot::new(&bot.bot_token);
-        client
-            .log_out()
-            .await
-            .map_err(|err| {
-                log::error!("Error logging out bot: {}", err);
-            })
-            .ok();
+        self.initialize(&bot).await
+    }
 
+    #[tracing::instrument(err, skip(self))]
+    async fn set_webhook(&self, bot: &entities::pm_forwarding_bot::Model) -> anyhow::Result<()> {
         let client = self.new_bot_client(&bot.bot_token)?;
         client
-            .set_webhook(Url::parse(&format!(
-                "http://pm-bot-forwarding-handler:8080/webhook/{}",
-                bot.bot_token
-            ))?)
+            .set_webhook(self.webhook_url(&bot.bot_token)?)
             .secret_token(&bot.bot_webhook_secret)
             .await
             .map_err(|err| {
@@ -201,16 +602,962 @@ impl IForwardingBotService for ForwardingBotService {
         Ok(())
     }
 
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self))]
     async fn list_bots(
         &self,
         telegram_user_id: u64,
     ) -> anyhow::Result<Vec<entities::pm_forwarding_bot::Model>> {
-        let bots = entities::pm_forwarding_bot::Entity::find()
-            .filter(entities::pm_forwarding_bot::Column::TelegramUserRefer.eq(telegram_user_id))
-            .all(&self.db)
-            .await?;
+        let bots = self.bots.list_by_user(telegram_user_id as i64).await?;
 
         Ok(bots)
     }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn delete_bot(&self, bot_id: i64, telegram_user_id: u64) -> anyhow::Result<()> {
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        // a revoked token can not delete its webhook, which must not keep the record around
+        let client = self.new_bot_client(&bot.bot_token)?;
+        client
+            .delete_webhook()
+            .await
+            .map_err(|err| {
+                log::error!("Error deleting webhook: {}", err);
+            })
+            .ok();
+
+        self.bots.delete(bot.id).await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn change_target_chat(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        target_chat_id: i64,
+    ) -> anyhow::Result<(entities::pm_forwarding_bot::Model, bool)> {
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+        let chat = self.check_target_chat(&bot, target_chat_id).await?;
+
+        // senders get topics only in a forum supergroup
+        let topics_disabled = bot.topics_enabled && !is_forum(&chat);
+        let bot = self
+            .initialize_and_update(
+                bot,
+                UpdatePmForwardingBot {
+                    target_chat_id: Some(target_chat_id),
+                    topics_enabled: topics_disabled.then_some(false),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        Ok((bot, topics_disabled))
+    }
+
+    #[tracing::instrument(err, skip(self, bot_token))]
+    async fn replace_token(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        bot_token: String,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        // message records and the target chat belong to the bot, not to the token
+        if token_bot_id(&bot_token) != token_bot_id(&bot.bot_token) {
+            return Err(anyhow::anyhow!("The token belongs to another bot"));
+        }
+
+        if let Some(existing) = self.bots.find_by_token(bot_token.clone()).await? {
+            if existing.id != bot.id {
+                return Err(anyhow::anyhow!("Bot token already exists"));
+            }
+        }
+
+        self.new_bot_client(&bot_token)?
+            .get_me()
+            .await
+            .map_err(|err| anyhow::anyhow!("The token is rejected by the bot api: {}", err))?;
+
+        self.initialize_and_update(
+            bot,
+            UpdatePmForwardingBot {
+                bot_token: Some(bot_token),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn rotate_webhook_secret(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        self.initialize_and_update(
+            bot,
+            UpdatePmForwardingBot {
+                bot_webhook_secret: Some(random_webhook_secret()),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn toggle_topics(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        if !bot.topics_enabled {
+            let chat = self
+                .new_bot_client(&bot.bot_token)?
+                .get_chat(ChatId(bot.target_chat_id))
+                .await
+                .map_err(|err| anyhow::anyhow!("Failed to get the target chat: {}", err))?;
+            if !is_forum(&chat) {
+                return Err(anyhow::anyhow!(
+                    "The target chat is not a forum supergroup, enable topics in the group first"
+                ));
+            }
+        }
+
+        Ok(self
+            .bots
+            .update(
+                bot.id,
+                UpdatePmForwardingBot {
+                    topics_enabled: Some(!bot.topics_enabled),
+                    ..Default::default()
+                },
+            )
+            .await?)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn change_rate_limit(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        rate_limit: i32,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        if !(0..=MAX_RATE_LIMIT).contains(&rate_limit) {
+            return Err(anyhow::anyhow!(
+                "The rate limit must be between 0 and {} messages per minute",
+                MAX_RATE_LIMIT
+            ));
+        }
+
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        Ok(self
+            .bots
+            .update(
+                bot.id,
+                UpdatePmForwardingBot {
+                    rate_limit: Some(rate_limit),
+                    ..Default::default()
+                },
+            )
+            .await?)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn change_text(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        kind: BotText,
+        text: Option<String>,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        if text
+            .as_ref()
+            .is_some_and(|text| text.chars().count() > MAX_TEXT_LEN)
+        {
+            return Err(anyhow::anyhow!(
+                "The text must be at most {} characters long",
+                MAX_TEXT_LEN
+            ));
+        }
+
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        Ok(self.bots.update(bot.id, kind.changes(text)).await?)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn change_retention(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        days: Option<i32>,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        if days.is_some_and(|days| !(0..=MAX_RETENTION_DAYS).contains(&days)) {
+            return Err(anyhow::anyhow!(
+                "The retention must be between 0 and {} days",
+                MAX_RETENTION_DAYS
+            ));
+        }
+
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        Ok(self
+            .bots
+            .update(
+                bot.id,
+                UpdatePmForwardingBot {
+                    message_retention_days: Some(days),
+                    ..Default::default()
+                },
+            )
+            .await?)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn change_captcha(
+        &self,
+        bot_id: i64,
+        telegram_user_id: u64,
+        settings: Option<CaptchaSettings>,
+    ) -> anyhow::Result<entities::pm_forwarding_bot::Model> {
+        let bot = self.find_user_bot(bot_id, telegram_user_id).await?;
+
+        let changes = match settings {
+            None => UpdatePmForwardingBot {
+                captcha: Some(None),
+                ..Default::default()
+            },
+            Some(settings) => UpdatePmForwardingBot {
+                captcha: Some(Some(settings.kind.as_str().to_string())),
+                captcha_timeout: settings
+                    .timeout
+                    .map(|timeout| i32::try_from(timeout.as_secs()))
+                    .transpose()?,
+                captcha_retries: settings.retries.map(i32::try_from).transpose()?,
+                ..Default::default()
+            },
+        };
+
+        Ok(self.bots.update(bot.id, changes).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pegasus_common::database::repositories::memory::InMemoryPmForwardingBotRepository;
+
+    use pegasus_common::database::repositories::pm_forwarding_message::{
+        NewPmForwardingMessage, PmForwardingMessageRepository, SeaOrmPmForwardingMessageRepository,
+    };
+    use pegasus_common::settings::PmForwarding;
+    use serde_json::json;
+
+    use crate::captcha::CaptchaKind;
+    use crate::services::testing::{sqlite_database, unreachable_api_settings, MockBotApi};
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn new_service() -> (ForwardingBotService, Arc<InMemoryPmForwardingBotRepository>) {
+        let bots = Arc::new(InMemoryPmForwardingBotRepository::new());
+        let service =
+            ForwardingBotService::with_repository(bots.clone(), unreachable_api_settings());
+        (service, bots)
+    }
+
+    /// Service on a mock bot api, which it logs bots out of as the cloud bot api too
+    fn new_service_with_api() -> (
+        ForwardingBotService,
+        Arc<InMemoryPmForwardingBotRepository>,
+        MockBotApi,
+    ) {
+        let api = MockBotApi::start();
+        let bots = Arc::new(InMemoryPmForwardingBotRepository::new());
+        let mut service = ForwardingBotService::with_repository(bots.clone(), api.settings());
+        service.cloud_api_url = api.url();
+        (service, bots, api)
+    }
+
+    fn new_bot(bot_token: &str, telegram_user_refer: i64) -> NewPmForwardingBot {
+        NewPmForwardingBot {
+            bot_token: bot_token.to_string(),
+            bot_webhook_secret: random_webhook_secret(),
+            target_chat_id: -100,
+            telegram_user_refer,
+        }
+    }
+
+    #[test]
+    fn test_random_webhook_secret() {
+        let secret = random_webhook_secret();
+        assert_eq!(secret.len(), 64);
+        assert!(secret.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(secret, random_webhook_secret());
+    }
+
+    #[tokio::test]
+    async fn test_find_bot_by_token() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        assert_eq!(
+            service
+                .find_bot_by_webhook_id(webhook_id("1:token"))
+                .await
+                .unwrap(),
+            Some(bot)
+        );
+        assert!(service
+            .find_bot_by_webhook_id(webhook_id("2:token"))
+            .await
+            .unwrap()
+            .is_none());
+
+        assert!(service
+            .check_token_exist("1:token".to_string())
+            .await
+            .unwrap());
+        assert!(!service
+            .check_token_exist("2:token".to_string())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_create_bot_record() {
+        let (service, bots) = new_service();
+        bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        // the duplicated token is rejected before the bot is initialized
+        assert!(service
+            .create_bot_record("1:token".to_string(), -100, 2)
+            .await
+            .is_err());
+        assert_eq!(bots.rows().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_bot_record_cleanup() {
+        let (service, bots, api) = new_service_with_api();
+
+        // target chats the bot can not post into are refused
+        api.fail("getChat", true);
+        assert!(service
+            .create_bot_record("1:token".to_string(), -100, 1)
+            .await
+            .is_err());
+        assert!(bots.rows().is_empty());
+        assert!(!api.methods().contains(&"setWebhook".to_string()));
+        api.fail("getChat", false);
+
+        // the record of a bot without webhook is removed, so the token can be registered again
+        api.fail("setWebhook", true);
+        assert!(service
+            .create_bot_record("1:token".to_string(), -100, 1)
+            .await
+            .is_err());
+        assert!(bots.rows().is_empty());
+        api.fail("setWebhook", false);
+
+        api.clear();
+        let bot = service
+            .create_bot_record("1:token".to_string(), -100, 1)
+            .await
+            .unwrap();
+        assert_eq!(bots.rows(), vec![bot]);
+        assert_eq!(
+            api.methods()
+                .into_iter()
+                .filter(|method| method != "logOut")
+                .collect::<Vec<_>>(),
+            vec!["getChat", "getChatMember", "setWebhook"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_bots() {
+        let (service, bots) = new_service();
+        let first = bots.create(new_bot("1:token", 1)).await.unwrap();
+        bots.create(new_bot("2:token", 2)).await.unwrap();
+        let third = bots.create(new_bot("3:token", 1)).await.unwrap();
+
+        assert_eq!(service.list_bots(1).await.unwrap(), vec![first, third]);
+        assert!(service.list_bots(3).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_webhook_url() {
+        let (service, _) = new_service();
+        assert_eq!(
+            service.webhook_url("1:token").unwrap().as_str(),
+            format!(
+                "http://pm-bot-forwarding-handler:8080/webhook/{}",
+                webhook_id("1:token")
+            )
+        );
+
+        let with_base_url = |public_base_url: &str| {
+            let mut settings = unreachable_api_settings();
+            settings.pm_forwarding = Some(PmForwarding {
+                public_base_url: Some(public_base_url.to_string()),
+                ..Default::default()
+            });
+            ForwardingBotService::with_repository(
+                Arc::new(InMemoryPmForwardingBotRepository::new()),
+                settings,
+            )
+        };
+
+        assert_eq!(
+            with_base_url("https://example.com/pegasus/pm")
+                .webhook_url("1:token")
+                .unwrap()
+                .as_str(),
+            format!(
+                "https://example.com/pegasus/pm/webhook/{}",
+                webhook_id("1:token")
+            )
+        );
+        assert_eq!(
+            with_base_url("https://example.com/")
+                .webhook_url("1:token")
+                .unwrap()
+                .as_str(),
+            format!("https://example.com/webhook/{}", webhook_id("1:token"))
+        );
+        assert!(with_base_url("example.com").webhook_url("1:token").is_err());
+        assert!(with_base_url("ftp://example.com")
+            .webhook_url("1:token")
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_find_bot_by_webhook_id() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        let id = webhook_id("1:token");
+        assert_eq!(id.len(), 64);
+        assert!(!is_legacy_webhook_id(&id));
+        assert_eq!(
+            service.find_bot_by_webhook_id(id).await.unwrap(),
+            Some(bot.clone())
+        );
+
+        // urls set before webhook ids still reach the bot
+        assert!(is_legacy_webhook_id("1:token"));
+        assert_eq!(
+            service
+                .find_bot_by_webhook_id("1:token".to_string())
+                .await
+                .unwrap(),
+            Some(bot)
+        );
+
+        assert_eq!(
+            service
+                .find_bot_by_webhook_id(webhook_id("2:token"))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_token_bot_id() {
+        assert_eq!(token_bot_id("123:token"), Some("123"));
+        assert_eq!(token_bot_id("123:token:with:colons"), Some("123"));
+        assert_eq!(token_bot_id("token"), None);
+    }
+
+    #[test]
+    fn test_is_cloud_api_url() {
+        let is_cloud = |url: &str| is_cloud_api_url(&Url::parse(url).unwrap());
+
+        assert!(is_cloud("https://api.telegram.org"));
+        assert!(is_cloud("https://api.telegram.org/"));
+        assert!(!is_cloud("http://telegram-bot-api:8081/"));
+        assert!(!is_cloud("http://127.0.0.1:9/"));
+    }
+
+    #[tokio::test]
+    async fn test_edit_bot_validation() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+        bots.create(new_bot("2:token", 1)).await.unwrap();
+
+        // bots of other users are not found
+        assert!(service.change_target_chat(bot.id, 2, -200).await.is_err());
+        assert!(service.rotate_webhook_secret(bot.id, 2).await.is_err());
+        assert!(service
+            .replace_token(bot.id, 2, "1:new-token".to_string())
+            .await
+            .is_err());
+
+        // tokens of another bot are rejected before the bot api is asked
+        let err = service
+            .replace_token(bot.id, 1, "2:token".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "The token belongs to another bot");
+
+        // the bot api is unreachable, the token is not saved
+        let err = service
+            .replace_token(bot.id, 1, "1:new-token".to_string())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("The token is rejected"));
+        assert_eq!(bots.rows()[0].bot_token, "1:token");
+    }
+
+    #[tokio::test]
+    async fn test_rotate_webhook_secret() {
+        let (service, bots, api) = new_service_with_api();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        // the bot api refuses the new secret, the old one is kept
+        api.fail("setWebhook", true);
+        assert!(service.rotate_webhook_secret(bot.id, 1).await.is_err());
+        assert_eq!(bots.rows()[0].bot_webhook_secret, bot.bot_webhook_secret);
+        let refused = api.calls_of("setWebhook").pop().unwrap();
+        assert_ne!(refused["secret_token"], json!(bot.bot_webhook_secret));
+
+        api.fail("setWebhook", false);
+        api.clear();
+        let rotated = service.rotate_webhook_secret(bot.id, 1).await.unwrap();
+        assert_ne!(rotated.bot_webhook_secret, bot.bot_webhook_secret);
+        assert_eq!(bots.rows()[0], rotated);
+        // not the cloud bot api, so logged out of it first
+        assert_eq!(api.methods(), vec!["logOut", "setWebhook"]);
+        let set = api.calls_of("setWebhook").pop().unwrap();
+        assert_eq!(set["secret_token"], json!(rotated.bot_webhook_secret));
+        assert_eq!(
+            set["url"],
+            json!(service.webhook_url("1:token").unwrap().as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_target_chat() {
+        let (service, bots, api) = new_service_with_api();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        // chats the bot can not see or post into are refused before anything is saved
+        api.fail("getChat", true);
+        let err = service
+            .change_target_chat(bot.id, 1, -200)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("The bot can not see chat -200"));
+        api.fail("getChat", false);
+
+        for status in [
+            json!({"status": "left"}),
+            json!({"status": "kicked", "until_date": 0}),
+        ] {
+            let mut member = status;
+            member["user"] = json!({"id": 1, "is_bot": true, "first_name": "Bot"});
+            api.answer("getChatMember", member);
+            let err = service
+                .change_target_chat(bot.id, 1, -200)
+                .await
+                .unwrap_err();
+            assert!(err
+                .to_string()
+                .starts_with("The bot can not post in chat -200"));
+        }
+        // channels only take the posts of admins
+        api.answer(
+            "getChat",
+            json!({"id": -200, "type": "channel", "title": "News"}),
+        );
+        api.answer(
+            "getChatMember",
+            json!({"status": "member", "user": {"id": 1, "is_bot": true, "first_name": "Bot"}}),
+        );
+        assert!(service.change_target_chat(bot.id, 1, -200).await.is_err());
+        assert!(!api.methods().contains(&"setWebhook".to_string()));
+        assert_eq!(bots.rows()[0].target_chat_id, -100);
+
+        // the bot itself is asked about
+        let member = api.calls_of("getChatMember").pop().unwrap();
+        assert_eq!(
+            (&member["chat_id"], &member["user_id"]),
+            (&json!(-200), &json!(1))
+        );
+
+        // the webhook is set before the change is saved
+        api.answer(
+            "getChat",
+            json!({"id": -200, "type": "supergroup", "title": "Support"}),
+        );
+        api.fail("setWebhook", true);
+        assert!(service.change_target_chat(bot.id, 1, -200).await.is_err());
+        assert_eq!(bots.rows()[0].target_chat_id, -100);
+
+        api.fail("setWebhook", false);
+        let (changed, topics_disabled) = service.change_target_chat(bot.id, 1, -200).await.unwrap();
+        assert_eq!((changed.target_chat_id, topics_disabled), (-200, false));
+        assert_eq!(bots.rows()[0].target_chat_id, -200);
+
+        // private chats of users who started the bot are fine as they are
+        api.answer(
+            "getChat",
+            json!({"id": 42, "type": "private", "first_name": "Alice"}),
+        );
+        api.clear();
+        service.change_target_chat(bot.id, 1, 42).await.unwrap();
+        assert_eq!(api.methods(), vec!["getChat", "logOut", "setWebhook"]);
+    }
+
+    #[tokio::test]
+    async fn test_change_target_chat_topics() {
+        let (service, bots, api) = new_service_with_api();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+        bots.update(
+            bot.id,
+            UpdatePmForwardingBot {
+                topics_enabled: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // another forum keeps topics on
+        api.answer(
+            "getChat",
+            json!({"id": -200, "type": "supergroup", "title": "Support", "is_forum": true}),
+        );
+        let (changed, topics_disabled) = service.change_target_chat(bot.id, 1, -200).await.unwrap();
+        assert!(changed.topics_enabled && !topics_disabled);
+
+        // a group without topics turns them off
+        api.answer(
+            "getChat",
+            json!({"id": -300, "type": "supergroup", "title": "Support"}),
+        );
+        let (changed, topics_disabled) = service.change_target_chat(bot.id, 1, -300).await.unwrap();
+        assert!(!changed.topics_enabled && topics_disabled);
+        assert!(!bots.rows()[0].topics_enabled);
+
+        // off already, nothing to tell
+        let (_, topics_disabled) = service.change_target_chat(bot.id, 1, -300).await.unwrap();
+        assert!(!topics_disabled);
+    }
+
+    #[tokio::test]
+    async fn test_toggle_topics() {
+        let (service, bots, api) = new_service_with_api();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        assert!(service.toggle_topics(bot.id, 2).await.is_err());
+
+        // the target chat can not be checked, topics stay off
+        api.fail("getChat", true);
+        let err = service.toggle_topics(bot.id, 1).await.unwrap_err();
+        assert!(err.to_string().starts_with("Failed to get the target chat"));
+        assert!(!bots.rows()[0].topics_enabled);
+        api.fail("getChat", false);
+
+        // not a forum
+        let err = service.toggle_topics(bot.id, 1).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("The target chat is not a forum"));
+        assert!(!bots.rows()[0].topics_enabled);
+
+        api.answer(
+            "getChat",
+            json!({"id": -100, "type": "supergroup", "title": "Support", "is_forum": true}),
+        );
+        assert!(
+            service
+                .toggle_topics(bot.id, 1)
+                .await
+                .unwrap()
+                .topics_enabled
+        );
+        assert_eq!(api.calls_of("getChat").pop().unwrap()["chat_id"], -100);
+
+        // switching off needs no forum
+        api.clear();
+        assert!(
+            !service
+                .toggle_topics(bot.id, 1)
+                .await
+                .unwrap()
+                .topics_enabled
+        );
+        assert!(api.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_change_rate_limit() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        assert!(service.change_rate_limit(bot.id, 2, 5).await.is_err());
+        assert!(service.change_rate_limit(bot.id, 1, -1).await.is_err());
+        assert!(service
+            .change_rate_limit(bot.id, 1, MAX_RATE_LIMIT + 1)
+            .await
+            .is_err());
+        assert_eq!(bots.rows()[0].rate_limit, 20);
+
+        assert_eq!(
+            service
+                .change_rate_limit(bot.id, 1, 5)
+                .await
+                .unwrap()
+                .rate_limit,
+            5
+        );
+        assert_eq!(
+            service
+                .change_rate_limit(bot.id, 1, 0)
+                .await
+                .unwrap()
+                .rate_limit,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_retention() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        assert!(service.change_retention(bot.id, 2, Some(5)).await.is_err());
+        assert!(service.change_retention(bot.id, 1, Some(-1)).await.is_err());
+        assert!(service
+            .change_retention(bot.id, 1, Some(MAX_RETENTION_DAYS + 1))
+            .await
+            .is_err());
+        assert_eq!(bots.rows()[0].message_retention_days, None);
+
+        for days in [Some(30), Some(0), None] {
+            assert_eq!(
+                service
+                    .change_retention(bot.id, 1, days)
+                    .await
+                    .unwrap()
+                    .message_retention_days,
+                days
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_text() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        assert!(service
+            .change_text(bot.id, 2, BotText::Welcome, Some("Hello".to_string()))
+            .await
+            .is_err());
+        assert!(service
+            .change_text(
+                bot.id,
+                1,
+                BotText::Welcome,
+                Some("a".repeat(MAX_TEXT_LEN + 1))
+            )
+            .await
+            .is_err());
+        assert_eq!(bots.rows()[0].welcome_text, None);
+
+        let bot = service
+            .change_text(bot.id, 1, BotText::Welcome, Some("Hello".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(bot.welcome_text.as_deref(), Some("Hello"));
+        let bot = service
+            .change_text(bot.id, 1, BotText::Ack, Some(String::new()))
+            .await
+            .unwrap();
+        assert_eq!(BotText::Ack.text(&bot), None);
+        // the welcome text is kept
+        assert_eq!(BotText::Welcome.text(&bot), Some("Hello"));
+
+        let bot = service
+            .change_text(bot.id, 1, BotText::Ack, None)
+            .await
+            .unwrap();
+        assert_eq!(bot.ack_text, None);
+    }
+
+    #[tokio::test]
+    async fn test_change_captcha() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+        let settings = CaptchaSettings {
+            kind: CaptchaKind::Emoji,
+            timeout: Some(std::time::Duration::from_secs(600)),
+            retries: Some(2),
+        };
+
+        assert!(service
+            .change_captcha(bot.id, 2, Some(settings))
+            .await
+            .is_err());
+        assert_eq!(bots.rows()[0].captcha, None);
+
+        let bot = service
+            .change_captcha(bot.id, 1, Some(settings))
+            .await
+            .unwrap();
+        assert_eq!(
+            (
+                bot.captcha.as_deref(),
+                bot.captcha_timeout,
+                bot.captcha_retries
+            ),
+            (Some("emoji"), 600, 2)
+        );
+
+        // the timeout and retries are kept unless given
+        let bot = service
+            .change_captcha(
+                bot.id,
+                1,
+                Some(CaptchaSettings {
+                    kind: CaptchaKind::Math,
+                    timeout: None,
+                    retries: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            (
+                bot.captcha.as_deref(),
+                bot.captcha_timeout,
+                bot.captcha_retries
+            ),
+            (Some("math"), 600, 2)
+        );
+
+        let bot = service.change_captcha(bot.id, 1, None).await.unwrap();
+        assert_eq!(bot.captcha, None);
+        assert_eq!(bot.captcha_retries, 2);
+    }
+
+    #[test]
+    fn test_is_forum() {
+        let chat = |extra: serde_json::Value| {
+            let mut chat =
+                serde_json::json!({"id": -100, "type": "supergroup", "title": "Support"});
+            chat.as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_str::<Chat>(&chat.to_string()).unwrap()
+        };
+
+        assert!(is_forum(&chat(serde_json::json!({"is_forum": true}))));
+        assert!(!is_forum(&chat(serde_json::json!({}))));
+        assert!(!is_forum(
+            &serde_json::from_str::<Chat>(
+                &serde_json::json!({"id": 42, "type": "private", "first_name": "Alice"})
+                    .to_string()
+            )
+            .unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_bot() {
+        let (service, bots) = new_service();
+        let bot = bots.create(new_bot("1:token", 1)).await.unwrap();
+
+        // only the user who registered the bot can delete it
+        assert!(service.delete_bot(bot.id, 2).await.is_err());
+        assert_eq!(bots.rows().len(), 1);
+
+        // the webhook can not be deleted, the record is deleted anyway
+        service.delete_bot(bot.id, 1).await.unwrap();
+        assert!(bots.rows().is_empty());
+        assert!(service.delete_bot(bot.id, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bot_records_sqlite() {
+        let db = sqlite_database().await;
+        let bots = SeaOrmPmForwardingBotRepository::new(db.clone(), Cryptor::new(None).unwrap());
+        let service =
+            ForwardingBotService::new(db.clone(), Cryptor::new(None).unwrap(), Settings::default());
+
+        let first = bots.create(new_bot("1:token", 1)).await.unwrap();
+        bots.create(new_bot("2:token", 2)).await.unwrap();
+        assert!(bots.create(new_bot("1:token", 3)).await.is_err());
+
+        assert_eq!(
+            service
+                .find_bot_by_webhook_id(webhook_id("1:token"))
+                .await
+                .unwrap(),
+            Some(first.clone())
+        );
+        assert!(!service
+            .check_token_exist("3:token".to_string())
+            .await
+            .unwrap());
+        assert_eq!(service.list_bots(1).await.unwrap(), vec![first.clone()]);
+
+        let updated = bots
+            .update(
+                first.id,
+                UpdatePmForwardingBot {
+                    bot_token: Some("1:new-token".to_string()),
+                    target_chat_id: Some(-200),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.target_chat_id, -200);
+        assert_eq!(updated.bot_webhook_secret, first.bot_webhook_secret);
+        assert_eq!(
+            service
+                .find_bot_by_webhook_id(webhook_id("1:new-token"))
+                .await
+                .unwrap(),
+            Some(updated)
+        );
+        assert!(!service
+            .check_token_exist("1:token".to_string())
+            .await
+            .unwrap());
+
+        let messages = SeaOrmPmForwardingMessageRepository::new(db.clone());
+        messages
+            .create(NewPmForwardingMessage {
+                bot_id: first.id,
+                target_chat_id: first.target_chat_id,
+                telegram_chat_id: 42,
+                telegram_message_id: 7,
+                forward_telegram_message_id: 100,
+            })
+            .await
+            .unwrap();
+
+        assert!(bots.delete(first.id).await.unwrap());
+        assert!(bots.find_by_id(first.id).await.unwrap().is_none());
+        assert!(messages
+            .find_by_forward_message_id(first.id, first.target_chat_id, 100)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(bots.list_all().await.unwrap().len(), 1);
+    }
 }
@@ -1,24 +1,179 @@
+use std::sync::Arc;
+
+use chrono::Utc;
 use reqwest::Url;
 use sea_orm::prelude::*;
-use sea_orm::ActiveValue;
 use teloxide::prelude::*;
-use teloxide::types::{InputFile, MediaKind, MessageId, MessageKind, UpdateKind};
+use teloxide::requests::HasPayload;
+use teloxide::types::{
+    CallbackQuery, InputFile, MessageEntity, MessageId, MessageKind, UpdateKind, User,
+};
+use teloxide::{ApiError, RequestError};
 
+use pegasus_common::cryptor::Cryptor;
 use pegasus_common::database::entities;
+use pegasus_common::database::repositories::pm_forwarding_block::{
+    NewPmForwardingBlock, PmForwardingBlockRepository, SeaOrmPmForwardingBlockRepository,
+};
+use pegasus_common::database::repositories::pm_forwarding_bot::{
+    PmForwardingBotRepository, SeaOrmPmForwardingBotRepository, UpdatePmForwardingBot,
+};
+use pegasus_common::database::repositories::pm_forwarding_message::{
+    NewPmForwardingMessage, PmForwardingMessageRepository, SeaOrmPmForwardingMessageRepository,
+};
+use pegasus_common::database::repositories::pm_forwarding_reply::{
+    NewPmForwardingReply, PmForwardingReplyRepository, SeaOrmPmForwardingReplyRepository,
+};
+use pegasus_common::database::repositories::pm_forwarding_topic::{
+    NewPmForwardingTopic, PmForwardingTopicRepository, SeaOrmPmForwardingTopicRepository,
+};
 use pegasus_common::settings::Settings;
 
+use crate::captcha::{
+    check_answer, CaptchaAnswer, CaptchaKind, CaptchaState, CaptchaStore, Challenge,
+    CAPTCHA_CALLBACK_PREFIX,
+};
+use crate::media_group::{
+    album_media, input_media, MediaGroupBuffer, MediaGroupPart, MEDIA_GROUP_WAIT,
+};
+use crate::moderation::{
+    block_line, blocks_csv, format_time, parse_moderation_command, ModerationCommand,
+};
+use crate::rate_limit::{RateDecision, RateLimiter, FLOOD_MUTE};
+use crate::texts::BotText;
+
 #[derive(Clone, Debug)]
 pub struct ForwardingMessageService {
-    db: DatabaseConnection,
+    bots: Arc<dyn PmForwardingBotRepository>,
+    messages: Arc<dyn PmForwardingMessageRepository>,
+    replies: Arc<dyn PmForwardingReplyRepository>,
+    topics: Arc<dyn PmForwardingTopicRepository>,
+    blocks: Arc<dyn PmForwardingBlockRepository>,
+    media_groups: Arc<dyn MediaGroupBuffer>,
+    rate_limiter: Arc<dyn RateLimiter>,
+    captchas: Arc<dyn CaptchaStore>,
     settings: Settings,
 }
 
+static DELETE_COMMAND: &str = "/delete";
+static START_COMMAND: &str = "/start";
+/// Blue, one of the colors the bot api allows for topic icons
+static TOPIC_ICON_COLOR: u32 = 0x6FB9F0;
+/// Topic names are limited to 128 characters
+static TOPIC_NAME_MAX_LEN: usize = 128;
+static NOT_FORWARDED_HINT: &str =
+    "This message isn't a forwarded one, reply to a forwarded message to answer its sender";
+/// Blocked senders listed in the target chat, the attached export has all of them
+static BLOCKED_LIST_LEN: usize = 50;
+static SLOW_DOWN_HINT: &str =
+    "You are sending messages too fast, they are not forwarded, please wait a minute";
+static CAPTCHA_PROMPT: &str =
+    "Please solve this captcha to have your messages forwarded, then send your message again";
+static CAPTCHA_VERIFIED: &str = "Thanks, you can send your messages now";
+static CAPTCHA_WRONG: &str = "Wrong answer, please try again";
+static CAPTCHA_FAILED: &str = "Too many wrong answers, please send a message again later";
+static CAPTCHA_EXPIRED: &str = "This captcha expired, send a message to get a new one";
+
+/// What became of a message sent to a bot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Forwarded {
+    Sent,
+    /// edit of a reply applied to its copy
+    Edited,
+    /// copy of a reply deleted
+    Deleted,
+    /// reply in the target chat to a message that was not forwarded by the bot
+    UnknownReply,
+    /// dropped, the sender is blocked
+    Blocked,
+    /// dropped, the sender has not solved the captcha of the bot yet
+    Unverified,
+    /// dropped, the sender is over the rate limit, `first` for the first message over it
+    Throttled {
+        first: bool,
+    },
+    /// moderation command of the target chat, answered already
+    Moderated,
+    /// forwarded with the rest of its album, by a task the first message of it scheduled
+    Collected,
+    /// `/start` of a sender, answered with the welcome text
+    Started,
+    /// not meant for the target chat, such as commands
+    Ignored,
+}
+
+/// Error of a message nothing was posted for yet, a redelivery of its update can try it again
+/// without posting anything twice
+#[derive(Debug)]
+struct NothingPosted(anyhow::Error);
+
+impl NothingPosted {
+    fn wrap(err: impl Into<anyhow::Error>) -> anyhow::Error {
+        anyhow::Error::new(NothingPosted(err.into()))
+    }
+}
+
+impl std::fmt::Display for NothingPosted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for NothingPosted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
 impl ForwardingMessageService {
-    pub fn new(db: DatabaseConnection, settings: Settings) -> Self {
-        Self { db, settings }
+    pub fn new(
+        db: DatabaseConnection,
+        cryptor: Cryptor,
+        media_groups: Arc<dyn MediaGroupBuffer>,
+        rate_limiter: Arc<dyn RateLimiter>,
+        captchas: Arc<dyn CaptchaStore>,
+        settings: Settings,
+    ) -> Self {
+        Self::with_repositories(
+            Arc::new(SeaOrmPmForwardingBotRepository::new(db.clone(), cryptor)),
+            Arc::new(SeaOrmPmForwardingMessageRepository::new(db.clone())),
+            Arc::new(SeaOrmPmForwardingReplyRepository::new(db.clone())),
+            Arc::new(SeaOrmPmForwardingTopicRepository::new(db.clone())),
+            Arc::new(SeaOrmPmForwardingBlockRepository::new(db)),
+            media_groups,
+            rate_limiter,
+            captchas,
+            settings,
+        )
+    }
+
+    /// Service on the given repositories and redis backed parts, for tests to swap them
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_repositories(
+        bots: Arc<dyn PmForwardingBotRepository>,
+        messages: Arc<dyn PmForwardingMessageRepository>,
+        replies: Arc<dyn PmForwardingReplyRepository>,
+        topics: Arc<dyn PmForwardingTopicRepository>,
+        blocks: Arc<dyn PmForwardingBlockRepository>,
+        media_groups: Arc<dyn MediaGroupBuffer>,
+        rate_limiter: Arc<dyn RateLimiter>,
+        captchas: Arc<dyn CaptchaStore>,
+        settings: Settings,
+    ) -> Self {
+        Self {
+            bots,
+            messages,
+            replies,
+            topics,
+            blocks,
+            media_groups,
+            rate_limiter,
+            captchas,
+            settings,
+        }
     }
 
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self, token))]
     fn new_bot_client(&self, token: &str) -> anyhow::Result<Bot> {
         let api_url = self
             .settings
@@ -42,26 +197,31 @@ pub trait IForwardingMessageService {
     /// * `bot_id`: bot id stored in the database
     /// * `update`: telegram bot update content
     ///
-    /// returns: `Result<(), Error>`
+    /// returns: `Result<(), Error>` fails only before anything was sent, so a redelivery can retry it
     ///
     async fn handle_update_income(&self, bot_id: i64, update: Update) -> anyhow::Result<()>;
 }
 
 impl IForwardingMessageService for ForwardingMessageService {
-    #[tracing::instrument(err)]
+    #[tracing::instrument(err, skip(self))]
     async fn handle_update_income(&self, bot_id: i64, update: Update) -> anyhow::Result<()> {
-        let bot = entities::pm_forwarding_bot::Entity::find()
-            .filter(entities::pm_forwarding_bot::Column::Id.eq(bot_id))
-            .one(&self.db)
+        let bot = self
+            .bots
+            .find_by_id(bot_id)
             .await?
             .ok_or_else(|| anyhow::anyhow!("Bot record not found"))?;
 
+        if let UpdateKind::CallbackQuery(query) = &update.kind {
+            return self.handle_captcha_answer(bot, query.clone()).await;
+        }
+
         let chat = update
             .chat()
             .ok_or_else(|| anyhow::anyhow!("Missing chat"))?;
 
-        let message_id = match &update.kind {
-            UpdateKind::Message(m) => m.id,
+        let (message_id, edited) = match &update.kind {
+            UpdateKind::Message(m) => (m.id, false),
+            UpdateKind::EditedMessage(m) => (m.id, true),
             _ => {
                 // ignore non-message updates
                 log::debug!("Ignoring non-message update from chat {}", &chat.id.0);
@@ -70,11 +230,13 @@ impl IForwardingMessageService for ForwardingMessageService {
         };
 
         let client = self.new_bot_client(&bot.bot_token)?;
+        let bot_info = bot.clone();
+        let from_target_chat = chat.id.0 == bot.target_chat_id;
 
-        let r = if chat.id.0 == bot.target_chat_id {
+        let r = if from_target_chat {
             log::debug!("Handling message reply from target chat {}", &chat.id.0);
             self.handle_target_chat_message(bot, update.clone()).await
-        } else if chat.is_private() {
+        } else if chat.is_private() && !edited {
             log::debug!("Handling message from chat {}", &chat.id.0);
             self.handle_forward_message(bot, update.clone()).await
         } else {
@@ -82,210 +244,2700 @@ impl IForwardingMessageService for ForwardingMessageService {
             return Ok(());
         };
 
-        match r {
-            Err(err) => {
-                log::error!("Error handling message: {}, chat_id: {}", err, &chat.id.0);
-                client
-                    .send_message(chat.id, format!("Error handling message: {}", err))
-                    .reply_to_message_id(message_id)
-                    .await?;
-            }
-            Ok(_) => {
-                log::debug!("Message handled successfully, chat_id: {}", &chat.id.0);
-                client
-                    .send_message(chat.id, "Message sent")
-                    .reply_to_message_id(message_id)
-                    .await?;
-            }
+        if r.as_ref().is_err_and(|err| err.is::<NothingPosted>()) {
+            // requeued, the chat is told only if the redelivery fails too
+            return r.map(|_| ());
         }
+        // once the message may be out, failing here would forward it again on redelivery
+        self.answer_outcome(&client, &bot_info, chat.id, Some(message_id), r)
+            .await;
 
         Ok(())
     }
 }
 
 impl ForwardingMessageService {
-    #[tracing::instrument(err)]
+    ///
+    /// Answer the chat a message came from about what became of it
+    ///
+    /// # Arguments
+    ///
+    /// * `client`: client of the bot
+    /// * `bot_info`: bot the message was sent to
+    /// * `chat_id`: chat the message came from, the target chat or a sender
+    /// * `reply_to`: message the answer replies to
+    /// * `outcome`: what became of the message
+    ///
+    /// returns: `()` notices are best effort, failures are only logged
+    ///
+    async fn answer_outcome(
+        &self,
+        client: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        chat_id: ChatId,
+        reply_to: Option<MessageId>,
+        outcome: anyhow::Result<Forwarded>,
+    ) {
+        let from_target_chat = chat_id.0 == bot_info.target_chat_id;
+        match outcome {
+            Err(err) => {
+                log::error!("Error handling message: {}, chat_id: {}", err, &chat_id.0);
+                // the target chat is the owner's, senders only get the error text of the bot
+                let text = if from_target_chat {
+                    Some(format!("Error handling message: {}", err))
+                } else {
+                    BotText::Error.text(bot_info).map(str::to_string)
+                };
+                if let Some(text) = text {
+                    send_notice(client, chat_id, text, reply_to).await;
+                }
+            }
+            Ok(Forwarded::Ignored) => {}
+            Ok(Forwarded::Edited) => {
+                log::debug!("Reply edit mirrored, chat_id: {}", &chat_id.0);
+            }
+            Ok(Forwarded::Deleted) => {
+                send_notice(client, chat_id, "Message deleted", reply_to).await;
+            }
+            Ok(Forwarded::UnknownReply) => {
+                send_notice(client, chat_id, NOT_FORWARDED_HINT, reply_to).await;
+            }
+            Ok(Forwarded::Blocked) => {
+                log::debug!("Message of blocked chat {} dropped", &chat_id.0);
+                if let Some(block_notice) = bot_info.block_notice.as_deref() {
+                    send_notice(client, chat_id, block_notice, reply_to).await;
+                }
+            }
+            Ok(Forwarded::Unverified) => {
+                log::debug!("Message of unverified chat {} dropped", &chat_id.0);
+            }
+            Ok(Forwarded::Throttled { first }) => {
+                log::debug!("Message of chat {} over the rate limit dropped", &chat_id.0);
+                // told once, answering every dropped message would spend the quota saved
+                if first {
+                    send_notice(client, chat_id, SLOW_DOWN_HINT, reply_to).await;
+                }
+            }
+            Ok(Forwarded::Moderated) => {}
+            Ok(Forwarded::Collected) => {
+                log::debug!("Message collected into its album, chat_id: {}", &chat_id.0);
+            }
+            Ok(Forwarded::Started) => {
+                if let Some(welcome_text) = BotText::Welcome.text(bot_info) {
+                    send_notice(client, chat_id, welcome_text, None).await;
+                }
+            }
+            Ok(Forwarded::Sent) => {
+                log::debug!("Message handled successfully, chat_id: {}", &chat_id.0);
+                let text = BotText::Away
+                    .text(bot_info)
+                    .or_else(|| BotText::Ack.text(bot_info));
+                if let Some(text) = text {
+                    send_notice(client, chat_id, text, reply_to).await;
+                }
+            }
+        }
+    }
+
+    ///
+    /// Find the original message a message in the target chat replies to
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_id`: bot id stored in the database
+    /// * `target_chat_id`: chat the reply was sent in, message ids are per chat
+    /// * `reply_id`: id of the message replied to
+    ///
+    /// returns: `Result<Option<Model>, Error>` none if the bot did not forward the message
+    ///
+    #[tracing::instrument(err, skip(self))]
+    async fn find_original_message(
+        &self,
+        bot_id: i64,
+        target_chat_id: i64,
+        reply_id: i32,
+    ) -> anyhow::Result<Option<entities::pm_forwarding_message::Model>> {
+        Ok(self
+            .messages
+            .find_by_forward_message_id(bot_id, target_chat_id, reply_id)
+            .await?)
+    }
+
+    #[tracing::instrument(err, skip(self))]
     async fn handle_forward_message(
         &self,
         bot_info: entities::pm_forwarding_bot::Model,
         update: Update,
-    ) -> anyhow::Result<()> {
-        let chat = update
-            .chat()
-            .ok_or_else(|| anyhow::anyhow!("Missing chat"))?
-            .clone();
+    ) -> anyhow::Result<Forwarded> {
+        let message = match update.kind {
+            UpdateKind::Message(message) => message,
+            _ => {
+                return Err(anyhow::anyhow!("Unsupported update kind"));
+            }
+        };
 
-        tracing::debug!("Creating bot client");
+        let from = match message.from() {
+            Some(from) if !from.is_bot => from.clone(),
+            _ => {
+                log::debug!(
+                    "Ignoring message from chat {}, sender is unknown",
+                    message.chat.id.0
+                );
+                return Ok(Forwarded::Ignored);
+            }
+        };
 
-        let bot = self
-            .new_bot_client(&bot_info.bot_token)
-            .map_err(|err| anyhow::anyhow!("Error creating bot client: {}", err))?;
+        if self
+            .blocks
+            .find_active(bot_info.id, message.chat.id.0, Utc::now())
+            .await
+            .map_err(NothingPosted::wrap)?
+            .is_some()
+        {
+            return Ok(Forwarded::Blocked);
+        }
 
-        tracing::debug!("Matching update kind & media kind");
+        // every message counts, commands and captcha answers too, an album once when it is
+        // forwarded
+        if message.media_group_id().is_none() {
+            if let Some(limited) = self
+                .apply_rate_limit(&bot_info, &from)
+                .await
+                .map_err(NothingPosted::wrap)?
+            {
+                return Ok(limited);
+            }
+        }
+
+        // `/start` of an unverified sender gets the captcha, the welcome text follows it
+        if let Some(kind) = bot_info.captcha.as_deref().and_then(CaptchaKind::parse) {
+            if !self
+                .ensure_verified(&bot_info, kind, message.chat.id.0)
+                .await
+                .map_err(NothingPosted::wrap)?
+            {
+                return Ok(Forwarded::Unverified);
+            }
+        }
+
+        if message
+            .text()
+            .is_some_and(|text| is_command(text, START_COMMAND))
+        {
+            return Ok(Forwarded::Started);
+        }
+
+        if message.text().is_some_and(|text| text.starts_with('/')) {
+            log::debug!("Ignoring command message from chat {}", message.chat.id.0);
+            return Ok(Forwarded::Ignored);
+        }
+
+        if !matches!(message.kind, MessageKind::Common(_)) {
+            return Err(anyhow::anyhow!("Unsupported message kind"));
+        }
 
-        match update.kind {
-            UpdateKind::Message(ref message) => {
-                if message.from().is_none() || message.from().unwrap().is_bot {
-                    log::debug!(
-                        "Ignoring message from chat {}, sender is unknown",
-                        chat.id.0
+        if let Some(media_group_id) = message.media_group_id().map(str::to_string) {
+            match self
+                .media_groups
+                .push(bot_info.id, &message)
+                .await
+                .map_err(NothingPosted::wrap)?
+            {
+                MediaGroupPart::Collected => {
+                    log::debug!("Collected message of media group {}", media_group_id);
+                    return Ok(Forwarded::Collected);
+                }
+                MediaGroupPart::Late => {
+                    log::debug!("Forwarding late message of media group {}", media_group_id);
+                    if let Some(limited) = self
+                        .apply_rate_limit(&bot_info, &from)
+                        .await
+                        .map_err(NothingPosted::wrap)?
+                    {
+                        return Ok(limited);
+                    }
+                }
+                MediaGroupPart::First => {
+                    // the first message of the album forwards all of it once the rest arrived
+                    self.schedule_media_group(
+                        bot_info,
+                        from,
+                        message.chat.id,
+                        message.id,
+                        media_group_id,
                     );
-                    return Ok(());
+                    return Ok(Forwarded::Collected);
                 }
+            }
+        }
 
-                if message.text().is_none() || message.text().unwrap().starts_with("/") {
-                    log::debug!("Ignoring command message from chat {}", chat.id.0);
-                    return Ok(());
+        self.post_messages(&bot_info, &from, message.chat.id.0, &[message])
+            .await
+    }
+
+    ///
+    /// Forward a media group once the rest of it arrived, in a task of its own so no worker
+    /// waits for it, the sender is answered as for a single message
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the media group was sent to
+    /// * `from`: sender of the media group
+    /// * `chat_id`: chat of the sender
+    /// * `leader_id`: message leading the media group, the answer replies to it
+    /// * `media_group_id`: media group id
+    ///
+    /// returns: `JoinHandle<()>` task forwarding the media group
+    ///
+    fn schedule_media_group(
+        &self,
+        bot_info: entities::pm_forwarding_bot::Model,
+        from: User,
+        chat_id: ChatId,
+        leader_id: MessageId,
+        media_group_id: String,
+    ) -> tokio::task::JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(MEDIA_GROUP_WAIT).await;
+
+            let forwarded = service
+                .forward_media_group(&bot_info, &from, chat_id.0, &media_group_id)
+                .await;
+            // no redelivery forwards it again, the sender is told it failed instead
+            match service.new_bot_client(&bot_info.bot_token) {
+                Ok(client) => {
+                    service
+                        .answer_outcome(&client, &bot_info, chat_id, Some(leader_id), forwarded)
+                        .await
                 }
+                Err(err) => log::error!("Error creating bot client: {}", err),
+            }
+        })
+    }
 
-                let meta = forwarding_meta(
-                    &message
-                        .from()
-                        .map(|from| {
-                            format!(
-                                "{}{}",
-                                from.first_name,
-                                if from.last_name.is_some() {
-                                    format!(" {}", from.last_name.as_ref().unwrap())
-                                } else {
-                                    "".into()
-                                }
-                            )
-                        })
-                        .unwrap_or("Unknown".into()),
-                    chat.id.0,
-                    message.id.0,
-                );
+    ///
+    /// Forward the messages of a media group collected so far, dropped albums are done with and
+    /// failed ones kept for their next message to forward them again
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the media group was sent to
+    /// * `from`: sender of the media group
+    /// * `chat_id`: chat of the sender
+    /// * `media_group_id`: media group id
+    ///
+    /// returns: `Result<Forwarded, Error>` what became of the media group
+    ///
+    #[tracing::instrument(err, skip(self))]
+    async fn forward_media_group(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+        chat_id: i64,
+        media_group_id: &str,
+    ) -> anyhow::Result<Forwarded> {
+        let forwarded = async {
+            let messages = self
+                .media_groups
+                .messages(bot_info.id, chat_id, media_group_id)
+                .await?;
+            if let Some(limited) = self.apply_rate_limit(bot_info, from).await? {
+                return Ok(limited);
+            }
 
-                let msg_forward_id = match &message.kind {
-                    MessageKind::Common(common_message) => match &common_message.media_kind {
-                        MediaKind::Text(media) => {
-                            bot.send_message(
-                                ChatId(bot_info.target_chat_id),
-                                format!("{}\n\n{}", meta, media.text),
-                            )
-                            .parse_mode(teloxide::types::ParseMode::Html)
-                            .await?
-                            .id
-                        }
-                        _ => {
-                            let file = match &common_message.media_kind {
-                                MediaKind::Animation(m) => Some(&m.animation.file),
-                                MediaKind::Audio(m) => Some(&m.audio.file),
-                                MediaKind::Contact(_) => None,
-                                MediaKind::Document(m) => Some(&m.document.file),
-                                MediaKind::Game(_) => None,
-                                MediaKind::Venue(_) => None,
-                                MediaKind::Location(_) => None,
-                                MediaKind::Photo(m) => Some(&m.photo.last().unwrap().file),
-                                MediaKind::Poll(_) => None,
-                                MediaKind::Sticker(m) => Some(&m.sticker.file),
-                                MediaKind::Text(_) => {
-                                    unreachable!("Text message already handled")
-                                }
-                                MediaKind::Video(m) => Some(&m.video.file),
-                                MediaKind::VideoNote(_) => None,
-                                MediaKind::Voice(m) => Some(&m.voice.file),
-                                MediaKind::Migration(_) => None,
-                            }
-                            .ok_or_else(|| anyhow::anyhow!("Unsupported media kind"))?;
-
-                            bot.send_document(
-                                ChatId(bot_info.target_chat_id),
-                                InputFile::file_id(&file.id),
-                            )
-                            .await?
-                            .id
-                        }
-                    },
-                    _ => {
-                        return Err(anyhow::anyhow!("Unsupported message kind"));
-                    }
-                };
+            self.post_messages(bot_info, from, chat_id, &messages).await
+        }
+        .await;
+
+        self.settle_media_group(bot_info.id, chat_id, media_group_id, forwarded.is_ok())
+            .await;
+        forwarded
+    }
+
+    ///
+    /// Post messages of a sender into the target chat and store the mappings replies reach the
+    /// sender by
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the messages were sent to
+    /// * `from`: sender of the messages
+    /// * `chat_id`: chat of the sender
+    /// * `messages`: messages of the sender, more than one for an album
+    ///
+    /// returns: `Result<Forwarded, Error>` sent
+    ///
+    async fn post_messages(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+        chat_id: i64,
+        messages: &[Message],
+    ) -> anyhow::Result<Forwarded> {
+        tracing::debug!("Creating bot client");
 
-                tracing::debug!(
-                    name = "Storing message to database",
-                    message_id = msg_forward_id.0,
+        let bot = self
+            .new_bot_client(&bot_info.bot_token)
+            .map_err(|err| anyhow::anyhow!("Error creating bot client: {}", err))?;
+
+        let posted = self
+            .post_to_target_chat(&bot, bot_info, from, messages)
+            .await?;
+
+        for (message_id, forward_id) in posted {
+            tracing::debug!(
+                name = "Storing message to database",
+                message_id = forward_id.0,
+            );
+
+            // forwarded already, replies to this copy are lost rather than the message sent twice
+            if let Err(err) = self
+                .messages
+                .create(NewPmForwardingMessage {
+                    bot_id: bot_info.id,
+                    target_chat_id: bot_info.target_chat_id,
+                    telegram_chat_id: chat_id,
+                    telegram_message_id: message_id.0,
+                    forward_telegram_message_id: forward_id.0,
+                })
+                .await
+            {
+                log::error!(
+                    "Failed to store forwarded message {}: {}",
+                    forward_id.0,
+                    err
                 );
+            }
+        }
 
-                // store message to database
-                entities::pm_forwarding_message::ActiveModel {
-                    bot_id: ActiveValue::Set(bot_info.id),
-                    telegram_chat_id: ActiveValue::Set(chat.id.0),
-                    telegram_message_id: ActiveValue::Set(message.id.0),
-                    forward_telegram_message_id: ActiveValue::Set(msg_forward_id.0),
-                    ..Default::default()
-                }
-                .save(&self.db)
+        Ok(Forwarded::Sent)
+    }
+
+    ///
+    /// Post messages of a sender into the target chat, after a header naming the sender or into
+    /// the forum topic of the sender
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: client of the bot
+    /// * `bot_info`: bot the messages were sent to
+    /// * `from`: sender of the messages
+    /// * `messages`: messages of the sender, more than one for an album
+    ///
+    /// returns: `Result<Vec<(MessageId, MessageId)>, Error>` ids of the messages and of the posts
+    /// replies to which reach the sender
+    ///
+    #[tracing::instrument(err, skip(self, bot, messages))]
+    async fn post_to_target_chat(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+        messages: &[Message],
+    ) -> anyhow::Result<Vec<(MessageId, MessageId)>> {
+        let target_chat_id = ChatId(bot_info.target_chat_id);
+        let first = messages
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Media group already forwarded"))?;
+        let message_ids = messages.iter().map(|message| message.id);
+
+        let header = forwarding_meta(&sender_name(from), first.chat.id.0, first.id.0);
+        if bot_info.topics_enabled {
+            let forward_ids = self
+                .post_to_sender_topic(bot, bot_info, from, header, messages)
                 .await?;
+            return Ok(message_ids.zip(forward_ids).collect());
+        }
+
+        let (header, header_entities) = header;
+        let header_id = bot
+            .send_message(target_chat_id, header)
+            .entities(header_entities)
+            .await
+            .map_err(NothingPosted::wrap)?
+            .id;
+        let forward_ids = send_copies(bot, target_chat_id, None, Some(header_id), messages).await?;
+
+        // replies to the header reach the sender too
+        Ok(std::iter::once((first.id, header_id))
+            .chain(message_ids.zip(forward_ids))
+            .collect())
+    }
+
+    /// Finish a media group led by a message once forwarded or dropped, or give it up for its
+    /// next message to forward, failures are only logged
+    async fn settle_media_group(
+        &self,
+        bot_id: i64,
+        chat_id: i64,
+        media_group_id: &str,
+        done: bool,
+    ) {
+        let settled = if done {
+            self.media_groups
+                .finish(bot_id, chat_id, media_group_id)
+                .await
+        } else {
+            self.media_groups
+                .release(bot_id, chat_id, media_group_id)
+                .await
+        };
+        if let Err(err) = settled {
+            log::error!("Failed to settle media group {}: {}", media_group_id, err);
+        }
+    }
+
+    ///
+    /// Take a token from the bucket of a sender, rate limits are off for a limit of `0`
+    ///
+    /// The limiter failing lets the message through, flood protection is not worth dropping
+    /// messages for.
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the message was sent to
+    /// * `from`: sender of the message
+    ///
+    /// returns: `RateDecision`
+    ///
+    #[tracing::instrument(skip(self))]
+    async fn check_rate_limit(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+    ) -> RateDecision {
+        let capacity = match u32::try_from(bot_info.rate_limit) {
+            Ok(0) | Err(_) => return RateDecision::Allowed,
+            Ok(capacity) => capacity,
+        };
+
+        match self
+            .rate_limiter
+            .take(bot_info.id, from.id.0 as i64, capacity)
+            .await
+        {
+            Ok(decision) => decision,
+            Err(err) => {
+                log::error!("Failed to check the rate limit: {}", err);
+                RateDecision::Allowed
             }
-            _ => {
-                return Err(anyhow::anyhow!("Unsupported update kind"));
+        }
+    }
+
+    ///
+    /// Apply the rate limit of a bot to a message of a sender, muting a sender flooding the bot
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the message was sent to
+    /// * `from`: sender of the message
+    ///
+    /// returns: `Result<Option<Forwarded>, Error>` outcome of a message over the limit, none for
+    /// a message within it
+    ///
+    #[tracing::instrument(err, skip(self))]
+    async fn apply_rate_limit(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+    ) -> anyhow::Result<Option<Forwarded>> {
+        match self.check_rate_limit(bot_info, from).await {
+            RateDecision::Allowed => Ok(None),
+            RateDecision::Throttled { first } => Ok(Some(Forwarded::Throttled { first })),
+            RateDecision::Flood => {
+                let bot = self
+                    .new_bot_client(&bot_info.bot_token)
+                    .map_err(|err| anyhow::anyhow!("Error creating bot client: {}", err))?;
+                self.mute_flooding_sender(&bot, bot_info, from).await?;
+                Ok(Some(Forwarded::Blocked))
             }
         }
+    }
 
-        Ok(())
+    ///
+    /// Check a sender solved the captcha of the bot, sending them one when due
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot with a captcha
+    /// * `kind`: captcha of the bot
+    /// * `chat_id`: private chat of the sender
+    ///
+    /// returns: `Result<bool, Error>` whether the messages of the sender are forwarded
+    ///
+    #[tracing::instrument(err, skip(self))]
+    async fn ensure_verified(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        kind: CaptchaKind,
+        chat_id: i64,
+    ) -> anyhow::Result<bool> {
+        let now = Utc::now().timestamp();
+        let attempts = match self.captchas.get(bot_info.id, chat_id).await? {
+            Some(CaptchaState::Verified) => return Ok(true),
+            // a captcha is waiting for its answer or the sender ran out of retries
+            Some(CaptchaState::Pending { expires_at, .. }) if expires_at > now => return Ok(false),
+            Some(CaptchaState::Failed { until }) if until > now => return Ok(false),
+            // waiting a captcha out does not take back wrong answers
+            Some(CaptchaState::Pending { attempts, .. }) => attempts,
+            _ => 0,
+        };
+
+        let challenge = Challenge::new(kind);
+        let sent = self
+            .new_bot_client(&bot_info.bot_token)?
+            .send_message(
+                ChatId(chat_id),
+                format!("{}\n\n{}", CAPTCHA_PROMPT, challenge.question),
+            )
+            .reply_markup(challenge.keyboard())
+            .await?;
+        self.captchas
+            .set(
+                bot_info.id,
+                chat_id,
+                CaptchaState::Pending {
+                    message_id: sent.id.0,
+                    answer: challenge.answer,
+                    attempts,
+                    expires_at: now + i64::from(bot_info.captcha_timeout),
+                },
+            )
+            .await?;
+
+        Ok(false)
     }
 
-    #[tracing::instrument(err)]
-    async fn handle_target_chat_message(
+    ///
+    /// Answer a sender tapping a button of their captcha
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the captcha was sent by
+    /// * `query`: callback query of the button, other queries are ignored
+    ///
+    /// returns: `Result<(), Error>`
+    ///
+    #[tracing::instrument(err, skip(self))]
+    async fn handle_captcha_answer(
         &self,
         bot_info: entities::pm_forwarding_bot::Model,
-        update: Update,
+        query: CallbackQuery,
     ) -> anyhow::Result<()> {
-        match update.kind {
-            UpdateKind::Message(ref message) => {
-                let bot = self
-                    .new_bot_client(&bot_info.bot_token)
-                    .map_err(|err| anyhow::anyhow!("Error creating bot client: {}", err))?;
+        let answer = query
+            .data
+            .as_deref()
+            .and_then(|data| data.strip_prefix(CAPTCHA_CALLBACK_PREFIX));
+        let (answer, message) = match (answer, &query.message) {
+            (Some(answer), Some(message)) if message.chat.is_private() => (answer, message),
+            _ => {
+                log::debug!("Ignoring callback query {}", query.id);
+                return Ok(());
+            }
+        };
 
-                let reply_id = message
-                    .reply_to_message()
-                    .map(|msg| msg.id.0)
-                    .ok_or_else(|| anyhow::anyhow!("Missing reply message"))?;
+        let (outcome, challenge) = self
+            .settle_captcha(&bot_info, message.chat.id.0, message.id.0, answer)
+            .await?;
 
-                // find original message
-                let message_entity = entities::pm_forwarding_message::Entity::find()
-                    .filter(
-                        entities::pm_forwarding_message::Column::ForwardTelegramMessageId
-                            .eq(reply_id),
+        // settled already, a redelivery would only find the captcha expired
+        let client = self.new_bot_client(&bot_info.bot_token)?;
+        let (chat_id, message_id) = (message.chat.id, message.id);
+        let (notice, edited) = match (outcome, challenge) {
+            (CaptchaAnswer::Verified, _) => {
+                let edited = client
+                    .edit_message_text(chat_id, message_id, CAPTCHA_VERIFIED)
+                    .await;
+                if let Some(welcome_text) = BotText::Welcome.text(&bot_info) {
+                    send_notice(&client, chat_id, welcome_text, None).await;
+                }
+                (CAPTCHA_VERIFIED, edited.map(drop))
+            }
+            (CaptchaAnswer::Wrong { .. }, Some(challenge)) => {
+                let edited = client
+                    .edit_message_text(
+                        chat_id,
+                        message_id,
+                        format!("{}\n\n{}", CAPTCHA_PROMPT, challenge.question),
                     )
-                    .one(&self.db)
-                    .await?
-                    .ok_or_else(|| anyhow::anyhow!("Message not found"))?;
-
-                // reply to original message
-                bot.send_message(
-                    ChatId(message_entity.telegram_chat_id),
-                    message
-                        .text()
-                        .ok_or_else(|| anyhow::anyhow!("Missing message text"))?,
-                )
-                .reply_to_message_id(MessageId(message_entity.telegram_message_id))
-                .await?;
+                    .reply_markup(challenge.keyboard())
+                    .await;
+                (CAPTCHA_WRONG, edited.map(drop))
             }
-            _ => {
-                return Err(anyhow::anyhow!("Unsupported update kind"));
+            (CaptchaAnswer::OutOfRetries, _) => {
+                let edited = client
+                    .edit_message_text(chat_id, message_id, CAPTCHA_FAILED)
+                    .await;
+                (CAPTCHA_FAILED, edited.map(drop))
             }
+            _ => (CAPTCHA_EXPIRED, Ok(())),
+        };
+        if let Err(err) = edited {
+            log::error!("Failed to update captcha of chat {}: {}", chat_id.0, err);
+        }
+        if let Err(err) = client.answer_callback_query(query.id).text(notice).await {
+            log::error!("Failed to answer captcha of chat {}: {}", chat_id.0, err);
         }
 
         Ok(())
     }
-}
 
-fn forwarding_meta(from: &str, chat_id: i64, message_id: i32) -> String {
-    format!(
-        r#"
-        From: {}
-        Chat ID: <a href="tg://user?id={}">{}</a>
-        Message ID: <code>{}</code>
-        "#,
-        from, chat_id, chat_id, message_id
-    )
-    .lines()
-    .map(|line| line.trim_start())
-    .collect::<Vec<_>>()
-    .join("\n")
-    .trim()
-    .to_string()
+    ///
+    /// Save the verification of a sender answering their captcha
+    ///
+    /// # Arguments
+    ///
+    /// * `bot_info`: bot the captcha was sent by
+    /// * `chat_id`: private chat of the sender
+    /// * `message_id`: message of the captcha
+    /// * `answer`: option the sender chose
+    ///
+    /// returns: `Result<(CaptchaAnswer, Option<Challenge>), Error>` the new challenge after a
+    /// wrong answer
+    ///
+    #[tracing::instrument(err, skip(self))]
+    async fn settle_captcha(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        chat_id: i64,
+        message_id: i32,
+        answer: &str,
+    ) -> anyhow::Result<(CaptchaAnswer, Option<Challenge>)> {
+        // buttons of a captcha turned off since are stale
+        let kind = match bot_info.captcha.as_deref().and_then(CaptchaKind::parse) {
+            Some(kind) => kind,
+            None => return Ok((CaptchaAnswer::Expired, None)),
+        };
+
+        let now = Utc::now().timestamp();
+        let state = self.captchas.get(bot_info.id, chat_id).await?;
+        let retries = u32::try_from(bot_info.captcha_retries).unwrap_or_default();
+        let outcome = check_answer(state.as_ref(), message_id, answer, now, retries);
+
+        let (state, challenge) = match outcome {
+            CaptchaAnswer::Verified => (CaptchaState::Verified, None),
+            CaptchaAnswer::Wrong {
+                attempts,
+                expires_at,
+            } => {
+                let challenge = Challenge::new(kind);
+                let state = CaptchaState::Pending {
+                    message_id,
+                    answer: challenge.answer.clone(),
+                    attempts,
+                    expires_at,
+                };
+                (state, Some(challenge))
+            }
+            CaptchaAnswer::OutOfRetries => {
+                let until = now + i64::from(bot_info.captcha_timeout);
+                (CaptchaState::Failed { until }, None)
+            }
+            CaptchaAnswer::Expired => return Ok((outcome, None)),
+        };
+        self.captchas.set(bot_info.id, chat_id, state).await?;
+
+        Ok((outcome, challenge))
+    }
+
+    ///
+    /// Mute a sender flooding the bot for a while and tell the target chat
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: client of the bot
+    /// * `bot_info`: bot the sender floods
+    /// * `from`: sender of the messages
+    ///
+    /// returns: `Result<(), Error>` the mute is saved even if the target chat can not be told
+    ///
+    #[tracing::instrument(err, skip(self, bot))]
+    async fn mute_flooding_sender(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+    ) -> anyhow::Result<()> {
+        let until = Utc::now() + chrono::Duration::from_std(FLOOD_MUTE)?;
+        self.blocks
+            .upsert(NewPmForwardingBlock {
+                bot_id: bot_info.id,
+                telegram_chat_id: from.id.0 as i64,
+                blocked_until: Some(until),
+            })
+            .await?;
+
+        if let Err(err) = bot
+            .send_message(
+                ChatId(bot_info.target_chat_id),
+                format!(
+                    "{} ({}) muted until {} for flooding the bot, reply /unban to one of their messages to lift it",
+                    sender_name(from),
+                    from.id.0,
+                    format_time(until)
+                ),
+            )
+            .await
+        {
+            log::error!("Failed to tell the target chat about a flood mute: {}", err);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Copy the messages of a sender into their forum topic, the topic is created on demand
+    ///
+    /// A topic deleted in the target chat is created again, the copies are sent once more.
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: client of the bot
+    /// * `bot_info`: bot in topics mode
+    /// * `from`: sender of the messages
+    /// * `header`: header posted into a new topic to introduce the sender
+    /// * `messages`: messages of the sender, more than one for an album
+    ///
+    /// returns: `Result<Vec<MessageId>, Error>` ids of the copies
+    ///
+    #[tracing::instrument(err, skip(self, bot, header, messages))]
+    async fn post_to_sender_topic(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+        header: (String, Vec<MessageEntity>),
+        messages: &[Message],
+    ) -> anyhow::Result<Vec<MessageId>> {
+        let target_chat_id = ChatId(bot_info.target_chat_id);
+        let chat_id = messages
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No message to post"))?
+            .chat
+            .id
+            .0;
+
+        let topic = match self
+            .topics
+            .find_by_telegram_chat_id(bot_info.id, bot_info.target_chat_id, chat_id)
+            .await
+            .map_err(NothingPosted::wrap)?
+        {
+            Some(topic) => topic,
+            None => {
+                self.create_sender_topic(bot, bot_info, from, chat_id, header.clone())
+                    .await?
+            }
+        };
+
+        match send_copies(
+            bot,
+            target_chat_id,
+            Some(topic.message_thread_id),
+            None,
+            messages,
+        )
+        .await
+        {
+            Err(err) if is_thread_gone(&err) => {
+                log::debug!(
+                    "Topic {} of chat {} is gone, creating it again",
+                    topic.message_thread_id,
+                    chat_id
+                );
+                self.topics.delete(topic.id).await?;
+                let topic = self
+                    .create_sender_topic(bot, bot_info, from, chat_id, header)
+                    .await?;
+
+                send_copies(
+                    bot,
+                    target_chat_id,
+                    Some(topic.message_thread_id),
+                    None,
+                    messages,
+                )
+                .await
+            }
+            result => result,
+        }
+    }
+
+    /// Create the forum topic of a sender and post the header into it
+    #[tracing::instrument(err, skip(self, bot, header))]
+    async fn create_sender_topic(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        from: &User,
+        chat_id: i64,
+        header: (String, Vec<MessageEntity>),
+    ) -> anyhow::Result<entities::pm_forwarding_topic::Model> {
+        let target_chat_id = ChatId(bot_info.target_chat_id);
+        let forum_topic = bot
+            .create_forum_topic(
+                target_chat_id,
+                topic_name(&sender_name(from), chat_id),
+                TOPIC_ICON_COLOR,
+                // no custom emoji, the colored default icon
+                "",
+            )
+            .await
+            .map_err(NothingPosted::wrap)?;
+
+        let created = self
+            .topics
+            .create(NewPmForwardingTopic {
+                bot_id: bot_info.id,
+                target_chat_id: bot_info.target_chat_id,
+                message_thread_id: forum_topic.message_thread_id,
+                telegram_chat_id: chat_id,
+            })
+            .await;
+        let topic = match created {
+            Ok(topic) => topic,
+            Err(err) => {
+                // another message of the sender created their topic meanwhile
+                let topic = self
+                    .topics
+                    .find_by_telegram_chat_id(bot_info.id, bot_info.target_chat_id, chat_id)
+                    .await?
+                    .ok_or(err)?;
+                bot.delete_forum_topic(target_chat_id, forum_topic.message_thread_id)
+                    .await?;
+                return Ok(topic);
+            }
+        };
+
+        let (header, header_entities) = header;
+        bot.send_message(target_chat_id, header)
+            .entities(header_entities)
+            .message_thread_id(topic.message_thread_id)
+            .await?;
+
+        Ok(topic)
+    }
+
+    #[tracing::instrument(err, skip(self))]
+    async fn handle_target_chat_message(
+        &self,
+        bot_info: entities::pm_forwarding_bot::Model,
+        update: Update,
+    ) -> anyhow::Result<Forwarded> {
+        let bot = self
+            .new_bot_client(&bot_info.bot_token)
+            .map_err(|err| anyhow::anyhow!("Error creating bot client: {}", err))?;
+
+        let message = match update.kind {
+            UpdateKind::Message(message) => message,
+            UpdateKind::EditedMessage(message) => {
+                return self.mirror_reply_edit(&bot, &bot_info, &message).await;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Unsupported update kind"));
+            }
+        };
+
+        let reply_id = message.reply_to_message().map(|msg| msg.id.0);
+
+        if let Some(reply_id) = reply_id.filter(|_| message.text().is_some_and(is_delete_command)) {
+            return self.delete_reply_copy(&bot, &bot_info, reply_id).await;
+        }
+
+        if let Some(command) = message.text().and_then(parse_moderation_command) {
+            return self.moderate(&bot, &bot_info, &message, command?).await;
+        }
+
+        if bot_info.topics_enabled && !matches!(message.kind, MessageKind::Common(_)) {
+            log::debug!("Ignoring service message, such as a topic created");
+            return Ok(Forwarded::Ignored);
+        }
+
+        if let Some(topic) = self
+            .topic_of_message(&bot_info, &message)
+            .await
+            .map_err(NothingPosted::wrap)?
+        {
+            if message.text().is_some_and(|text| text.starts_with('/')) {
+                log::debug!("Ignoring command message in topic {:?}", message.thread_id);
+                return Ok(Forwarded::Ignored);
+            }
+
+            // any message in the topic reaches the sender, replies keep pointing at the original
+            let reply_to = match reply_id {
+                Some(reply_id) => self
+                    .find_original_message(bot_info.id, message.chat.id.0, reply_id)
+                    .await
+                    .map_err(NothingPosted::wrap)?
+                    .filter(|original| original.telegram_chat_id == topic.telegram_chat_id)
+                    .map(|original| MessageId(original.telegram_message_id)),
+                None => None,
+            };
+
+            return self
+                .copy_to_sender(
+                    &bot,
+                    &bot_info,
+                    &message,
+                    ChatId(topic.telegram_chat_id),
+                    reply_to,
+                )
+                .await;
+        }
+
+        // chatter of the target chat, such as in its general topic, is not meant for a sender
+        let reply_id = match reply_id {
+            Some(reply_id) => reply_id,
+            None => {
+                log::debug!("Ignoring message {}, not a reply", message.id.0);
+                return Ok(Forwarded::Ignored);
+            }
+        };
+        let message_entity = match self
+            .find_original_message(bot_info.id, message.chat.id.0, reply_id)
+            .await
+            .map_err(NothingPosted::wrap)?
+        {
+            Some(message_entity) => message_entity,
+            None => return Ok(Forwarded::UnknownReply),
+        };
+
+        self.copy_to_sender(
+            &bot,
+            &bot_info,
+            &message,
+            ChatId(message_entity.telegram_chat_id),
+            Some(MessageId(message_entity.telegram_message_id)),
+        )
+        .await
+    }
+
+    ///
+    /// Run a moderation command of the target chat and answer it
+    ///
+    /// Bans, unbans and mutes are replied to a forwarded message, or sent in the topic of the
+    /// sender in topics mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: client of the bot
+    /// * `bot_info`: bot the command is for
+    /// * `message`: message with the command
+    /// * `command`: parsed command
+    ///
+    /// returns: `Result<Forwarded, Error>`
+    ///
+    #[tracing::instrument(err, skip(self, bot))]
+    async fn moderate(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        message: &Message,
+        command: ModerationCommand,
+    ) -> anyhow::Result<Forwarded> {
+        let now = Utc::now();
+        let answer = match command {
+            ModerationCommand::Blocked => {
+                let blocks = self.blocks.list_active(bot_info.id, now).await?;
+                if !blocks.is_empty() {
+                    bot.send_document(
+                        message.chat.id,
+                        InputFile::memory(blocks_csv(&blocks)).file_name("blocked.csv"),
+                    )
+                    .reply_to_message_id(message.id)
+                    .await?;
+                }
+
+                blocked_list(&blocks)
+            }
+            ModerationCommand::BlockNotice(block_notice) => {
+                let answer = match &block_notice {
+                    Some(_) => "Blocked senders now get the notice".to_string(),
+                    None => "Messages of blocked senders are now dropped silently".to_string(),
+                };
+                self.bots
+                    .update(
+                        bot_info.id,
+                        UpdatePmForwardingBot {
+                            block_notice: Some(block_notice),
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+
+                answer
+            }
+            command => {
+                let chat_id = match self.sender_of_message(bot_info, message).await? {
+                    Some(chat_id) => chat_id,
+                    None => return Ok(Forwarded::UnknownReply),
+                };
+
+                match command {
+                    ModerationCommand::Unban => {
+                        if self.blocks.delete(bot_info.id, chat_id).await? {
+                            format!("Sender {} unblocked", chat_id)
+                        } else {
+                            format!("Sender {} is not blocked", chat_id)
+                        }
+                    }
+                    command => {
+                        let blocked_until = match command {
+                            ModerationCommand::Mute(duration) => {
+                                Some(now + chrono::Duration::from_std(duration)?)
+                            }
+                            _ => None,
+                        };
+                        let block = self
+                            .blocks
+                            .upsert(NewPmForwardingBlock {
+                                bot_id: bot_info.id,
+                                telegram_chat_id: chat_id,
+                                blocked_until,
+                            })
+                            .await?;
+
+                        match block.blocked_until {
+                            Some(until) => {
+                                format!("Sender {} muted until {}", chat_id, format_time(until))
+                            }
+                            None => format!("Sender {} banned", chat_id),
+                        }
+                    }
+                }
+            }
+        };
+
+        bot.send_message(message.chat.id, answer)
+            .reply_to_message_id(message.id)
+            .await?;
+
+        Ok(Forwarded::Moderated)
+    }
+
+    /// Private chat of the sender a message of the target chat is about, by reply or by topic
+    #[tracing::instrument(err, skip(self))]
+    async fn sender_of_message(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        message: &Message,
+    ) -> anyhow::Result<Option<i64>> {
+        if let Some(topic) = self.topic_of_message(bot_info, message).await? {
+            return Ok(Some(topic.telegram_chat_id));
+        }
+
+        let reply_id = match message.reply_to_message() {
+            Some(reply) => reply.id.0,
+            None => return Ok(None),
+        };
+
+        Ok(self
+            .find_original_message(bot_info.id, message.chat.id.0, reply_id)
+            .await?
+            .map(|original| original.telegram_chat_id))
+    }
+
+    /// Topic of the sender a message in the target chat was sent in, for bots in topics mode
+    #[tracing::instrument(err, skip(self))]
+    async fn topic_of_message(
+        &self,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        message: &Message,
+    ) -> anyhow::Result<Option<entities::pm_forwarding_topic::Model>> {
+        let thread_id = match (&message.kind, message.thread_id) {
+            (MessageKind::Common(common_message), Some(thread_id))
+                if bot_info.topics_enabled && common_message.is_topic_message =>
+            {
+                thread_id
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(self
+            .topics
+            .find_by_message_thread_id(bot_info.id, message.chat.id.0, thread_id)
+            .await?)
+    }
+
+    /// Copy a message of the target chat to a sender and remember the copy for edits
+    #[tracing::instrument(err, skip(self, bot))]
+    async fn copy_to_sender(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        message: &Message,
+        chat_id: ChatId,
+        reply_to: Option<MessageId>,
+    ) -> anyhow::Result<Forwarded> {
+        // copied rather than sent again, so media and entities are kept
+        let mut request = bot.copy_message(chat_id, message.chat.id, message.id);
+        request.payload_mut().reply_to_message_id = reply_to;
+        let copy_id = request
+            .allow_sending_without_reply(true)
+            .await
+            .map_err(NothingPosted::wrap)?;
+
+        // sent already, only edits of this reply are lost
+        if let Err(err) = self
+            .replies
+            .create(NewPmForwardingReply {
+                bot_id: bot_info.id,
+                target_chat_id: message.chat.id.0,
+                target_message_id: message.id.0,
+                telegram_chat_id: chat_id.0,
+                telegram_message_id: copy_id.0,
+            })
+            .await
+        {
+            log::error!("Failed to store reply copy {}: {}", copy_id.0, err);
+        }
+
+        Ok(Forwarded::Sent)
+    }
+
+    /// Apply an edit of a reply in the target chat to its copy
+    #[tracing::instrument(err, skip(self, bot))]
+    async fn mirror_reply_edit(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        message: &Message,
+    ) -> anyhow::Result<Forwarded> {
+        let reply = match self
+            .replies
+            .find_by_target_message_id(bot_info.id, message.chat.id.0, message.id.0)
+            .await?
+        {
+            Some(reply) => reply,
+            None => {
+                log::debug!("Ignoring edit of message {}, not a reply", message.id.0);
+                return Ok(Forwarded::Ignored);
+            }
+        };
+
+        let chat_id = ChatId(reply.telegram_chat_id);
+        let message_id = MessageId(reply.telegram_message_id);
+        match message.text() {
+            Some(text) => {
+                bot.edit_message_text(chat_id, message_id, text)
+                    .entities(message.entities().unwrap_or_default().to_vec())
+                    .await?;
+            }
+            None => {
+                bot.edit_message_media(chat_id, message_id, input_media(message)?)
+                    .await?;
+            }
+        }
+
+        Ok(Forwarded::Edited)
+    }
+
+    /// Delete the copy of a reply in the target chat, the bot api does not report deletions
+    #[tracing::instrument(err, skip(self, bot))]
+    async fn delete_reply_copy(
+        &self,
+        bot: &Bot,
+        bot_info: &entities::pm_forwarding_bot::Model,
+        target_message_id: i32,
+    ) -> anyhow::Result<Forwarded> {
+        let reply = self
+            .replies
+            .find_by_target_message_id(bot_info.id, bot_info.target_chat_id, target_message_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Reply not found"))?;
+
+        bot.delete_message(
+            ChatId(reply.telegram_chat_id),
+            MessageId(reply.telegram_message_id),
+        )
+        .await?;
+        if let Err(err) = self.replies.delete(reply.id).await {
+            log::error!("Failed to remove deleted reply copy {}: {}", reply.id, err);
+        }
+
+        Ok(Forwarded::Deleted)
+    }
+}
+
+///
+/// Send a notice to a chat, failures are only logged
+///
+/// # Arguments
+///
+/// * `bot`: client of the bot
+/// * `chat_id`: chat to notify
+/// * `text`: notice text
+/// * `reply_to`: message the notice answers
+///
+/// returns: `()`
+///
+async fn send_notice(
+    bot: &Bot,
+    chat_id: ChatId,
+    text: impl Into<String>,
+    reply_to: Option<MessageId>,
+) {
+    let mut request = bot.send_message(chat_id, text);
+    request.payload_mut().reply_to_message_id = reply_to;
+    if let Err(err) = request.await {
+        log::error!("Failed to send a notice to chat {}: {}", chat_id.0, err);
+    }
+}
+
+///
+/// Copy messages into the target chat, the messages of an album are sent as an album
+///
+/// # Arguments
+///
+/// * `bot`: client of the bot
+/// * `chat_id`: target chat
+/// * `thread_id`: forum topic to post into, `None` for the chat itself
+/// * `reply_to`: message the copies reply to
+/// * `messages`: messages to copy, more than one for an album
+///
+/// returns: `Result<Vec<MessageId>, Error>` ids of the copies
+///
+async fn send_copies(
+    bot: &Bot,
+    chat_id: ChatId,
+    thread_id: Option<i32>,
+    reply_to: Option<MessageId>,
+    messages: &[Message],
+) -> anyhow::Result<Vec<MessageId>> {
+    match messages {
+        [message] => {
+            let mut request = bot.copy_message(chat_id, message.chat.id, message.id);
+            request.payload_mut().message_thread_id = thread_id;
+            request.payload_mut().reply_to_message_id = reply_to;
+
+            Ok(vec![request.allow_sending_without_reply(true).await?])
+        }
+        _ => {
+            let mut request = bot.send_media_group(chat_id, album_media(messages)?);
+            request.payload_mut().message_thread_id = thread_id;
+            request.payload_mut().reply_to_message_id = reply_to;
+
+            Ok(request
+                .allow_sending_without_reply(true)
+                .await?
+                .iter()
+                .map(|message| message.id)
+                .collect())
+        }
+    }
+}
+
+/// Whether posting failed because the forum topic was deleted
+fn is_thread_gone(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<RequestError>(),
+        Some(RequestError::Api(ApiError::Unknown(description)))
+            if description.contains("message thread not found")
+                || description.contains("TOPIC_DELETED")
+    )
+}
+
+/// Name of the forum topic of a sender, the chat id keeps senders of the same name apart
+fn topic_name(name: &str, chat_id: i64) -> String {
+    let suffix = format!(" ({})", chat_id);
+    let name = name.trim();
+    let name = if name.is_empty() { "Unknown" } else { name };
+
+    let mut len = utf16_len(&suffix);
+    let name = name
+        .chars()
+        .take_while(|c| {
+            len += c.len_utf16();
+            len <= TOPIC_NAME_MAX_LEN
+        })
+        .collect::<String>();
+
+    format!("{}{}", name, suffix)
+}
+
+/// Blocked senders as listed in the target chat, the first of them with the count
+fn blocked_list(blocks: &[entities::pm_forwarding_block::Model]) -> String {
+    if blocks.is_empty() {
+        return "No blocked senders".to_string();
+    }
+
+    let mut lines = vec![format!("Blocked senders: {}", blocks.len())];
+    lines.extend(blocks.iter().take(BLOCKED_LIST_LEN).map(block_line));
+    if blocks.len() > BLOCKED_LIST_LEN {
+        lines.push(format!(
+            "and {} more, see the attached list",
+            blocks.len() - BLOCKED_LIST_LEN
+        ));
+    }
+
+    lines.join("\n")
+}
+
+/// Command replied to a reply in the target chat to delete its copy, `/delete` or `/delete@bot`
+fn is_delete_command(text: &str) -> bool {
+    is_command(text, DELETE_COMMAND)
+}
+
+/// Whether the text is the command, possibly addressed to the bot and with arguments
+fn is_command(text: &str, command: &str) -> bool {
+    let first = text.split_whitespace().next().unwrap_or_default();
+    first == command || first.starts_with(&format!("{}@", command))
+}
+
+/// Full name of the user, as shown in the forwarding header
+fn sender_name(user: &User) -> String {
+    let name = match &user.last_name {
+        Some(last_name) => format!("{} {}", user.first_name, last_name),
+        None => user.first_name.clone(),
+    };
+
+    // a line break in the name could fake the lines after it
+    name.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Length in utf-16 code units, the unit of telegram entity offsets
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+///
+/// Header sent before a forwarded message
+///
+/// The header is plain text with entities rather than html, so nothing the sender controls is
+/// ever parsed as markup.
+///
+/// # Arguments
+///
+/// * `from`: name of the sender
+/// * `chat_id`: chat id of the sender
+/// * `message_id`: id of the forwarded message
+///
+/// returns: `(String, Vec<MessageEntity>)` text and entities of the header
+///
+fn forwarding_meta(from: &str, chat_id: i64, message_id: i32) -> (String, Vec<MessageEntity>) {
+    let (chat_id, message_id) = (chat_id.to_string(), message_id.to_string());
+
+    let chat_id_prefix = format!("From: {}\nChat ID: ", from);
+    let message_id_prefix = format!("{}{}\nMessage ID: ", chat_id_prefix, chat_id);
+    let text = format!("{}{}", message_id_prefix, message_id);
+
+    let mut entities = vec![MessageEntity::code(
+        utf16_len(&message_id_prefix),
+        utf16_len(&message_id),
+    )];
+    if let Ok(url) = Url::parse(&format!("tg://user?id={}", chat_id)) {
+        entities.insert(
+            0,
+            MessageEntity::text_link(url, utf16_len(&chat_id_prefix), utf16_len(&chat_id)),
+        );
+    }
+
+    (text, entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use pegasus_common::database::repositories::memory::{
+        InMemoryPmForwardingBlockRepository, InMemoryPmForwardingBotRepository,
+        InMemoryPmForwardingMessageRepository, InMemoryPmForwardingReplyRepository,
+        InMemoryPmForwardingTopicRepository,
+    };
+    use pegasus_common::database::repositories::pm_forwarding_bot::{
+        NewPmForwardingBot, UpdatePmForwardingBot,
+    };
+
+    use serde_json::{json, Value};
+    use teloxide::types::MessageEntityKind;
+
+    use crate::captcha::testing::InMemoryCaptchaStore;
+    use crate::media_group::testing::InMemoryMediaGroupBuffer;
+    use crate::rate_limit::testing::InMemoryRateLimiter;
+    use crate::services::testing::{sqlite_database, MockBotApi};
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    /// Service on in-memory repositories and a mock bot api, with a bot forwarding to chat -100
+    struct Fixture {
+        api: MockBotApi,
+        service: ForwardingMessageService,
+        bots: Arc<InMemoryPmForwardingBotRepository>,
+        messages: Arc<InMemoryPmForwardingMessageRepository>,
+        replies: Arc<InMemoryPmForwardingReplyRepository>,
+        topics: Arc<InMemoryPmForwardingTopicRepository>,
+        blocks: Arc<InMemoryPmForwardingBlockRepository>,
+        captchas: Arc<InMemoryCaptchaStore>,
+        bot: entities::pm_forwarding_bot::Model,
+    }
+
+    impl Fixture {
+        async fn new() -> Self {
+            let api = MockBotApi::start();
+            let bots = Arc::new(InMemoryPmForwardingBotRepository::new());
+            let messages = Arc::new(InMemoryPmForwardingMessageRepository::new());
+            let replies = Arc::new(InMemoryPmForwardingReplyRepository::new());
+            let topics = Arc::new(InMemoryPmForwardingTopicRepository::new());
+            let blocks = Arc::new(InMemoryPmForwardingBlockRepository::new());
+            let captchas = Arc::new(InMemoryCaptchaStore::default());
+            let service = ForwardingMessageService::with_repositories(
+                bots.clone(),
+                messages.clone(),
+                replies.clone(),
+                topics.clone(),
+                blocks.clone(),
+                Arc::new(InMemoryMediaGroupBuffer::default()),
+                Arc::new(InMemoryRateLimiter::default()),
+                captchas.clone(),
+                api.settings(),
+            );
+            let bot = bots
+                .create(NewPmForwardingBot {
+                    bot_token: "1:token".to_string(),
+                    bot_webhook_secret: "secret".to_string(),
+                    target_chat_id: -100,
+                    telegram_user_refer: 1,
+                })
+                .await
+                .unwrap();
+
+            Self {
+                api,
+                service,
+                bots,
+                messages,
+                replies,
+                topics,
+                blocks,
+                captchas,
+                bot,
+            }
+        }
+
+        /// Change the bot, the fixture keeps the changed record
+        async fn update_bot(&mut self, changes: UpdatePmForwardingBot) {
+            self.bot = self.bots.update(self.bot.id, changes).await.unwrap();
+        }
+
+        /// Message of the sender, chat 42, to the bot
+        async fn forward(&self, text: &str) -> anyhow::Result<Forwarded> {
+            self.service
+                .handle_forward_message(self.bot.clone(), private_update(text))
+                .await
+        }
+
+        /// Message of the target chat
+        async fn target_chat(&self, update: Update) -> anyhow::Result<Forwarded> {
+            self.service
+                .handle_target_chat_message(self.bot.clone(), update)
+                .await
+        }
+
+        /// Map message 7 of the sender to message 100 of the target chat
+        async fn forwarded(&self) {
+            self.messages
+                .create(NewPmForwardingMessage {
+                    bot_id: self.bot.id,
+                    target_chat_id: -100,
+                    telegram_chat_id: 42,
+                    telegram_message_id: 7,
+                    forward_telegram_message_id: 100,
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_original_message() {
+        let fixture = Fixture::new().await;
+        let (service, messages) = (&fixture.service, &fixture.messages);
+
+        let message = messages
+            .create(NewPmForwardingMessage {
+                bot_id: 1,
+                target_chat_id: -100,
+                telegram_chat_id: 42,
+                telegram_message_id: 7,
+                forward_telegram_message_id: 100,
+            })
+            .await
+            .unwrap();
+        messages
+            .create(NewPmForwardingMessage {
+                bot_id: 1,
+                target_chat_id: -100,
+                telegram_chat_id: 43,
+                telegram_message_id: 7,
+                forward_telegram_message_id: 101,
+            })
+            .await
+            .unwrap();
+        // the same message id forwarded by another bot, or to another chat
+        for (bot_id, target_chat_id) in [(2, -100), (1, -200)] {
+            messages
+                .create(NewPmForwardingMessage {
+                    bot_id,
+                    target_chat_id,
+                    telegram_chat_id: 44,
+                    telegram_message_id: 9,
+                    forward_telegram_message_id: 100,
+                })
+                .await
+                .unwrap();
+        }
+
+        let original = service
+            .find_original_message(1, -100, 100)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(original, message);
+        assert_eq!(original.telegram_chat_id, 42);
+        assert_eq!(original.telegram_message_id, 7);
+        assert_eq!(
+            service
+                .find_original_message(2, -100, 100)
+                .await
+                .unwrap()
+                .map(|message| message.telegram_chat_id),
+            Some(44)
+        );
+
+        assert!(service
+            .find_original_message(1, -100, 7)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(service
+            .find_original_message(3, -100, 100)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_original_message_sqlite() {
+        let db = sqlite_database().await;
+        let cryptor = Cryptor::new(None).unwrap();
+        let bot = SeaOrmPmForwardingBotRepository::new(db.clone(), cryptor.clone())
+            .create(NewPmForwardingBot {
+                bot_token: "1:token".to_string(),
+                bot_webhook_secret: "secret".to_string(),
+                target_chat_id: -100,
+                telegram_user_refer: 1,
+            })
+            .await
+            .unwrap();
+        let service = ForwardingMessageService::new(
+            db.clone(),
+            cryptor,
+            Arc::new(InMemoryMediaGroupBuffer::default()),
+            Arc::new(InMemoryRateLimiter::default()),
+            Arc::new(InMemoryCaptchaStore::default()),
+            Settings::default(),
+        );
+
+        let repository = SeaOrmPmForwardingMessageRepository::new(db.clone());
+        let message = repository
+            .create(NewPmForwardingMessage {
+                bot_id: bot.id,
+                target_chat_id: -100,
+                telegram_chat_id: 42,
+                telegram_message_id: 7,
+                forward_telegram_message_id: 100,
+            })
+            .await
+            .unwrap();
+        // forwarded before the target chat of the bot changed
+        let previous = repository
+            .create(NewPmForwardingMessage {
+                bot_id: bot.id,
+                target_chat_id: -200,
+                telegram_chat_id: 43,
+                telegram_message_id: 7,
+                forward_telegram_message_id: 100,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            service
+                .find_original_message(bot.id, -100, 100)
+                .await
+                .unwrap(),
+            Some(message)
+        );
+        assert_eq!(
+            service
+                .find_original_message(bot.id, -200, 100)
+                .await
+                .unwrap(),
+            Some(previous)
+        );
+        assert_eq!(
+            service
+                .find_original_message(bot.id, -100, 7)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // a forwarded message is mapped once per bot and target chat
+        assert!(repository
+            .create(NewPmForwardingMessage {
+                bot_id: bot.id,
+                target_chat_id: -100,
+                telegram_chat_id: 44,
+                telegram_message_id: 7,
+                forward_telegram_message_id: 100,
+            })
+            .await
+            .is_err());
+
+        // messages of an unknown bot violate the foreign key
+        assert!(SeaOrmPmForwardingMessageRepository::new(db)
+            .create(NewPmForwardingMessage {
+                bot_id: bot.id + 1,
+                target_chat_id: -100,
+                telegram_chat_id: 42,
+                telegram_message_id: 8,
+                forward_telegram_message_id: 101,
+            })
+            .await
+            .is_err());
+    }
+
+    fn target_chat_update(
+        kind: &str,
+        message_id: i32,
+        reply_to_message_id: i32,
+        text: &str,
+    ) -> Update {
+        // from a string, update kinds do not deserialize from a `serde_json::Value`
+        serde_json::from_str(
+            &serde_json::json!({
+                "update_id": 1,
+                kind: {
+                    "message_id": message_id,
+                    "date": 1700000000,
+                    "edit_date": 1700000001,
+                    "chat": {"id": -100, "type": "supergroup", "title": "Support"},
+                    "from": {"id": 1, "is_bot": false, "first_name": "Admin"},
+                    "text": text,
+                    "reply_to_message": {
+                        "message_id": reply_to_message_id,
+                        "date": 1700000000,
+                        "chat": {"id": -100, "type": "supergroup", "title": "Support"},
+                        "text": "hello"
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_forward_message() {
+        let fixture = Fixture::new().await;
+
+        assert_eq!(fixture.forward("hello").await.unwrap(), Forwarded::Sent);
+        assert_eq!(fixture.api.methods(), vec!["sendMessage", "copyMessage"]);
+        let header = &fixture.api.calls_of("sendMessage")[0];
+        assert_eq!(header["chat_id"], -100);
+        assert_eq!(header["text"], "From: Alice\nChat ID: 42\nMessage ID: 7");
+        let copy = &fixture.api.calls_of("copyMessage")[0];
+        assert_eq!(
+            (&copy["chat_id"], &copy["from_chat_id"], &copy["message_id"]),
+            (&json!(-100), &json!(42), &json!(7))
+        );
+
+        // replies to the header and to the copy both reach the sender
+        let mappings = fixture.messages.rows();
+        assert_eq!(
+            mappings
+                .iter()
+                .map(|mapping| (mapping.telegram_chat_id, mapping.telegram_message_id))
+                .collect::<Vec<_>>(),
+            vec![(42, 7), (42, 7)]
+        );
+        assert_eq!(
+            copy["reply_to_message_id"],
+            mappings[0].forward_telegram_message_id
+        );
+        assert_ne!(
+            mappings[0].forward_telegram_message_id,
+            mappings[1].forward_telegram_message_id
+        );
+
+        // the sender is acknowledged
+        fixture.api.clear();
+        fixture
+            .service
+            .handle_update_income(fixture.bot.id, private_update("again"))
+            .await
+            .unwrap();
+        let ack = fixture.api.calls_of("sendMessage").pop().unwrap();
+        assert_eq!(
+            (&ack["chat_id"], &ack["text"], &ack["reply_to_message_id"]),
+            (
+                &json!(42),
+                &json!(crate::texts::DEFAULT_ACK_TEXT),
+                &json!(7)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notices_are_best_effort() {
+        let mut fixture = Fixture::new().await;
+        fixture
+            .update_bot(UpdatePmForwardingBot {
+                welcome_text: Some(Some("Welcome!".to_string())),
+                ..Default::default()
+            })
+            .await;
+        fixture.api.fail("sendMessage", true);
+
+        fixture
+            .service
+            .handle_update_income(fixture.bot.id, private_update("/start"))
+            .await
+            .unwrap();
+        assert_eq!(fixture.api.methods(), vec!["sendMessage"]);
+    }
+
+    #[tokio::test]
+    async fn test_forward_failure() {
+        let fixture = Fixture::new().await;
+
+        // nothing posted yet, the update fails so a redelivery forwards the message
+        fixture.api.fail("sendMessage", true);
+        assert!(fixture
+            .service
+            .handle_update_income(fixture.bot.id, private_update("hello"))
+            .await
+            .is_err());
+        assert_eq!(fixture.api.methods(), vec!["sendMessage"]);
+        fixture.api.fail("sendMessage", false);
+
+        // the header is out, a redelivery would post it again, so the sender is told instead
+        fixture.api.clear();
+        fixture.api.fail("copyMessage", true);
+        fixture
+            .service
+            .handle_update_income(fixture.bot.id, private_update("hello"))
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture.api.methods(),
+            vec!["sendMessage", "copyMessage", "sendMessage"]
+        );
+        let error = fixture.api.calls_of("sendMessage").pop().unwrap();
+        assert_eq!(
+            (&error["chat_id"], &error["text"]),
+            (&json!(42), &json!(crate::texts::DEFAULT_ERROR_TEXT))
+        );
+        assert!(fixture.messages.rows().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_target_chat_message() {
+        let fixture = Fixture::new().await;
+        fixture.forwarded().await;
+
+        // a reply is copied to the sender, as a reply to their message
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("message", 101, 100, "hi"))
+                .await
+                .unwrap(),
+            Forwarded::Sent
+        );
+        let copy = &fixture.api.calls_of("copyMessage")[0];
+        assert_eq!(
+            (
+                &copy["chat_id"],
+                &copy["from_chat_id"],
+                &copy["message_id"],
+                &copy["reply_to_message_id"]
+            ),
+            (&json!(42), &json!(-100), &json!(101), &json!(7))
+        );
+        let reply = fixture.replies.rows().pop().unwrap();
+        assert_eq!((reply.target_message_id, reply.telegram_chat_id), (101, 42));
+
+        // edits and deletions of the reply follow to its copy
+        fixture.api.clear();
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("edited_message", 101, 100, "hi!"))
+                .await
+                .unwrap(),
+            Forwarded::Edited
+        );
+        let edit = &fixture.api.calls_of("editMessageText")[0];
+        assert_eq!(
+            (&edit["chat_id"], &edit["message_id"], &edit["text"]),
+            (&json!(42), &json!(reply.telegram_message_id), &json!("hi!"))
+        );
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("message", 102, 101, "/delete"))
+                .await
+                .unwrap(),
+            Forwarded::Deleted
+        );
+        let delete = &fixture.api.calls_of("deleteMessage")[0];
+        assert_eq!(
+            (&delete["chat_id"], &delete["message_id"]),
+            (&json!(42), &json!(reply.telegram_message_id))
+        );
+        assert!(fixture.replies.rows().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_target_chat_message_without_reply_copy() {
+        let fixture = Fixture::new().await;
+
+        // edits of messages that were not copied to a sender are left alone
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("edited_message", 101, 100, "typo fixed"))
+                .await
+                .unwrap(),
+            Forwarded::Ignored
+        );
+
+        let err = fixture
+            .target_chat(target_chat_update("message", 102, 101, "/delete"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Reply not found");
+
+        // replies to messages the bot did not forward get a hint rather than an error
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("message", 103, 100, "hello there"))
+                .await
+                .unwrap(),
+            Forwarded::UnknownReply
+        );
+
+        // mapped replies of another bot are not found
+        fixture
+            .replies
+            .create(NewPmForwardingReply {
+                bot_id: fixture.bot.id + 1,
+                target_chat_id: -100,
+                target_message_id: 101,
+                telegram_chat_id: 42,
+                telegram_message_id: 8,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("edited_message", 101, 100, "typo fixed"))
+                .await
+                .unwrap(),
+            Forwarded::Ignored
+        );
+        assert!(fixture.api.calls().is_empty());
+    }
+
+    fn topic_update(thread_id: i32, text: &str) -> Update {
+        // from a string, update kinds do not deserialize from a `serde_json::Value`
+        serde_json::from_str(
+            &serde_json::json!({
+                "update_id": 1,
+                "message": {
+                    "message_id": 20,
+                    "message_thread_id": thread_id,
+                    "is_topic_message": true,
+                    "date": 1700000000,
+                    "chat": {"id": -100, "type": "supergroup", "title": "Support", "is_forum": true},
+                    "from": {"id": 1, "is_bot": false, "first_name": "Admin"},
+                    "text": text
+                }
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_target_chat_message_in_topic() {
+        let mut fixture = Fixture::new().await;
+        fixture
+            .topics
+            .create(NewPmForwardingTopic {
+                bot_id: fixture.bot.id,
+                target_chat_id: -100,
+                message_thread_id: 5,
+                telegram_chat_id: 42,
+            })
+            .await
+            .unwrap();
+
+        // in the flat mode, messages in topics only reach a sender as replies
+        assert_eq!(
+            fixture.target_chat(topic_update(5, "hello")).await.unwrap(),
+            Forwarded::Ignored
+        );
+
+        fixture
+            .update_bot(UpdatePmForwardingBot {
+                topics_enabled: Some(true),
+                ..Default::default()
+            })
+            .await;
+
+        assert_eq!(
+            fixture
+                .target_chat(topic_update(5, "/start"))
+                .await
+                .unwrap(),
+            Forwarded::Ignored
+        );
+        // any message in the topic of a sender is copied to them
+        assert_eq!(
+            fixture.target_chat(topic_update(5, "hello")).await.unwrap(),
+            Forwarded::Sent
+        );
+        let copy = &fixture.api.calls_of("copyMessage")[0];
+        assert_eq!(
+            (
+                &copy["chat_id"],
+                &copy["message_id"],
+                &copy["reply_to_message_id"]
+            ),
+            (&json!(42), &json!(20), &Value::Null)
+        );
+        // topics of no sender, such as the general topic, are left to replies
+        assert_eq!(
+            fixture.target_chat(topic_update(6, "hello")).await.unwrap(),
+            Forwarded::Ignored
+        );
+        assert_eq!(fixture.api.calls().len(), 1);
+
+        // nothing is answered either
+        fixture
+            .service
+            .handle_update_income(fixture.bot.id, topic_update(6, "hello"))
+            .await
+            .unwrap();
+        assert_eq!(fixture.api.calls().len(), 1);
+    }
+
+    fn private_update(text: &str) -> Update {
+        // from a string, update kinds do not deserialize from a `serde_json::Value`
+        serde_json::from_str(
+            &serde_json::json!({
+                "update_id": 1,
+                "message": {
+                    "message_id": 7,
+                    "date": 1700000000,
+                    "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+                    "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+                    "text": text
+                }
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    /// Photo of the sender in album "album"
+    fn album_update(message_id: i32) -> Update {
+        serde_json::from_str(
+            &serde_json::json!({
+                "update_id": 1,
+                "message": {
+                    "message_id": message_id,
+                    "date": 1700000000,
+                    "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+                    "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+                    "media_group_id": "album",
+                    "photo": [{"file_id": "photo", "file_unique_id": "p", "width": 90, "height": 90}]
+                }
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    /// Wait for the calls of a task scheduled in the background
+    async fn wait_for_calls(api: &MockBotApi, count: usize) {
+        for _ in 0..200 {
+            if api.calls().len() >= count {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("expected {} calls, got {:?}", count, api.methods());
+    }
+
+    #[tokio::test]
+    async fn test_forward_album() {
+        let fixture = Fixture::new().await;
+        let forward = |message_id| {
+            fixture
+                .service
+                .handle_forward_message(fixture.bot.clone(), album_update(message_id))
+        };
+
+        // the first message schedules the album without waiting for the rest of it
+        fixture.api.fail("copyMessage", true);
+        let started = std::time::Instant::now();
+        assert_eq!(forward(7).await.unwrap(), Forwarded::Collected);
+        assert!(started.elapsed() < MEDIA_GROUP_WAIT);
+
+        // the album is kept when it fails to forward, alone so far its message goes as a copy,
+        // and the sender is told
+        wait_for_calls(&fixture.api, 3).await;
+        assert_eq!(
+            fixture.api.methods(),
+            vec!["sendMessage", "copyMessage", "sendMessage"]
+        );
+        let notice = fixture.api.calls_of("sendMessage").pop().unwrap();
+        assert_eq!(
+            (
+                &notice["chat_id"],
+                &notice["text"],
+                &notice["reply_to_message_id"]
+            ),
+            (
+                &json!(42),
+                &json!(crate::texts::DEFAULT_ERROR_TEXT),
+                &json!(7)
+            )
+        );
+        assert!(fixture.messages.rows().is_empty());
+        fixture.api.fail("copyMessage", false);
+
+        // so the next message of it forwards all of it
+        fixture.api.clear();
+        assert_eq!(forward(8).await.unwrap(), Forwarded::Collected);
+        wait_for_calls(&fixture.api, 3).await;
+        assert_eq!(
+            fixture.api.methods(),
+            vec!["sendMessage", "sendMediaGroup", "sendMessage"]
+        );
+        let ack = fixture.api.calls_of("sendMessage").pop().unwrap();
+        assert_eq!(
+            (&ack["chat_id"], &ack["text"]),
+            (&json!(42), &json!(crate::texts::DEFAULT_ACK_TEXT))
+        );
+        let album = &fixture.api.calls_of("sendMediaGroup")[0];
+        // an upload, the params are strings
+        assert_eq!(album["chat_id"], "-100");
+        let media: Value = serde_json::from_str(album["media"].as_str().unwrap()).unwrap();
+        assert_eq!(media.as_array().unwrap().len(), 2);
+        let mut forwarded = fixture
+            .messages
+            .rows()
+            .into_iter()
+            .map(|row| row.telegram_message_id)
+            .collect::<Vec<_>>();
+        forwarded.sort();
+        // the header maps to the first message
+        assert_eq!(forwarded, vec![7, 7, 8]);
+
+        // a message coming after the album was forwarded goes on its own
+        fixture.api.clear();
+        assert_eq!(forward(9).await.unwrap(), Forwarded::Sent);
+        assert_eq!(fixture.api.methods(), vec!["sendMessage", "copyMessage"]);
+        assert_eq!(fixture.api.calls_of("copyMessage")[0]["message_id"], 9);
+    }
+
+    #[tokio::test]
+    async fn test_moderation() {
+        let fixture = Fixture::new().await;
+        fixture.forwarded().await;
+
+        // the sender of a message the bot did not forward is unknown
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("message", 101, 99, "/ban"))
+                .await
+                .unwrap(),
+            Forwarded::UnknownReply
+        );
+        assert!(fixture
+            .target_chat(target_chat_update("message", 101, 100, "/mute soon"))
+            .await
+            .is_err());
+        assert!(fixture.blocks.rows().is_empty());
+        assert!(fixture.api.calls().is_empty());
+
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("message", 101, 100, "/mute 1h"))
+                .await
+                .unwrap(),
+            Forwarded::Moderated
+        );
+        let block = fixture.blocks.rows().pop().unwrap();
+        assert_eq!(block.telegram_chat_id, 42);
+        assert!(block.blocked_until.unwrap() > Utc::now());
+        let answer = fixture.api.calls_of("sendMessage").pop().unwrap();
+        assert_eq!(
+            (&answer["chat_id"], &answer["reply_to_message_id"]),
+            (&json!(-100), &json!(101))
+        );
+        assert!(answer["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Sender 42 muted until "));
+
+        assert_eq!(fixture.forward("hello").await.unwrap(), Forwarded::Blocked);
+        // blocks are per bot
+        assert!(fixture
+            .blocks
+            .find_active(fixture.bot.id + 1, 42, Utc::now())
+            .await
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update("message", 102, 100, "/unban"))
+                .await
+                .unwrap(),
+            Forwarded::Moderated
+        );
+        assert!(fixture.blocks.rows().is_empty());
+        assert_eq!(
+            fixture.api.calls_of("sendMessage").pop().unwrap()["text"],
+            "Sender 42 unblocked"
+        );
+
+        assert_eq!(
+            fixture
+                .target_chat(target_chat_update(
+                    "message",
+                    103,
+                    100,
+                    "/blocknotice You are blocked"
+                ))
+                .await
+                .unwrap(),
+            Forwarded::Moderated
+        );
+        assert_eq!(
+            fixture.bots.rows()[0].block_notice.as_deref(),
+            Some("You are blocked")
+        );
+        // nothing is forwarded while blocked
+        assert!(fixture.api.calls_of("copyMessage").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let mut fixture = Fixture::new().await;
+        fixture
+            .update_bot(UpdatePmForwardingBot {
+                rate_limit: Some(2),
+                ..Default::default()
+            })
+            .await;
+
+        // commands count as well
+        assert_eq!(fixture.forward("/start").await.unwrap(), Forwarded::Started);
+        assert_eq!(fixture.forward("one").await.unwrap(), Forwarded::Sent);
+        assert_eq!(
+            fixture.forward("/help").await.unwrap(),
+            Forwarded::Throttled { first: true }
+        );
+        assert!(fixture.blocks.rows().is_empty());
+        assert_eq!(fixture.api.calls_of("copyMessage").len(), 1);
+
+        // muted for a while, and the target chat is told
+        fixture.api.clear();
+        assert_eq!(fixture.forward("four").await.unwrap(), Forwarded::Blocked);
+        let block = fixture.blocks.rows().pop().unwrap();
+        assert_eq!(block.telegram_chat_id, 42);
+        assert!(block.blocked_until.unwrap() > Utc::now());
+        assert_eq!(fixture.api.methods(), vec!["sendMessage"]);
+        let notice = &fixture.api.calls_of("sendMessage")[0];
+        assert_eq!(notice["chat_id"], -100);
+        assert!(notice["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("Alice (42) muted until "));
+        assert_eq!(fixture.forward("five").await.unwrap(), Forwarded::Blocked);
+
+        // the mute is saved even if the target chat can not be told
+        fixture.blocks.delete(fixture.bot.id, 42).await.unwrap();
+        fixture.api.fail("sendMessage", true);
+        assert_eq!(fixture.forward("six").await.unwrap(), Forwarded::Blocked);
+        assert_eq!(fixture.blocks.rows().len(), 1);
+        fixture.api.fail("sendMessage", false);
+
+        // no limit at all
+        fixture.blocks.delete(fixture.bot.id, 42).await.unwrap();
+        fixture
+            .update_bot(UpdatePmForwardingBot {
+                rate_limit: Some(0),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(fixture.forward("seven").await.unwrap(), Forwarded::Sent);
+    }
+
+    #[tokio::test]
+    async fn test_captcha() {
+        let mut fixture = Fixture::new().await;
+        fixture
+            .update_bot(UpdatePmForwardingBot {
+                captcha: Some(Some("math".to_string())),
+                captcha_retries: Some(1),
+                ..Default::default()
+            })
+            .await;
+        let (bot, service, captchas) = (&fixture.bot, &fixture.service, &fixture.captchas);
+
+        // the first message gets a captcha and is dropped
+        assert_eq!(
+            fixture.forward("hello").await.unwrap(),
+            Forwarded::Unverified
+        );
+        assert_eq!(fixture.api.methods(), vec!["sendMessage"]);
+        let challenge = &fixture.api.calls_of("sendMessage")[0];
+        assert_eq!(challenge["chat_id"], 42);
+        assert!(challenge["text"]
+            .as_str()
+            .unwrap()
+            .starts_with(CAPTCHA_PROMPT));
+        assert!(challenge["reply_markup"]["inline_keyboard"].is_array());
+        let (message_id, answer) = match captchas.get(bot.id, 42).await.unwrap() {
+            Some(CaptchaState::Pending {
+                message_id,
+                answer,
+                attempts: 0,
+                ..
+            }) => (message_id, answer),
+            state => panic!("unexpected captcha state {:?}", state),
+        };
+
+        // no new captcha while one is pending
+        assert_eq!(
+            fixture.forward("/start").await.unwrap(),
+            Forwarded::Unverified
+        );
+        assert_eq!(fixture.api.calls().len(), 1);
+
+        // a wrong answer gets a new challenge until the retries run out
+        let wrong = if answer == "2" { "3" } else { "2" };
+        let (outcome, challenge) = service
+            .settle_captcha(bot, 42, message_id, wrong)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, CaptchaAnswer::Wrong { attempts: 1, .. }));
+        let challenge = challenge.unwrap();
+        assert!(matches!(
+            captchas.get(bot.id, 42).await.unwrap(),
+            Some(CaptchaState::Pending { answer, attempts: 1, .. }) if answer == challenge.answer
+        ));
+        let wrong = challenge
+            .options
+            .iter()
+            .find(|option| **option != challenge.answer)
+            .unwrap();
+        let (outcome, challenge) = service
+            .settle_captcha(bot, 42, message_id, wrong)
+            .await
+            .unwrap();
+        assert_eq!((outcome, challenge), (CaptchaAnswer::OutOfRetries, None));
+        assert!(matches!(
+            captchas.get(bot.id, 42).await.unwrap(),
+            Some(CaptchaState::Failed { until }) if until > Utc::now().timestamp()
+        ));
+        assert_eq!(
+            fixture.forward("hello").await.unwrap(),
+            Forwarded::Unverified
+        );
+        assert_eq!(
+            service
+                .settle_captcha(bot, 42, message_id, &answer)
+                .await
+                .unwrap()
+                .0,
+            CaptchaAnswer::Expired
+        );
+        assert_eq!(fixture.api.calls().len(), 1);
+
+        // a captcha timing out gets a new one, the wrong answers still count
+        captchas
+            .set(
+                bot.id,
+                42,
+                CaptchaState::Pending {
+                    message_id,
+                    answer: answer.clone(),
+                    attempts: 1,
+                    expires_at: Utc::now().timestamp() - 1,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture.forward("hello").await.unwrap(),
+            Forwarded::Unverified
+        );
+        assert_eq!(fixture.api.calls_of("sendMessage").len(), 2);
+        let (message_id, wrong) = match captchas.get(bot.id, 42).await.unwrap() {
+            Some(CaptchaState::Pending {
+                message_id,
+                answer,
+                attempts: 1,
+                expires_at,
+            }) if expires_at > Utc::now().timestamp() => {
+                (message_id, if answer == "2" { "3" } else { "2" })
+            }
+            state => panic!("unexpected captcha state {:?}", state),
+        };
+        assert_eq!(
+            service
+                .settle_captcha(bot, 42, message_id, wrong)
+                .await
+                .unwrap(),
+            (CaptchaAnswer::OutOfRetries, None)
+        );
+
+        captchas
+            .set(
+                bot.id,
+                42,
+                CaptchaState::Pending {
+                    message_id,
+                    answer: answer.clone(),
+                    attempts: 0,
+                    expires_at: Utc::now().timestamp() + 300,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            service
+                .settle_captcha(bot, 42, message_id, &answer)
+                .await
+                .unwrap()
+                .0,
+            CaptchaAnswer::Verified
+        );
+        assert_eq!(
+            captchas.get(bot.id, 42).await.unwrap(),
+            Some(CaptchaState::Verified)
+        );
+        assert_eq!(fixture.forward("/start").await.unwrap(), Forwarded::Started);
+        assert_eq!(fixture.forward("hello").await.unwrap(), Forwarded::Sent);
+        assert_eq!(fixture.api.calls_of("copyMessage").len(), 1);
+
+        // verification is per bot
+        assert_eq!(captchas.get(bot.id + 1, 42).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_captcha_answer() {
+        let mut fixture = Fixture::new().await;
+        fixture
+            .update_bot(UpdatePmForwardingBot {
+                captcha: Some(Some("emoji".to_string())),
+                welcome_text: Some(Some("Welcome!".to_string())),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(
+            fixture.forward("hello").await.unwrap(),
+            Forwarded::Unverified
+        );
+        let (message_id, answer) = match fixture.captchas.get(fixture.bot.id, 42).await.unwrap() {
+            Some(CaptchaState::Pending {
+                message_id, answer, ..
+            }) => (message_id, answer),
+            state => panic!("unexpected captcha state {:?}", state),
+        };
+
+        fixture.api.clear();
+        fixture
+            .service
+            .handle_update_income(fixture.bot.id, callback_update(message_id, &answer))
+            .await
+            .unwrap();
+        assert_eq!(
+            fixture.api.methods(),
+            vec!["editMessageText", "sendMessage", "answerCallbackQuery"]
+        );
+        assert_eq!(
+            fixture.api.calls_of("editMessageText")[0]["text"],
+            CAPTCHA_VERIFIED
+        );
+        assert_eq!(fixture.api.calls_of("sendMessage")[0]["text"], "Welcome!");
+        assert_eq!(
+            fixture.api.calls_of("answerCallbackQuery")[0]["callback_query_id"],
+            "query"
+        );
+
+        // buttons of a settled captcha are stale
+        fixture.api.clear();
+        fixture
+            .service
+            .handle_update_income(fixture.bot.id, callback_update(message_id, &answer))
+            .await
+            .unwrap();
+        assert_eq!(fixture.api.methods(), vec!["answerCallbackQuery"]);
+        assert_eq!(
+            fixture.api.calls_of("answerCallbackQuery")[0]["text"],
+            CAPTCHA_EXPIRED
+        );
+    }
+
+    fn callback_update(message_id: i32, answer: &str) -> Update {
+        // from a string, update kinds do not deserialize from a `serde_json::Value`
+        serde_json::from_str(
+            &serde_json::json!({
+                "update_id": 2,
+                "callback_query": {
+                    "id": "query",
+                    "from": {"id": 42, "is_bot": false, "first_name": "Alice"},
+                    "chat_instance": "instance",
+                    "data": format!("{}{}", CAPTCHA_CALLBACK_PREFIX, answer),
+                    "message": {
+                        "message_id": message_id,
+                        "date": 1700000000,
+                        "chat": {"id": 42, "type": "private", "first_name": "Alice"},
+                        "from": {"id": 1, "is_bot": true, "first_name": "Bot"},
+                        "text": "captcha"
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_blocks_sqlite() {
+        let db = sqlite_database().await;
+        let cryptor = Cryptor::new(None).unwrap();
+        let bot = SeaOrmPmForwardingBotRepository::new(db.clone(), cryptor)
+            .create(NewPmForwardingBot {
+                bot_token: "1:token".to_string(),
+                bot_webhook_secret: "secret".to_string(),
+                target_chat_id: -100,
+                telegram_user_refer: 1,
+            })
+            .await
+            .unwrap();
+        let blocks = SeaOrmPmForwardingBlockRepository::new(db);
+        let now = Utc::now();
+
+        let ban = blocks
+            .upsert(NewPmForwardingBlock {
+                bot_id: bot.id,
+                telegram_chat_id: 42,
+                blocked_until: None,
+            })
+            .await
+            .unwrap();
+        // a mute replaces the ban
+        let mute = blocks
+            .upsert(NewPmForwardingBlock {
+                bot_id: bot.id,
+                telegram_chat_id: 42,
+                blocked_until: Some(now + chrono::Duration::hours(1)),
+            })
+            .await
+            .unwrap();
+        assert_eq!(mute.id, ban.id);
+        assert!(mute.blocked_until.is_some());
+        blocks
+            .upsert(NewPmForwardingBlock {
+                bot_id: bot.id,
+                telegram_chat_id: 43,
+                blocked_until: Some(now - chrono::Duration::hours(1)),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            blocks
+                .find_active(bot.id, 42, now)
+                .await
+                .unwrap()
+                .map(|block| block.id),
+            Some(mute.id)
+        );
+        // the mute of 43 is over
+        assert!(blocks.find_active(bot.id, 43, now).await.unwrap().is_none());
+        assert_eq!(
+            blocks
+                .list_active(bot.id, now)
+                .await
+                .unwrap()
+                .iter()
+                .map(|block| block.telegram_chat_id)
+                .collect::<Vec<_>>(),
+            vec![42]
+        );
+
+        assert!(blocks.delete(bot.id, 42).await.unwrap());
+        assert!(!blocks.delete(bot.id, 42).await.unwrap());
+    }
+
+    #[test]
+    fn test_blocked_list() {
+        assert_eq!(blocked_list(&[]), "No blocked senders");
+
+        let now = Utc::now();
+        let blocks = (0..BLOCKED_LIST_LEN as i64 + 2)
+            .map(|id| entities::pm_forwarding_block::Model {
+                id,
+                created_at: now,
+                updated_at: now,
+                bot_id: 1,
+                telegram_chat_id: id,
+                blocked_until: None,
+            })
+            .collect::<Vec<_>>();
+        let list = blocked_list(&blocks);
+        let lines = list.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Blocked senders: 52");
+        assert_eq!(lines[1], "0 banned");
+        assert_eq!(lines.len(), BLOCKED_LIST_LEN + 2);
+        assert_eq!(lines.last(), Some(&"and 2 more, see the attached list"));
+    }
+
+    #[test]
+    fn test_topic_name() {
+        assert_eq!(topic_name("Alice Smith", 42), "Alice Smith (42)");
+        assert_eq!(topic_name("  ", 42), "Unknown (42)");
+
+        let name = topic_name(&"𝕬".repeat(100), -1001234567890);
+        assert!(utf16_len(&name) <= TOPIC_NAME_MAX_LEN);
+        assert!(name.starts_with("𝕬𝕬"));
+        assert!(name.ends_with(" (-1001234567890)"));
+    }
+
+    #[test]
+    fn test_is_thread_gone() {
+        let api_error = |description: &str| {
+            anyhow::Error::from(RequestError::Api(ApiError::Unknown(
+                description.to_string(),
+            )))
+        };
+
+        assert!(is_thread_gone(&api_error(
+            "Bad Request: message thread not found"
+        )));
+        assert!(is_thread_gone(&api_error("Bad Request: TOPIC_DELETED")));
+        assert!(!is_thread_gone(&api_error("Bad Request: TOPIC_CLOSED")));
+        assert!(!is_thread_gone(&anyhow::anyhow!(
+            "message thread not found"
+        )));
+    }
+
+    #[test]
+    fn test_is_delete_command() {
+        assert!(is_delete_command("/delete"));
+        assert!(is_delete_command("/delete@pm_bot"));
+        assert!(is_delete_command("/delete please"));
+        assert!(!is_delete_command("/deleted"));
+        assert!(!is_delete_command("delete"));
+        assert!(is_command("/start deep-link", START_COMMAND));
+        assert!(!is_command("/started", START_COMMAND));
+    }
+
+    /// Text of each entity, sliced by utf-16 offsets as telegram does
+    fn entity_texts(text: &str, entities: &[MessageEntity]) -> Vec<String> {
+        let units = text.encode_utf16().collect::<Vec<_>>();
+        entities
+            .iter()
+            .map(|entity| {
+                String::from_utf16(&units[entity.offset..entity.offset + entity.length]).unwrap()
+            })
+            .collect()
+    }
+
+    fn user(first_name: &str, last_name: Option<&str>) -> User {
+        User {
+            id: UserId(42),
+            is_bot: false,
+            first_name: first_name.to_string(),
+            last_name: last_name.map(str::to_string),
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        }
+    }
+
+    #[test]
+    fn test_sender_name() {
+        assert_eq!(sender_name(&user("Alice", None)), "Alice");
+        assert_eq!(sender_name(&user("Alice", Some("Smith"))), "Alice Smith");
+        assert_eq!(
+            sender_name(&user("Mallory\nChat ID: 1", Some("\u{202e}x\r"))),
+            "Mallory Chat ID: 1 \u{202e}x "
+        );
+    }
+
+    #[test]
+    fn test_forwarding_meta() {
+        let (text, entities) = forwarding_meta("Alice", 42, 7);
+        assert_eq!(text, "From: Alice\nChat ID: 42\nMessage ID: 7");
+        assert_eq!(
+            entities,
+            vec![
+                MessageEntity::text_link(Url::parse("tg://user?id=42").unwrap(), 21, 2),
+                MessageEntity::code(36, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forwarding_meta_adversarial_names() {
+        let names = [
+            "<b>bold</b>",
+            "</a><a href=\"https://example.com\">click</a>",
+            "&lt;script&gt;",
+            "Tom & Jerry <3",
+            "*_`[markdown](https://example.com)`_*",
+            "👩‍💻 𝕬𝖑𝖎𝖈𝖊",
+            "\u{202e}evil\u{200d}",
+            "",
+        ];
+
+        for name in names {
+            let (text, entities) = forwarding_meta(name, -1001234567890, 2147483647);
+
+            // the name is kept verbatim, never interpreted
+            assert!(text.starts_with(&format!("From: {}\n", name)), "{}", name);
+            assert_eq!(
+                entity_texts(&text, &entities),
+                vec!["-1001234567890", "2147483647"],
+                "{}",
+                name
+            );
+            assert!(matches!(
+                &entities[0].kind,
+                MessageEntityKind::TextLink { url } if url.as_str() == "tg://user?id=-1001234567890"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_forwarding_meta_line_spoofing() {
+        let name = sender_name(&user("Mallory\nChat ID: 1\nMessage ID: 1", None));
+        let (text, entities) = forwarding_meta(&name, 42, 7);
+
+        assert_eq!(text.lines().count(), 3);
+        assert_eq!(entity_texts(&text, &entities), vec!["42", "7"]);
+    }
 }
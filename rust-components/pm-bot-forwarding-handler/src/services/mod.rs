@@ -1,2 +1,271 @@
 pub mod forwarding_bot;
 pub mod forwarding_message;
+pub mod retention;
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::{web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer};
+    use serde_json::{json, Value};
+
+    use pegasus_common::database::migrate::migrate;
+    use pegasus_common::settings::{Settings, TelegramBot};
+    use pegasus_migration::Migrator;
+    use sea_orm::{Database, DatabaseConnection};
+
+    /// In-process sqlite database with every migration applied
+    pub(crate) async fn sqlite_database() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate::<Migrator>(&db).await.unwrap();
+        db
+    }
+
+    /// Settings with an api url nothing listens on, so bot api calls fail right away
+    pub(crate) fn unreachable_api_settings() -> Settings {
+        Settings {
+            telegram_bot: Some(TelegramBot {
+                token: "0:token".to_string(),
+                api_url: Some("http://127.0.0.1:9/".to_string()),
+                webhook: None,
+                error_report: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Call a bot made to the bot api, params of multipart uploads are all strings
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) struct BotApiCall {
+        /// method name as in the bot api docs, such as `sendMessage`
+        pub(crate) method: String,
+        pub(crate) params: Value,
+    }
+
+    #[derive(Debug, Default)]
+    struct MockBotApiState {
+        calls: Vec<BotApiCall>,
+        /// results of methods answered other than by default
+        results: HashMap<String, Value>,
+        /// methods answered with an error
+        failing: Vec<String>,
+        /// message ids handed out so far, they go up like they do in a chat
+        message_ids: i64,
+    }
+
+    ///
+    /// Local bot api recording the calls of the bots, every call succeeds with a made-up result
+    /// unless told otherwise
+    ///
+    #[derive(Clone, Debug)]
+    pub(crate) struct MockBotApi {
+        url: String,
+        state: Arc<Mutex<MockBotApiState>>,
+    }
+
+    impl MockBotApi {
+        pub(crate) fn start() -> Self {
+            let state = Arc::new(Mutex::new(MockBotApiState::default()));
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/", listener.local_addr().unwrap());
+
+            let data = web::Data::new(state.clone());
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(data.clone())
+                    .default_service(web::to(answer_call))
+            })
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .unwrap()
+            .run();
+            tokio::spawn(server);
+
+            Self { url, state }
+        }
+
+        /// Settings with the api url of the mock
+        pub(crate) fn settings(&self) -> Settings {
+            Settings {
+                telegram_bot: Some(TelegramBot {
+                    token: "0:token".to_string(),
+                    api_url: Some(self.url.clone()),
+                    webhook: None,
+                    error_report: None,
+                }),
+                ..Default::default()
+            }
+        }
+
+        pub(crate) fn url(&self) -> reqwest::Url {
+            reqwest::Url::parse(&self.url).unwrap()
+        }
+
+        pub(crate) fn calls(&self) -> Vec<BotApiCall> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        /// Names of the methods called, in order
+        pub(crate) fn methods(&self) -> Vec<String> {
+            self.calls().into_iter().map(|call| call.method).collect()
+        }
+
+        /// Calls of one method, in order
+        pub(crate) fn calls_of(&self, method: &str) -> Vec<Value> {
+            self.calls()
+                .into_iter()
+                .filter(|call| call.method == method)
+                .map(|call| call.params)
+                .collect()
+        }
+
+        pub(crate) fn clear(&self) {
+            self.state.lock().unwrap().calls.clear();
+        }
+
+        /// Answer a method with the given result from now on
+        pub(crate) fn answer(&self, method: &str, result: Value) {
+            self.state
+                .lock()
+                .unwrap()
+                .results
+                .insert(method.to_string(), result);
+        }
+
+        /// Answer a method with an error from now on, or again with success
+        pub(crate) fn fail(&self, method: &str, failing: bool) {
+            let mut state = self.state.lock().unwrap();
+            state.failing.retain(|failing| failing != method);
+            if failing {
+                state.failing.push(method.to_string());
+            }
+        }
+    }
+
+    async fn answer_call(
+        req: HttpRequest,
+        body: web::Bytes,
+        state: web::Data<Arc<Mutex<MockBotApiState>>>,
+    ) -> HttpResponse {
+        // `/bot<token>/SendMessage`, method names are case insensitive
+        let method = req.path().rsplit('/').next().unwrap_or_default();
+        let mut chars = method.chars();
+        let method = chars
+            .next()
+            .map(|first| first.to_lowercase().chain(chars).collect::<String>())
+            .unwrap_or_default();
+        let params = match req.content_type() {
+            "multipart/form-data" => multipart_params(&req, &body),
+            _ => serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null),
+        };
+
+        let mut state = state.lock().unwrap();
+        state.calls.push(BotApiCall {
+            method: method.clone(),
+            params: params.clone(),
+        });
+
+        if state.failing.contains(&method) {
+            return HttpResponse::Ok().json(json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: failing on purpose"
+            }));
+        }
+
+        // an album takes one message id per part
+        let id = state.message_ids + 1001;
+        state.message_ids += album_len(&params);
+        let result = match state.results.get(&method) {
+            Some(result) => result.clone(),
+            None => default_result(&method, &params, id),
+        };
+
+        HttpResponse::Ok().json(json!({"ok": true, "result": result}))
+    }
+
+    /// Text fields of a multipart body, files are left out
+    fn multipart_params(req: &HttpRequest, body: &[u8]) -> Value {
+        let content_type = req
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let boundary = match content_type.split_once("boundary=") {
+            Some((_, boundary)) => format!("--{}", boundary.trim_matches('"')),
+            None => return Value::Null,
+        };
+
+        let body = String::from_utf8_lossy(body);
+        let mut params = serde_json::Map::new();
+        for part in body.split(boundary.as_str()) {
+            let (headers, value) = match part.split_once("\r\n\r\n") {
+                Some(part) => part,
+                None => continue,
+            };
+            let name = headers
+                .split("name=\"")
+                .nth(1)
+                .and_then(|name| name.split('"').next());
+            if let (Some(name), false) = (name, headers.contains("filename=")) {
+                let value = value.strip_suffix("\r\n").unwrap_or(value);
+                params.insert(name.to_string(), Value::String(value.to_string()));
+            }
+        }
+
+        Value::Object(params)
+    }
+
+    /// Messages of an album, its media comes as a json string in a multipart upload
+    fn album_len(params: &Value) -> i64 {
+        let media = match &params["media"] {
+            Value::String(media) => serde_json::from_str(media).unwrap_or(Value::Null),
+            media => media.clone(),
+        };
+        media.as_array().map_or(1, Vec::len) as i64
+    }
+
+    fn default_result(method: &str, params: &Value, id: i64) -> Value {
+        let chat_id = params["chat_id"].as_i64().unwrap_or_default();
+        let chat = if chat_id < 0 {
+            json!({"id": chat_id, "type": "supergroup", "title": "Support"})
+        } else {
+            json!({"id": chat_id, "type": "private", "first_name": "Alice"})
+        };
+        let bot = json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": "Bot",
+            "username": "forwarding_bot"
+        });
+        let message = |id: i64| {
+            json!({
+                "message_id": id,
+                "date": 1700000000,
+                "chat": chat,
+                "text": params["text"].as_str().unwrap_or("message")
+            })
+        };
+
+        match method {
+            "copyMessage" => json!({ "message_id": id }),
+            "sendMediaGroup" => {
+                let len = album_len(params);
+                Value::Array((0..len).map(|index| message(id + index)).collect())
+            }
+            "createForumTopic" => json!({
+                "message_thread_id": id,
+                "name": params["name"],
+                "icon_color": params["icon_color"]
+            }),
+            "getMe" => bot,
+            "getChat" => chat,
+            "getChatMember" => json!({"status": "member", "user": bot}),
+            method if method.starts_with("send") || method.starts_with("edit") => message(id),
+            _ => json!(true),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::prelude::*;
+
+use pegasus_common::cryptor::Cryptor;
+use pegasus_common::database::repositories::pm_forwarding_bot::{
+    PmForwardingBotRepository, SeaOrmPmForwardingBotRepository,
+};
+use pegasus_common::database::repositories::pm_forwarding_message::{
+    PmForwardingMessageRepository, SeaOrmPmForwardingMessageRepository,
+};
+use pegasus_common::database::repositories::pm_forwarding_reply::{
+    PmForwardingReplyRepository, SeaOrmPmForwardingReplyRepository,
+};
+use pegasus_common::settings::Settings;
+
+static DEFAULT_BATCH_SIZE: u64 = 1000;
+
+#[derive(Clone, Debug)]
+pub struct RetentionService {
+    bots: Arc<dyn PmForwardingBotRepository>,
+    messages: Arc<dyn PmForwardingMessageRepository>,
+    replies: Arc<dyn PmForwardingReplyRepository>,
+    settings: Settings,
+}
+
+impl RetentionService {
+    pub fn new(db: DatabaseConnection, cryptor: Cryptor, settings: Settings) -> Self {
+        Self::with_repositories(
+            Arc::new(SeaOrmPmForwardingBotRepository::new(db.clone(), cryptor)),
+            Arc::new(SeaOrmPmForwardingMessageRepository::new(db.clone())),
+            Arc::new(SeaOrmPmForwardingReplyRepository::new(db)),
+            settings,
+        )
+    }
+
+    pub fn with_repositories(
+        bots: Arc<dyn PmForwardingBotRepository>,
+        messages: Arc<dyn PmForwardingMessageRepository>,
+        replies: Arc<dyn PmForwardingReplyRepository>,
+        settings: Settings,
+    ) -> Self {
+        Self {
+            bots,
+            messages,
+            replies,
+            settings,
+        }
+    }
+
+    fn default_days(&self) -> Option<u32> {
+        self.settings
+            .pm_forwarding
+            .as_ref()
+            .and_then(|pm_forwarding| pm_forwarding.retention.as_ref())
+            .and_then(|retention| retention.days)
+    }
+
+    fn batch_size(&self) -> u64 {
+        self.settings
+            .pm_forwarding
+            .as_ref()
+            .and_then(|pm_forwarding| pm_forwarding.retention.as_ref())
+            .and_then(|retention| retention.batch_size)
+            .filter(|batch_size| *batch_size > 0)
+            .unwrap_or(DEFAULT_BATCH_SIZE)
+    }
+}
+
+/// Creation time before which messages of the bot expire, `None` if they are kept forever
+fn expire_before(
+    bot_days: Option<i32>,
+    default_days: Option<u32>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let days = match bot_days {
+        Some(days) => days as i64,
+        None => default_days? as i64,
+    };
+
+    if days <= 0 {
+        return None;
+    }
+
+    Some(now - Duration::days(days))
+}
+
+pub trait IRetentionService {
+    ///
+    /// Delete message and reply mappings older than the retention of their bot
+    ///
+    /// returns: `Result<u64, Error>` count of deleted mappings
+    ///
+    async fn prune_expired_messages(&self) -> anyhow::Result<u64>;
+}
+
+impl IRetentionService for RetentionService {
+    #[tracing::instrument(err, skip(self))]
+    async fn prune_expired_messages(&self) -> anyhow::Result<u64> {
+        let now = Utc::now();
+        let default_days = self.default_days();
+        let batch_size = self.batch_size();
+
+        let bots = self.bots.list_all().await?;
+
+        let mut deleted = 0;
+        for bot in bots {
+            let expire_before = match expire_before(bot.message_retention_days, default_days, now) {
+                Some(expire_before) => expire_before,
+                None => continue,
+            };
+
+            loop {
+                let batch = self
+                    .messages
+                    .delete_created_before(bot.id, expire_before, batch_size)
+                    .await?;
+                deleted += batch;
+
+                if batch < batch_size {
+                    break;
+                }
+            }
+
+            loop {
+                let batch = self
+                    .replies
+                    .delete_created_before(bot.id, expire_before, batch_size)
+                    .await?;
+                deleted += batch;
+
+                if batch < batch_size {
+                    break;
+                }
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pegasus_common::database::entities::{
+        pm_forwarding_bot, pm_forwarding_message, pm_forwarding_reply,
+    };
+    use pegasus_common::database::repositories::memory::{
+        bot_model, InMemoryPmForwardingBotRepository, InMemoryPmForwardingMessageRepository,
+        InMemoryPmForwardingReplyRepository,
+    };
+    use pegasus_common::database::repositories::pm_forwarding_bot::NewPmForwardingBot;
+    use pegasus_common::database::repositories::pm_forwarding_reply::NewPmForwardingReply;
+    use pegasus_common::settings::{PmForwarding, Retention};
+    use sea_orm::{ActiveModelTrait, ActiveValue};
+
+    use crate::services::testing::sqlite_database;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    fn retention_settings(days: u32, batch_size: u64) -> Settings {
+        Settings {
+            pm_forwarding: Some(PmForwarding {
+                retention: Some(Retention {
+                    days: Some(days),
+                    batch_size: Some(batch_size),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_expire_before() {
+        let now = Utc::now();
+
+        assert_eq!(
+            expire_before(Some(30), Some(90), now),
+            Some(now - Duration::days(30))
+        );
+        assert_eq!(
+            expire_before(None, Some(90), now),
+            Some(now - Duration::days(90))
+        );
+        assert_eq!(expire_before(Some(0), Some(90), now), None);
+        assert_eq!(expire_before(None, None, now), None);
+        assert_eq!(
+            expire_before(Some(7), None, now),
+            Some(now - Duration::days(7))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired_messages() {
+        let now = Utc::now();
+        let bots = Arc::new(InMemoryPmForwardingBotRepository::new());
+        let messages = Arc::new(InMemoryPmForwardingMessageRepository::new());
+        for (id, message_retention_days) in [(1, None), (2, Some(0)), (3, Some(10))] {
+            bots.insert(pm_forwarding_bot::Model {
+                message_retention_days,
+                ..bot_model(id)
+            });
+
+            for (message_id, age) in [(1, 5), (2, 20), (3, 40), (4, 100)] {
+                messages.insert(pm_forwarding_message::Model {
+                    id: id * 10 + message_id,
+                    created_at: now - Duration::days(age),
+                    updated_at: now,
+                    bot_id: id,
+                    target_chat_id: 1,
+                    telegram_chat_id: 1,
+                    telegram_message_id: message_id as i32,
+                    forward_telegram_message_id: message_id as i32,
+                });
+            }
+        }
+
+        let replies = Arc::new(InMemoryPmForwardingReplyRepository::new());
+        replies
+            .create(NewPmForwardingReply {
+                bot_id: 1,
+                target_chat_id: 1,
+                target_message_id: 100,
+                telegram_chat_id: 1,
+                telegram_message_id: 5,
+            })
+            .await
+            .unwrap();
+
+        let service = RetentionService::with_repositories(
+            bots,
+            messages.clone(),
+            replies.clone(),
+            retention_settings(30, 1),
+        );
+
+        // bot 1 keeps 30 days, bot 2 keeps forever, bot 3 keeps 10 days
+        assert_eq!(service.prune_expired_messages().await.unwrap(), 5);
+        // just created
+        assert_eq!(replies.rows().len(), 1);
+        let mut remaining = messages
+            .rows()
+            .into_iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, vec![11, 12, 21, 22, 23, 24, 31]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired_messages_sqlite() {
+        let now = Utc::now();
+        let db = sqlite_database().await;
+        let cryptor = Cryptor::new(None).unwrap();
+        let bot = SeaOrmPmForwardingBotRepository::new(db.clone(), cryptor.clone())
+            .create(NewPmForwardingBot {
+                bot_token: "1:token".to_string(),
+                bot_webhook_secret: "secret".to_string(),
+                target_chat_id: -100,
+                telegram_user_refer: 1,
+            })
+            .await
+            .unwrap();
+
+        for (message_id, age) in [(1, 5), (2, 20), (3, 40), (4, 100)] {
+            pm_forwarding_message::ActiveModel {
+                created_at: ActiveValue::Set(now - Duration::days(age)),
+                updated_at: ActiveValue::Set(now),
+                bot_id: ActiveValue::Set(bot.id),
+                target_chat_id: ActiveValue::Set(bot.target_chat_id),
+                telegram_chat_id: ActiveValue::Set(1),
+                telegram_message_id: ActiveValue::Set(message_id),
+                forward_telegram_message_id: ActiveValue::Set(message_id),
+                ..Default::default()
+            }
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+
+        for (target_message_id, age) in [(100, 5), (101, 40)] {
+            pm_forwarding_reply::ActiveModel {
+                created_at: ActiveValue::Set(now - Duration::days(age)),
+                updated_at: ActiveValue::Set(now),
+                bot_id: ActiveValue::Set(bot.id),
+                target_chat_id: ActiveValue::Set(bot.target_chat_id),
+                target_message_id: ActiveValue::Set(target_message_id),
+                telegram_chat_id: ActiveValue::Set(1),
+                telegram_message_id: ActiveValue::Set(target_message_id),
+                ..Default::default()
+            }
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+
+        let service = RetentionService::new(db.clone(), cryptor, retention_settings(30, 1));
+        assert_eq!(service.prune_expired_messages().await.unwrap(), 3);
+        assert_eq!(
+            pm_forwarding_reply::Entity::find()
+                .all(&db)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|reply| reply.target_message_id)
+                .collect::<Vec<_>>(),
+            vec![100]
+        );
+
+        let remaining = pm_forwarding_message::Entity::find()
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.telegram_message_id)
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![1, 2]);
+    }
+}
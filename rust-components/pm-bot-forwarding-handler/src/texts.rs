@@ -0,0 +1,190 @@
+use pegasus_common::database::entities::pm_forwarding_bot;
+use pegasus_common::database::repositories::pm_forwarding_bot::UpdatePmForwardingBot;
+
+pub static DEFAULT_ACK_TEXT: &str = "Message sent";
+pub static DEFAULT_ERROR_TEXT: &str =
+    "Sorry, your message could not be delivered, please try again later";
+/// Texts are sent as messages, which are limited to 4096 characters
+pub static MAX_TEXT_LEN: usize = 4096;
+/// Sent in the dialogue to restore the default text
+pub static DEFAULT_TEXT_COMMAND: &str = "/default";
+/// Sent in the dialogue to send nothing
+pub static NO_TEXT_COMMAND: &str = "/none";
+
+/// Text the owner of a bot sets for its senders
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotText {
+    /// sent on `/start`
+    Welcome,
+    /// sent for each forwarded message
+    Ack,
+    /// sent for each forwarded message instead of the acknowledgement while set
+    Away,
+    /// sent when a message can not be forwarded
+    Error,
+}
+
+impl BotText {
+    pub const ALL: [BotText; 4] = [
+        BotText::Welcome,
+        BotText::Ack,
+        BotText::Away,
+        BotText::Error,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BotText::Welcome => "Welcome",
+            BotText::Ack => "Acknowledgement",
+            BotText::Away => "Away message",
+            BotText::Error => "Error",
+        }
+    }
+
+    /// Data of the menu button choosing the text
+    pub fn callback_data(self) -> &'static str {
+        match self {
+            BotText::Welcome => "forward_bot_text_welcome",
+            BotText::Ack => "forward_bot_text_ack",
+            BotText::Away => "forward_bot_text_away",
+            BotText::Error => "forward_bot_text_error",
+        }
+    }
+
+    pub fn from_callback_data(data: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|text| text.callback_data() == data)
+    }
+
+    /// Text sent when the owner sets none, welcome and away messages are off by default
+    pub fn default_text(self) -> Option<&'static str> {
+        match self {
+            BotText::Welcome | BotText::Away => None,
+            BotText::Ack => Some(DEFAULT_ACK_TEXT),
+            BotText::Error => Some(DEFAULT_ERROR_TEXT),
+        }
+    }
+
+    fn stored(self, bot: &pm_forwarding_bot::Model) -> Option<&str> {
+        match self {
+            BotText::Welcome => bot.welcome_text.as_deref(),
+            BotText::Ack => bot.ack_text.as_deref(),
+            BotText::Away => bot.away_text.as_deref(),
+            BotText::Error => bot.error_text.as_deref(),
+        }
+    }
+
+    ///
+    /// Text of the bot to send, its default if the owner set none
+    ///
+    /// # Arguments
+    ///
+    /// * `bot`: bot the text is sent by
+    ///
+    /// returns: `Option<&str>` none to send nothing
+    ///
+    pub fn text(self, bot: &pm_forwarding_bot::Model) -> Option<&str> {
+        match self.stored(bot) {
+            None => self.default_text(),
+            Some("") => None,
+            Some(text) => Some(text),
+        }
+    }
+
+    /// Changes of the bot setting the text, `None` restores the default
+    pub fn changes(self, text: Option<String>) -> UpdatePmForwardingBot {
+        let text = Some(text);
+        match self {
+            BotText::Welcome => UpdatePmForwardingBot {
+                welcome_text: text,
+                ..Default::default()
+            },
+            BotText::Ack => UpdatePmForwardingBot {
+                ack_text: text,
+                ..Default::default()
+            },
+            BotText::Away => UpdatePmForwardingBot {
+                away_text: text,
+                ..Default::default()
+            },
+            BotText::Error => UpdatePmForwardingBot {
+                error_text: text,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+///
+/// Text sent by the owner in the dialogue, as stored
+///
+/// # Arguments
+///
+/// * `input`: text of the message
+///
+/// returns: `Option<String>` none for [`DEFAULT_TEXT_COMMAND`], an empty text for
+/// [`NO_TEXT_COMMAND`]
+///
+pub fn parse_text_input(input: &str) -> Option<String> {
+    match input.trim() {
+        command if command == DEFAULT_TEXT_COMMAND => None,
+        command if command == NO_TEXT_COMMAND => Some(String::new()),
+        _ => Some(input.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pegasus_common::database::repositories::memory::bot_model;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let mut bot = bot_model(1);
+        assert_eq!(BotText::Welcome.text(&bot), None);
+        assert_eq!(BotText::Ack.text(&bot), Some(DEFAULT_ACK_TEXT));
+        assert_eq!(BotText::Away.text(&bot), None);
+        assert_eq!(BotText::Error.text(&bot), Some(DEFAULT_ERROR_TEXT));
+
+        bot.welcome_text = Some("Hello".to_string());
+        bot.ack_text = Some(String::new());
+        bot.error_text = Some("Oops".to_string());
+        assert_eq!(BotText::Welcome.text(&bot), Some("Hello"));
+        assert_eq!(BotText::Ack.text(&bot), None);
+        assert_eq!(BotText::Error.text(&bot), Some("Oops"));
+    }
+
+    #[test]
+    fn test_callback_data() {
+        for text in BotText::ALL {
+            assert_eq!(
+                BotText::from_callback_data(text.callback_data()),
+                Some(text)
+            );
+        }
+        assert_eq!(BotText::from_callback_data("forward_bot_text_"), None);
+    }
+
+    #[test]
+    fn test_changes() {
+        let changes = BotText::Away.changes(Some("Back on monday".to_string()));
+        assert_eq!(changes.away_text, Some(Some("Back on monday".to_string())));
+        assert_eq!(changes.ack_text, None);
+
+        assert_eq!(BotText::Ack.changes(None).ack_text, Some(None));
+    }
+
+    #[test]
+    fn test_parse_text_input() {
+        assert_eq!(parse_text_input("/default"), None);
+        assert_eq!(parse_text_input(" /none "), Some(String::new()));
+        assert_eq!(
+            parse_text_input("Thanks, we answer within a day"),
+            Some("Thanks, we answer within a day".to_string())
+        );
+    }
+}
@@ -1,19 +1,64 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use actix_web::dev::ServiceRequest;
 use actix_web::http::header::HeaderName;
 use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use regex::Regex;
+
+use pegasus_common::cryptor::secret_eq;
+use pegasus_common::settings::Settings;
 
-use crate::services::forwarding_bot::{ForwardingBotService, IForwardingBotService};
-use crate::services::forwarding_message::{ForwardingMessageService, IForwardingMessageService};
+use crate::queue::{QueuedUpdate, UpdateQueue};
+use crate::services::forwarding_bot::{
+    is_legacy_webhook_id, ForwardingBotService, IForwardingBotService,
+};
 
 static TELEGRAM_BOT_API_SECRET_TOKEN: &[u8] = b"X-Telegram-Bot-Api-Secret-Token";
+static DEFAULT_ADDRESS: &str = "0.0.0.0";
+static DEFAULT_PORT: u16 = 8080;
+
+/// Address and port the webhook server binds to, from the server settings
+pub(crate) fn listen_address(settings: &Settings) -> (String, u16) {
+    let server = settings.server.as_ref();
+
+    (
+        server
+            .and_then(|server| server.address.clone())
+            .filter(|address| !address.is_empty())
+            .unwrap_or_else(|| DEFAULT_ADDRESS.to_string()),
+        server
+            .and_then(|server| server.port)
+            .unwrap_or(DEFAULT_PORT),
+    )
+}
 
-#[post("/webhook/{token}")]
-#[tracing::instrument]
+/// Replace bot tokens in the text, as found in webhook urls set before webhook ids
+pub(crate) fn redact_tokens(text: &str) -> Cow<'_, str> {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    TOKEN
+        .get_or_init(|| Regex::new(r"[0-9]+(:|%3[aA])[a-zA-Z0-9_-]{30,}").unwrap())
+        .replace_all(text, "<redacted>")
+}
+
+/// Request line of the access log, with bot tokens redacted
+pub(crate) fn redacted_request_line(req: &ServiceRequest) -> String {
+    format!(
+        "{} {} {:?}",
+        req.method(),
+        redact_tokens(&req.uri().to_string()),
+        req.version()
+    )
+}
+
+#[post("/webhook/{webhook_id}")]
+#[tracing::instrument(skip_all)]
 pub async fn forwarding_bot_update_handler(
     req: HttpRequest,
     update: web::Json<teloxide::types::Update>,
-    token: web::Path<String>,
+    webhook_id: web::Path<String>,
     forwarding_bot_service: web::Data<ForwardingBotService>,
-    forwarding_message_service: web::Data<ForwardingMessageService>,
+    update_queue: web::Data<dyn UpdateQueue>,
 ) -> impl Responder {
     log::debug!("Received update: {:?}", update);
 
@@ -28,29 +73,177 @@ pub async fn forwarding_bot_update_handler(
         }
     };
 
+    let webhook_id = webhook_id.into_inner();
     let bot_info = match forwarding_bot_service
-        .get_bot_record_by_token(token.into_inner())
+        .find_bot_by_webhook_id(webhook_id.clone())
         .await
     {
-        Ok(bot_info) => bot_info,
+        Ok(Some(bot_info)) => bot_info,
+        Ok(None) => {
+            log::warn!("Received update for an unknown bot");
+            return HttpResponse::NotFound().body("Bot not found");
+        }
         Err(err) => {
             log::error!("Error getting bot info: {}", err);
             return HttpResponse::InternalServerError().body(err.to_string());
         }
     };
 
-    if bot_info.bot_webhook_secret != secret {
+    if !secret_eq(&bot_info.bot_webhook_secret, secret) {
         return HttpResponse::Unauthorized().body("Invalid secret token");
     }
 
-    match forwarding_message_service
-        .handle_update_income(bot_info.id, update.into_inner())
+    if is_legacy_webhook_id(&webhook_id) {
+        // move the bot to the url without its token
+        log::info!("Moving bot {} to its webhook id url", bot_info.id);
+        if let Err(err) = forwarding_bot_service.set_webhook(&bot_info).await {
+            log::error!("Error moving bot {} webhook: {}", bot_info.id, err);
+        }
+    }
+
+    // processed by the update workers, telegram redelivers it if queueing fails
+    match update_queue
+        .publish(QueuedUpdate {
+            bot_id: bot_info.id,
+            update: update.into_inner(),
+        })
         .await
     {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(err) => {
-            log::error!("Error handling update: {}", err);
+            log::error!("Error queueing update: {}", err);
             HttpResponse::InternalServerError().body(err.to_string())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::http::header::ContentType;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use pegasus_common::database::repositories::memory::InMemoryPmForwardingBotRepository;
+    use pegasus_common::database::repositories::pm_forwarding_bot::{
+        NewPmForwardingBot, PmForwardingBotRepository,
+    };
+    use pegasus_common::settings::Server;
+
+    use crate::services::forwarding_bot::webhook_id;
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_listen_address() {
+        assert_eq!(
+            listen_address(&Settings::default()),
+            ("0.0.0.0".to_string(), 8080)
+        );
+
+        let settings = Settings {
+            server: Some(Server {
+                network: Some("tcp".to_string()),
+                address: Some("127.0.0.1".to_string()),
+                port: Some(9000),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(listen_address(&settings), ("127.0.0.1".to_string(), 9000));
+    }
+
+    #[test]
+    fn test_redact_tokens() {
+        assert_eq!(
+            redact_tokens("POST /webhook/123456789:AAHdqTcvCH1vGWJxfSeofSAs0K5PALDsaw HTTP/1.1"),
+            "POST /webhook/<redacted> HTTP/1.1"
+        );
+        assert_eq!(
+            redact_tokens("/webhook/123456789%3AAAHdqTcvCH1vGWJxfSeofSAs0K5PALDsaw?a=1"),
+            "/webhook/<redacted>?a=1"
+        );
+
+        let webhook_id = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(redact_tokens(webhook_id), webhook_id);
+    }
+
+    /// Keeps the published updates
+    #[derive(Debug, Default)]
+    struct VecUpdateQueue {
+        updates: Mutex<Vec<QueuedUpdate>>,
+    }
+
+    impl UpdateQueue for VecUpdateQueue {
+        fn publish(&self, update: QueuedUpdate) -> BoxFuture<'_, anyhow::Result<()>> {
+            self.updates.lock().unwrap().push(update);
+            futures::future::ok(()).boxed()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_forwarding_bot_update_handler_auth() {
+        let bots = Arc::new(InMemoryPmForwardingBotRepository::new());
+        let bot = bots
+            .create(NewPmForwardingBot {
+                bot_token: "1:token".to_string(),
+                bot_webhook_secret: "secret".to_string(),
+                target_chat_id: -100,
+                telegram_user_refer: 1,
+            })
+            .await
+            .unwrap();
+
+        let update_queue = Arc::new(VecUpdateQueue::default());
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(ForwardingBotService::with_repository(
+                    bots,
+                    Settings::default(),
+                )))
+                .app_data(web::Data::from(update_queue.clone() as Arc<dyn UpdateQueue>))
+                .service(forwarding_bot_update_handler),
+        )
+        .await;
+
+        let request = |webhook_id: String, secret: Option<&str>| {
+            let request = TestRequest::post()
+                .uri(&format!("/webhook/{}", webhook_id))
+                .insert_header(ContentType::json())
+                .set_payload(r#"{"update_id": 1}"#);
+            match secret {
+                Some(secret) => request
+                    .insert_header(("X-Telegram-Bot-Api-Secret-Token", secret))
+                    .to_request(),
+                None => request.to_request(),
+            }
+        };
+
+        let status = |request| async { call_service(&app, request).await.status() };
+
+        assert_eq!(
+            status(request(webhook_id(&bot.bot_token), None)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(request(webhook_id(&bot.bot_token), Some("wrong"))).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(request(webhook_id("2:token"), Some("secret"))).await,
+            StatusCode::NOT_FOUND
+        );
+        assert!(update_queue.updates.lock().unwrap().is_empty());
+
+        assert_eq!(
+            status(request(webhook_id(&bot.bot_token), Some("secret"))).await,
+            StatusCode::OK
+        );
+        let updates = update_queue.updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!((updates[0].bot_id, updates[0].update.id), (bot.id, 1));
+    }
+}
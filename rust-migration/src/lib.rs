@@ -1,14 +1,228 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20240407_142137_create_pm_forwarding_tables;
+mod m20240601_000000_add_pm_forwarding_constraints;
+mod m20240602_000000_add_pm_forwarding_retention;
+mod m20240603_000000_add_pm_forwarding_bot_token_hash;
+mod m20240604_000000_create_pm_forwarding_replies_table;
+mod m20240605_000000_scope_pm_forwarding_mappings_by_target_chat;
+mod m20240606_000000_create_pm_forwarding_topics_table;
+mod m20240607_000000_create_pm_forwarding_blocks_table;
+mod m20240608_000000_add_pm_forwarding_rate_limit;
+mod m20240609_000000_add_pm_forwarding_bot_texts;
+mod m20240610_000000_add_pm_forwarding_captcha;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(
-            m20240407_142137_create_pm_forwarding_tables::Migration,
-        )]
+        vec![
+            Box::new(m20240407_142137_create_pm_forwarding_tables::Migration),
+            Box::new(m20240601_000000_add_pm_forwarding_constraints::Migration),
+            Box::new(m20240602_000000_add_pm_forwarding_retention::Migration),
+            Box::new(m20240603_000000_add_pm_forwarding_bot_token_hash::Migration),
+            Box::new(m20240604_000000_create_pm_forwarding_replies_table::Migration),
+            Box::new(m20240605_000000_scope_pm_forwarding_mappings_by_target_chat::Migration),
+            Box::new(m20240606_000000_create_pm_forwarding_topics_table::Migration),
+            Box::new(m20240607_000000_create_pm_forwarding_blocks_table::Migration),
+            Box::new(m20240608_000000_add_pm_forwarding_rate_limit::Migration),
+            Box::new(m20240609_000000_add_pm_forwarding_bot_texts::Migration),
+            Box::new(m20240610_000000_add_pm_forwarding_captcha::Migration),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pegasus_common::database::migrate::migrate;
+    use sea_orm_migration::sea_orm::{ConnectionTrait, Database, DbBackend, Statement};
+
+    #[allow(unused_imports)]
+    use super::*;
+
+    async fn count(db: &impl ConnectionTrait, table: &str) -> i64 {
+        db.query_one(Statement::from_string(
+            DbBackend::Sqlite,
+            format!("SELECT COUNT(*) FROM \"{}\"", table),
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get_by_index(0)
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrations_sqlite() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate::<Migrator>(&db).await.unwrap();
+        // nothing left to apply
+        migrate::<Migrator>(&db).await.unwrap();
+
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_bots (bot_token, bot_webhook_secret, target_chat_id, telegram_user_refer) VALUES ('1:token', 'secret', -100, 1)",
+        )
+        .await
+        .unwrap();
+        // existing bots get the default rate limit
+        let rate_limit: i32 = db
+            .query_one(Statement::from_string(
+                DbBackend::Sqlite,
+                "SELECT rate_limit FROM pm_forwarding_bots",
+            ))
+            .await
+            .unwrap()
+            .unwrap()
+            .try_get_by_index(0)
+            .unwrap();
+        assert_eq!(rate_limit, 20);
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_messages (bot_id, telegram_chat_id, telegram_message_id, forward_telegram_message_id) VALUES (1, 42, 7, 100)",
+        )
+        .await
+        .unwrap();
+
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_replies (bot_id, target_message_id, telegram_chat_id, telegram_message_id) VALUES (1, 101, 42, 8)",
+        )
+        .await
+        .unwrap();
+        // one copy per reply
+        assert!(db
+            .execute_unprepared(
+                "INSERT INTO pm_forwarding_replies (bot_id, target_message_id, telegram_chat_id, telegram_message_id) VALUES (1, 101, 42, 9)",
+            )
+            .await
+            .is_err());
+
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_topics (bot_id, target_chat_id, message_thread_id, telegram_chat_id) VALUES (1, -100, 5, 42)",
+        )
+        .await
+        .unwrap();
+        // one topic per sender, and one sender per topic
+        for values in ["(1, -100, 6, 42)", "(1, -100, 5, 43)"] {
+            assert!(db
+                .execute_unprepared(&format!(
+                    "INSERT INTO pm_forwarding_topics (bot_id, target_chat_id, message_thread_id, telegram_chat_id) VALUES {}",
+                    values
+                ))
+                .await
+                .is_err());
+        }
+
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_blocks (bot_id, telegram_chat_id) VALUES (1, 42)",
+        )
+        .await
+        .unwrap();
+        // one block per sender, a mute replaces a ban
+        assert!(db
+            .execute_unprepared(
+                "INSERT INTO pm_forwarding_blocks (bot_id, telegram_chat_id) VALUES (1, 42)",
+            )
+            .await
+            .is_err());
+
+        // messages, replies, topics and blocks are deleted with their bot
+        db.execute_unprepared("DELETE FROM pm_forwarding_bots")
+            .await
+            .unwrap();
+        assert_eq!(count(&db, "pm_forwarding_messages").await, 0);
+        assert_eq!(count(&db, "pm_forwarding_replies").await, 0);
+        assert_eq!(count(&db, "pm_forwarding_topics").await, 0);
+        assert_eq!(count(&db, "pm_forwarding_blocks").await, 0);
+
+        Migrator::down(&db, None).await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        assert_eq!(count(&db, "pm_forwarding_bots").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_bot_token_hash_sqlite() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        // every migration before the token hash
+        Migrator::up(&db, Some(3)).await.unwrap();
+
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_bots (bot_token, bot_webhook_secret, target_chat_id, telegram_user_refer) VALUES ('1:token', 'secret', -100, 1), ('2:token', 'secret', -100, 1)",
+        )
+        .await
+        .unwrap();
+
+        Migrator::up(&db, Some(1)).await.unwrap();
+
+        let hashes = db
+            .query_all(Statement::from_string(
+                DbBackend::Sqlite,
+                "SELECT bot_token_hash FROM pm_forwarding_bots ORDER BY id",
+            ))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.try_get_by_index::<String>(0).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hashes,
+            vec![
+                pegasus_common::cryptor::secret_hash("1:token"),
+                pegasus_common::cryptor::secret_hash("2:token")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scope_mappings_by_target_chat_sqlite() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        // every migration before the target chat columns
+        Migrator::up(&db, Some(5)).await.unwrap();
+
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_bots (bot_token, bot_webhook_secret, target_chat_id, telegram_user_refer) VALUES ('1:token', 'secret', -100, 1)",
+        )
+        .await
+        .unwrap();
+        // the same forwarded message id in the old and the new target chat
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_messages (bot_id, telegram_chat_id, telegram_message_id, forward_telegram_message_id) VALUES (1, 42, 7, 100), (1, 43, 8, 100), (1, 42, 9, 101)",
+        )
+        .await
+        .unwrap();
+
+        Migrator::up(&db, Some(1)).await.unwrap();
+
+        let rows = db
+            .query_all(Statement::from_string(
+                DbBackend::Sqlite,
+                "SELECT telegram_message_id, target_chat_id FROM pm_forwarding_messages ORDER BY id",
+            ))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                (
+                    row.try_get_by_index::<i32>(0).unwrap(),
+                    row.try_get_by_index::<i64>(1).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![(8, -100), (9, -100)]);
+
+        // unique per bot and target chat
+        assert!(db
+            .execute_unprepared(
+                "INSERT INTO pm_forwarding_messages (bot_id, target_chat_id, telegram_chat_id, telegram_message_id, forward_telegram_message_id) VALUES (1, -100, 42, 10, 101)",
+            )
+            .await
+            .is_err());
+        db.execute_unprepared(
+            "INSERT INTO pm_forwarding_messages (bot_id, target_chat_id, telegram_chat_id, telegram_message_id, forward_telegram_message_id) VALUES (1, -200, 42, 10, 101)",
+        )
+        .await
+        .unwrap();
+
+        Migrator::down(&db, Some(1)).await.unwrap();
+        assert_eq!(count(&db, "pm_forwarding_messages").await, 3);
     }
 }
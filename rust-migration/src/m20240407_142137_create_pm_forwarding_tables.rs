@@ -1,43 +1,157 @@
 use sea_orm_migration::prelude::*;
 
-use pegasus_common::database::entities;
-
-use crate::sea_orm::Schema;
-
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
+#[derive(DeriveIden)]
+pub(crate) enum PmForwardingBots {
+    Table,
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    BotToken,
+    BotWebhookSecret,
+    TargetChatId,
+    TelegramUserRefer,
+}
+
+#[derive(DeriveIden)]
+pub(crate) enum PmForwardingMessages {
+    Table,
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    BotId,
+    TelegramChatId,
+    TelegramMessageId,
+    ForwardTelegramMessageId,
+}
+
+pub(crate) static FK_MESSAGES_BOT_ID: &str = "fk-pm_forwarding_messages-bot_id";
+
+/// Columns of the messages table, also used to rebuild it where constraints can not be altered
+pub(crate) fn create_messages_table<T: IntoTableRef>(table: T) -> TableCreateStatement {
+    Table::create()
+        .table(table)
+        .col(
+            ColumnDef::new(PmForwardingMessages::Id)
+                .big_integer()
+                .not_null()
+                .auto_increment()
+                .primary_key(),
+        )
+        .col(
+            ColumnDef::new(PmForwardingMessages::CreatedAt)
+                .timestamp_with_time_zone()
+                .not_null()
+                .default(Expr::current_timestamp()),
+        )
+        .col(
+            ColumnDef::new(PmForwardingMessages::UpdatedAt)
+                .timestamp_with_time_zone()
+                .not_null()
+                .default(Expr::current_timestamp()),
+        )
+        .col(
+            ColumnDef::new(PmForwardingMessages::BotId)
+                .big_integer()
+                .not_null(),
+        )
+        .col(
+            ColumnDef::new(PmForwardingMessages::TelegramChatId)
+                .big_integer()
+                .not_null(),
+        )
+        .col(
+            ColumnDef::new(PmForwardingMessages::TelegramMessageId)
+                .integer()
+                .not_null(),
+        )
+        .col(
+            ColumnDef::new(PmForwardingMessages::ForwardTelegramMessageId)
+                .integer()
+                .not_null(),
+        )
+        .to_owned()
+}
+
+/// Same schema the entities produced when this migration was written
 #[async_trait::async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
-        let schema = Schema::new(manager.get_database_backend());
-
         manager
-            .create_table(schema.create_table_from_entity(entities::pm_forwarding_bot::Entity))
+            .create_table(
+                Table::create()
+                    .table(PmForwardingBots::Table)
+                    .col(
+                        ColumnDef::new(PmForwardingBots::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBots::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBots::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBots::BotToken)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBots::BotWebhookSecret)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBots::TargetChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    // signed like the `i64` of the entity, telegram user ids fit in 52 bits
+                    .col(
+                        ColumnDef::new(PmForwardingBots::TelegramUserRefer)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
             .await?;
 
         manager
-            .create_table(schema.create_table_from_entity(entities::pm_forwarding_message::Entity))
+            .create_table(
+                create_messages_table(PmForwardingMessages::Table)
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name(FK_MESSAGES_BOT_ID)
+                            .from(PmForwardingMessages::Table, PmForwardingMessages::BotId)
+                            .to(PmForwardingBots::Table, PmForwardingBots::Id),
+                    )
+                    .to_owned(),
+            )
             .await?;
 
         Ok(())
     }
 
     async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the child table references the parent, drop it first
         manager
-            .drop_table(
-                Table::drop()
-                    .table(entities::pm_forwarding_bot::Entity)
-                    .to_owned(),
-            )
+            .drop_table(Table::drop().table(PmForwardingMessages::Table).to_owned())
             .await?;
 
         manager
-            .drop_table(
-                Table::drop()
-                    .table(entities::pm_forwarding_message::Entity)
-                    .to_owned(),
-            )
+            .drop_table(Table::drop().table(PmForwardingBots::Table).to_owned())
             .await?;
 
         Ok(())
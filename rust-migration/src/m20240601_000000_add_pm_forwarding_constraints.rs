@@ -0,0 +1,137 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::DbBackend;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::{
+    create_messages_table, PmForwardingBots, PmForwardingMessages, FK_MESSAGES_BOT_ID,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+pub(crate) static IDX_MESSAGES_BOT_ID_FORWARD_MESSAGE_ID: &str =
+    "idx-pm_forwarding_messages-bot_id-forward_telegram_message_id";
+static IDX_BOTS_TELEGRAM_USER_REFER: &str = "idx-pm_forwarding_bots-telegram_user_refer";
+static SQLITE_REBUILD_TABLE: &str = "pm_forwarding_messages_rebuild";
+
+fn messages_bot_id_fk(on_delete: ForeignKeyAction) -> ForeignKeyCreateStatement {
+    ForeignKey::create()
+        .name(FK_MESSAGES_BOT_ID)
+        .from(PmForwardingMessages::Table, PmForwardingMessages::BotId)
+        .to(PmForwardingBots::Table, PmForwardingBots::Id)
+        .on_delete(on_delete)
+        .to_owned()
+}
+
+/// Sqlite can not alter constraints of an existing table, copy the rows to a new table instead
+async fn rebuild_sqlite_messages_table(
+    manager: &SchemaManager<'_>,
+    on_delete: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    let rebuild = Alias::new(SQLITE_REBUILD_TABLE);
+
+    manager
+        .create_table(
+            create_messages_table(rebuild.clone())
+                .foreign_key(&mut messages_bot_id_fk(on_delete))
+                .to_owned(),
+        )
+        .await?;
+
+    // both tables have the same columns in the same order
+    manager
+        .get_connection()
+        .execute_unprepared(&format!(
+            "INSERT INTO \"{}\" SELECT * FROM \"{}\"",
+            SQLITE_REBUILD_TABLE,
+            PmForwardingMessages::Table.to_string()
+        ))
+        .await?;
+
+    manager
+        .drop_table(Table::drop().table(PmForwardingMessages::Table).to_owned())
+        .await?;
+
+    manager
+        .rename_table(
+            Table::rename()
+                .table(rebuild, PmForwardingMessages::Table)
+                .to_owned(),
+        )
+        .await
+}
+
+async fn replace_messages_bot_id_fk(
+    manager: &SchemaManager<'_>,
+    on_delete: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    if manager.get_database_backend() == DbBackend::Sqlite {
+        return rebuild_sqlite_messages_table(manager, on_delete).await;
+    }
+
+    manager
+        .drop_foreign_key(
+            ForeignKey::drop()
+                .name(FK_MESSAGES_BOT_ID)
+                .table(PmForwardingMessages::Table)
+                .to_owned(),
+        )
+        .await?;
+
+    manager
+        .create_foreign_key(messages_bot_id_fk(on_delete))
+        .await
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        replace_messages_bot_id_fk(manager, ForeignKeyAction::Cascade).await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_MESSAGES_BOT_ID_FORWARD_MESSAGE_ID)
+                    .table(PmForwardingMessages::Table)
+                    .col(PmForwardingMessages::BotId)
+                    .col(PmForwardingMessages::ForwardTelegramMessageId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_BOTS_TELEGRAM_USER_REFER)
+                    .table(PmForwardingBots::Table)
+                    .col(PmForwardingBots::TelegramUserRefer)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_BOTS_TELEGRAM_USER_REFER)
+                    .table(PmForwardingBots::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_MESSAGES_BOT_ID_FORWARD_MESSAGE_ID)
+                    .table(PmForwardingMessages::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        replace_messages_bot_id_fk(manager, ForeignKeyAction::NoAction).await?;
+
+        Ok(())
+    }
+}
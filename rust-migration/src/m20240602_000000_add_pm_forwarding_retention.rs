@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::{PmForwardingBots, PmForwardingMessages};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsRetention {
+    MessageRetentionDays,
+}
+
+static IDX_MESSAGES_BOT_ID_CREATED_AT: &str = "idx-pm_forwarding_messages-bot_id-created_at";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .add_column(
+                        ColumnDef::new(PmForwardingBotsRetention::MessageRetentionDays)
+                            .integer()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_MESSAGES_BOT_ID_CREATED_AT)
+                    .table(PmForwardingMessages::Table)
+                    .col(PmForwardingMessages::BotId)
+                    .col(PmForwardingMessages::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_MESSAGES_BOT_ID_CREATED_AT)
+                    .table(PmForwardingMessages::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .drop_column(PmForwardingBotsRetention::MessageRetentionDays)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
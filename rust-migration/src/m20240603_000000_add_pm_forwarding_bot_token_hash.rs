@@ -0,0 +1,118 @@
+use pegasus_common::cryptor::secret_hash;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend};
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsTokenHash {
+    BotTokenHash,
+}
+
+static IDX_BOTS_BOT_TOKEN_HASH: &str = "idx-pm_forwarding_bots-bot_token_hash";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .add_column(
+                        ColumnDef::new(PmForwardingBotsTokenHash::BotTokenHash)
+                            .string_len(64)
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // tokens are still plaintext at this point, sqlite has no sha256 so they are hashed here
+        match manager.get_database_backend() {
+            DbBackend::Postgres => {
+                manager
+                    .get_connection()
+                    .execute_unprepared(
+                        r#"UPDATE "pm_forwarding_bots" SET "bot_token_hash" = encode(sha256(convert_to("bot_token", 'UTF8')), 'hex')"#,
+                    )
+                    .await?;
+            }
+            DbBackend::MySql => {
+                manager
+                    .get_connection()
+                    .execute_unprepared(
+                        "UPDATE `pm_forwarding_bots` SET `bot_token_hash` = SHA2(`bot_token`, 256)",
+                    )
+                    .await?;
+            }
+            DbBackend::Sqlite => {
+                let db = manager.get_connection();
+                let backend = manager.get_database_backend();
+                let bots = db
+                    .query_all(
+                        backend.build(
+                            Query::select()
+                                .columns([PmForwardingBots::Id, PmForwardingBots::BotToken])
+                                .from(PmForwardingBots::Table),
+                        ),
+                    )
+                    .await?;
+
+                for bot in bots {
+                    let id: i64 = bot.try_get_by_index(0)?;
+                    let bot_token: String = bot.try_get_by_index(1)?;
+                    db.execute(
+                        backend.build(
+                            Query::update()
+                                .table(PmForwardingBots::Table)
+                                .value(
+                                    PmForwardingBotsTokenHash::BotTokenHash,
+                                    secret_hash(&bot_token),
+                                )
+                                .and_where(Expr::col(PmForwardingBots::Id).eq(id)),
+                        ),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_BOTS_BOT_TOKEN_HASH)
+                    .table(PmForwardingBots::Table)
+                    .col(PmForwardingBotsTokenHash::BotTokenHash)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(IDX_BOTS_BOT_TOKEN_HASH)
+                    .table(PmForwardingBots::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .drop_column(PmForwardingBotsTokenHash::BotTokenHash)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
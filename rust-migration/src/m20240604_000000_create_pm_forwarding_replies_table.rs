@@ -0,0 +1,104 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+pub(crate) enum PmForwardingReplies {
+    Table,
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    BotId,
+    TargetMessageId,
+    TelegramChatId,
+    TelegramMessageId,
+}
+
+static FK_REPLIES_BOT_ID: &str = "fk-pm_forwarding_replies-bot_id";
+pub(crate) static IDX_REPLIES_BOT_ID_TARGET_MESSAGE_ID: &str =
+    "idx-pm_forwarding_replies-bot_id-target_message_id";
+
+/// Replies of the target chat copied back to the sender, the reverse of the messages table
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PmForwardingReplies::Table)
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::BotId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::TargetMessageId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::TelegramChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingReplies::TelegramMessageId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name(FK_REPLIES_BOT_ID)
+                            .from(PmForwardingReplies::Table, PmForwardingReplies::BotId)
+                            .to(PmForwardingBots::Table, PmForwardingBots::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_REPLIES_BOT_ID_TARGET_MESSAGE_ID)
+                    .table(PmForwardingReplies::Table)
+                    .col(PmForwardingReplies::BotId)
+                    .col(PmForwardingReplies::TargetMessageId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PmForwardingReplies::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingMessages;
+use crate::m20240601_000000_add_pm_forwarding_constraints::IDX_MESSAGES_BOT_ID_FORWARD_MESSAGE_ID;
+use crate::m20240604_000000_create_pm_forwarding_replies_table::{
+    PmForwardingReplies, IDX_REPLIES_BOT_ID_TARGET_MESSAGE_ID,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingMappingsTargetChat {
+    TargetChatId,
+}
+
+static IDX_MESSAGES_FORWARD_MESSAGE: &str =
+    "idx-pm_forwarding_messages-bot_id-target_chat_id-forward_telegram_message_id";
+static IDX_REPLIES_TARGET_MESSAGE: &str =
+    "idx-pm_forwarding_replies-bot_id-target_chat_id-target_message_id";
+
+/// Add the target chat column, filled with the current target chat of the bot
+async fn add_target_chat_id(manager: &SchemaManager<'_>, table: DynIden) -> Result<(), DbErr> {
+    manager
+        .alter_table(
+            Table::alter()
+                .table(table.clone())
+                .add_column(
+                    ColumnDef::new(PmForwardingMappingsTargetChat::TargetChatId)
+                        .big_integer()
+                        .not_null()
+                        .default(0),
+                )
+                .to_owned(),
+        )
+        .await?;
+
+    // unquoted, the identifiers are not reserved on any backend
+    manager
+        .get_connection()
+        .execute_unprepared(&format!(
+            "UPDATE {table} SET target_chat_id = (SELECT pm_forwarding_bots.target_chat_id FROM pm_forwarding_bots WHERE pm_forwarding_bots.id = {table}.bot_id)",
+            table = table.to_string()
+        ))
+        .await?;
+
+    Ok(())
+}
+
+/// Replace an index, the new one is created first as mysql backs the foreign key with it
+async fn replace_index(
+    manager: &SchemaManager<'_>,
+    create: IndexCreateStatement,
+    drop: IndexDropStatement,
+) -> Result<(), DbErr> {
+    manager.create_index(create).await?;
+    manager.drop_index(drop).await
+}
+
+/// Message ids are per chat, mappings are looked up by bot and target chat
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_target_chat_id(manager, PmForwardingMessages::Table.into_iden()).await?;
+        add_target_chat_id(manager, PmForwardingReplies::Table.into_iden()).await?;
+
+        // mappings made before the target chat of the bot changed can collide, keep the latest
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "DELETE FROM pm_forwarding_messages WHERE id NOT IN (SELECT id FROM (SELECT MAX(id) AS id FROM pm_forwarding_messages GROUP BY bot_id, target_chat_id, forward_telegram_message_id) AS latest)",
+            )
+            .await?;
+
+        replace_index(
+            manager,
+            Index::create()
+                .name(IDX_MESSAGES_FORWARD_MESSAGE)
+                .table(PmForwardingMessages::Table)
+                .col(PmForwardingMessages::BotId)
+                .col(PmForwardingMappingsTargetChat::TargetChatId)
+                .col(PmForwardingMessages::ForwardTelegramMessageId)
+                .unique()
+                .to_owned(),
+            Index::drop()
+                .name(IDX_MESSAGES_BOT_ID_FORWARD_MESSAGE_ID)
+                .table(PmForwardingMessages::Table)
+                .to_owned(),
+        )
+        .await?;
+
+        replace_index(
+            manager,
+            Index::create()
+                .name(IDX_REPLIES_TARGET_MESSAGE)
+                .table(PmForwardingReplies::Table)
+                .col(PmForwardingReplies::BotId)
+                .col(PmForwardingMappingsTargetChat::TargetChatId)
+                .col(PmForwardingReplies::TargetMessageId)
+                .unique()
+                .to_owned(),
+            Index::drop()
+                .name(IDX_REPLIES_BOT_ID_TARGET_MESSAGE_ID)
+                .table(PmForwardingReplies::Table)
+                .to_owned(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        replace_index(
+            manager,
+            Index::create()
+                .name(IDX_REPLIES_BOT_ID_TARGET_MESSAGE_ID)
+                .table(PmForwardingReplies::Table)
+                .col(PmForwardingReplies::BotId)
+                .col(PmForwardingReplies::TargetMessageId)
+                .unique()
+                .to_owned(),
+            Index::drop()
+                .name(IDX_REPLIES_TARGET_MESSAGE)
+                .table(PmForwardingReplies::Table)
+                .to_owned(),
+        )
+        .await?;
+
+        replace_index(
+            manager,
+            Index::create()
+                .name(IDX_MESSAGES_BOT_ID_FORWARD_MESSAGE_ID)
+                .table(PmForwardingMessages::Table)
+                .col(PmForwardingMessages::BotId)
+                .col(PmForwardingMessages::ForwardTelegramMessageId)
+                .to_owned(),
+            Index::drop()
+                .name(IDX_MESSAGES_FORWARD_MESSAGE)
+                .table(PmForwardingMessages::Table)
+                .to_owned(),
+        )
+        .await?;
+
+        for table in [
+            PmForwardingMessages::Table.into_iden(),
+            PmForwardingReplies::Table.into_iden(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(table)
+                        .drop_column(PmForwardingMappingsTargetChat::TargetChatId)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsTopics {
+    TopicsEnabled,
+}
+
+#[derive(DeriveIden)]
+enum PmForwardingTopics {
+    Table,
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    BotId,
+    TargetChatId,
+    MessageThreadId,
+    TelegramChatId,
+}
+
+static FK_TOPICS_BOT_ID: &str = "fk-pm_forwarding_topics-bot_id";
+static IDX_TOPICS_TELEGRAM_CHAT_ID: &str =
+    "idx-pm_forwarding_topics-bot_id-target_chat_id-telegram_chat_id";
+static IDX_TOPICS_MESSAGE_THREAD_ID: &str =
+    "idx-pm_forwarding_topics-bot_id-target_chat_id-message_thread_id";
+
+/// Forum topic of each sender in the target supergroup, for bots in topics mode
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .add_column(
+                        ColumnDef::new(PmForwardingBotsTopics::TopicsEnabled)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(PmForwardingTopics::Table)
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::BotId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::TargetChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::MessageThreadId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingTopics::TelegramChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name(FK_TOPICS_BOT_ID)
+                            .from(PmForwardingTopics::Table, PmForwardingTopics::BotId)
+                            .to(PmForwardingBots::Table, PmForwardingBots::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // one topic per sender, and one sender per topic
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_TOPICS_TELEGRAM_CHAT_ID)
+                    .table(PmForwardingTopics::Table)
+                    .col(PmForwardingTopics::BotId)
+                    .col(PmForwardingTopics::TargetChatId)
+                    .col(PmForwardingTopics::TelegramChatId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_TOPICS_MESSAGE_THREAD_ID)
+                    .table(PmForwardingTopics::Table)
+                    .col(PmForwardingTopics::BotId)
+                    .col(PmForwardingTopics::TargetChatId)
+                    .col(PmForwardingTopics::MessageThreadId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PmForwardingTopics::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .drop_column(PmForwardingBotsTopics::TopicsEnabled)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
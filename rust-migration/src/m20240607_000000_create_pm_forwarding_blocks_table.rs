@@ -0,0 +1,125 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsBlocks {
+    BlockNotice,
+}
+
+#[derive(DeriveIden)]
+enum PmForwardingBlocks {
+    Table,
+    Id,
+    CreatedAt,
+    UpdatedAt,
+    BotId,
+    TelegramChatId,
+    BlockedUntil,
+}
+
+static FK_BLOCKS_BOT_ID: &str = "fk-pm_forwarding_blocks-bot_id";
+static IDX_BLOCKS_BOT_ID_TELEGRAM_CHAT_ID: &str =
+    "idx-pm_forwarding_blocks-bot_id-telegram_chat_id";
+
+/// Senders banned or muted by the target chat, with the notice they get instead of a forward
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .add_column(
+                        ColumnDef::new(PmForwardingBotsBlocks::BlockNotice)
+                            .text()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(PmForwardingBlocks::Table)
+                    .col(
+                        ColumnDef::new(PmForwardingBlocks::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBlocks::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBlocks::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBlocks::BotId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBlocks::TelegramChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PmForwardingBlocks::BlockedUntil)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name(FK_BLOCKS_BOT_ID)
+                            .from(PmForwardingBlocks::Table, PmForwardingBlocks::BotId)
+                            .to(PmForwardingBots::Table, PmForwardingBots::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(IDX_BLOCKS_BOT_ID_TELEGRAM_CHAT_ID)
+                    .table(PmForwardingBlocks::Table)
+                    .col(PmForwardingBlocks::BotId)
+                    .col(PmForwardingBlocks::TelegramChatId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PmForwardingBlocks::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .drop_column(PmForwardingBotsBlocks::BlockNotice)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsRateLimit {
+    RateLimit,
+}
+
+/// Messages a sender may send per minute to a bot, existing bots get the default limit
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .add_column(
+                        ColumnDef::new(PmForwardingBotsRateLimit::RateLimit)
+                            .integer()
+                            .not_null()
+                            .default(20),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PmForwardingBots::Table)
+                    .drop_column(PmForwardingBotsRateLimit::RateLimit)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
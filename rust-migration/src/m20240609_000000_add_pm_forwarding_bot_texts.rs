@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsTexts {
+    #[sea_orm(iden = "welcome_text")]
+    Welcome,
+    #[sea_orm(iden = "ack_text")]
+    Ack,
+    #[sea_orm(iden = "away_text")]
+    Away,
+    #[sea_orm(iden = "error_text")]
+    Error,
+}
+
+/// Texts the owner of a bot sets for its senders, null for the default ones
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // one column per statement, sqlite alters a single column at a time
+        for column in [
+            PmForwardingBotsTexts::Welcome,
+            PmForwardingBotsTexts::Ack,
+            PmForwardingBotsTexts::Away,
+            PmForwardingBotsTexts::Error,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(PmForwardingBots::Table)
+                        .add_column(ColumnDef::new(column).text().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            PmForwardingBotsTexts::Welcome,
+            PmForwardingBotsTexts::Ack,
+            PmForwardingBotsTexts::Away,
+            PmForwardingBotsTexts::Error,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(PmForwardingBots::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
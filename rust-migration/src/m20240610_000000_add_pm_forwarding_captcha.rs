@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240407_142137_create_pm_forwarding_tables::PmForwardingBots;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum PmForwardingBotsCaptcha {
+    Captcha,
+    CaptchaTimeout,
+    CaptchaRetries,
+}
+
+/// Challenge first-time senders must solve before their messages are forwarded
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // one column per statement, sqlite alters a single column at a time
+        for mut column in [
+            ColumnDef::new(PmForwardingBotsCaptcha::Captcha)
+                .text()
+                .null()
+                .to_owned(),
+            ColumnDef::new(PmForwardingBotsCaptcha::CaptchaTimeout)
+                .integer()
+                .not_null()
+                .default(300)
+                .to_owned(),
+            ColumnDef::new(PmForwardingBotsCaptcha::CaptchaRetries)
+                .integer()
+                .not_null()
+                .default(1)
+                .to_owned(),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(PmForwardingBots::Table)
+                        .add_column(&mut column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            PmForwardingBotsCaptcha::Captcha,
+            PmForwardingBotsCaptcha::CaptchaTimeout,
+            PmForwardingBotsCaptcha::CaptchaRetries,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(PmForwardingBots::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -5,7 +5,8 @@ use std::env;
 async fn main() {
     let ref settings = pegasus_common::settings::Settings::read_from_default_file().unwrap();
     let database_url =
-        pegasus_common::database::utils::database_url(settings.database.as_ref().unwrap());
+        pegasus_common::database::utils::database_url(settings.database.as_ref().unwrap())
+            .expect("Invalid database settings");
     env::set_var("DATABASE_URL", database_url);
 
     cli::run_cli(pegasus_migration::Migrator).await;
@@ -0,0 +1,29 @@
+use pegasus_common::cryptor::Cryptor;
+use pegasus_common::database;
+use pegasus_common::database::repositories::pm_forwarding_bot::SeaOrmPmForwardingBotRepository;
+use pegasus_migration::Migrator;
+
+/// Encrypt stored secrets with the primary key, run it after adding a new primary key and
+/// before removing the old one from the settings
+#[async_std::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let settings = pegasus_common::settings::Settings::read_from_default_file()?;
+    let cryptor = Cryptor::new(settings.encryption.as_ref())?;
+    let db = database::init_conn::<Migrator>(
+        settings
+            .database
+            .as_ref()
+            .ok_or("Database is not configured")?,
+    )
+    .await?;
+
+    let updated = SeaOrmPmForwardingBotRepository::new(db, cryptor)
+        .rotate_secrets()
+        .await?;
+    println!(
+        "Encrypted the secrets of {} bots with the primary key",
+        updated
+    );
+
+    Ok(())
+}